@@ -0,0 +1,20 @@
+use std::env;
+use network_analyzer::analyzer::Analyzer;
+
+fn main() {
+    //The path of the capture file is passed as first argument, e.g. `cargo run --example analyzing_capture_file capture.pcap`
+    let path = env::args().nth(1).expect("Usage: analyzing_capture_file <path/to/capture.pcap>");
+
+    //Into the file capture_report.md
+    let filename = String::from("capture_report");
+
+    //The analyzer reads all the packets of the file instead of listening to a device,
+    //the report is still rewritten every 5 seconds while the file is being analyzed
+    let timer:u64=5;
+    let a=Analyzer::from_file(&path, &filename, timer);
+
+    //When the end of the file is reached the final report is written
+    a.wait_for_completion().expect("Error writing the final report");
+
+    println!("Analysis of {} completed, report saved in 'report/{}.md'", path, filename);
+}
@@ -23,7 +23,7 @@ fn main() {
     //i print the aggregated data
     {
         println!("First print:");
-        let agg_data = agg_data.lock().unwrap();
+        let agg_data = agg_data.read().unwrap();
         for (conn,data) in agg_data.iter() {
             println!("{}{}",conn,data);
         }
@@ -38,7 +38,7 @@ fn main() {
 
     {
         println!("Second print:");
-        let agg_data = agg_data.lock().unwrap();
+        let agg_data = agg_data.read().unwrap();
         for (conn,data) in agg_data.iter() {
             println!("{}{}",conn,data);
         }
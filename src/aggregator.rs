@@ -1,9 +1,200 @@
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
-use std::sync::mpsc::{channel, Sender};
-use crate::parsed_packet::ParsedPacket;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, TimeZone, Utc};
+use crate::parsed_packet::{ParsedPacket, TCP_ACK, TCP_SYN};
+use crate::parser::ProtocolStats;
 use crate::report_entry::{Connection, ConnectionMetadata};
 
+/// Enum describing which fields of a [ParsedPacket] make up the [Connection] key of the aggregated data map,
+/// the fields not part of the key are left empty
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AggregationKey {
+    /// Source and destination addresses and ports and protocol (the default)
+    #[default]
+    Connection,
+    /// Source address only, one entry per host sending traffic
+    SourceIp,
+    /// Destination address only, one entry per host receiving traffic
+    DestinationIp,
+    /// Destination address, port and protocol, one entry per service
+    DestinationIpPort,
+    /// Protocol only
+    Protocol,
+    /// Source and destination networks, with the given prefix lengths for IPv4 and IPv6 addresses
+    Subnets { ipv4_prefix: u8, ipv6_prefix: u8 },
+}
+
+impl AggregationKey {
+    /// Returns the [Connection] key of the given [ParsedPacket]
+    pub fn key(&self, p: &ParsedPacket) -> Connection {
+        let mut key = Connection::new(p.source_ip, p.destination_ip, p.source_port, p.destination_port, p.protocol, p.interface.clone());
+        match *self {
+            AggregationKey::Connection => {},
+            AggregationKey::SourceIp => {
+                key.destination_ip = None;
+                key.source_port = None;
+                key.destination_port = None;
+                key.protocol = None;
+            },
+            AggregationKey::DestinationIp => {
+                key.source_ip = None;
+                key.source_port = None;
+                key.destination_port = None;
+                key.protocol = None;
+            },
+            AggregationKey::DestinationIpPort => {
+                key.source_ip = None;
+                key.source_port = None;
+            },
+            AggregationKey::Protocol => {
+                key.source_ip = None;
+                key.destination_ip = None;
+                key.source_port = None;
+                key.destination_port = None;
+            },
+            AggregationKey::Subnets { ipv4_prefix, ipv6_prefix } => {
+                let (source, source_prefix) = network(p.source_ip, ipv4_prefix, ipv6_prefix);
+                let (destination, destination_prefix) = network(p.destination_ip, ipv4_prefix, ipv6_prefix);
+                key.source_ip = Some(source);
+                key.source_prefix = Some(source_prefix);
+                key.destination_ip = Some(destination);
+                key.destination_prefix = Some(destination_prefix);
+                key.source_port = None;
+                key.destination_port = None;
+                key.protocol = None;
+            },
+        }
+        key
+    }
+
+    /// Returns `true` if the key has both a source and a destination, so that the two directions of a connection can be told apart
+    pub fn is_directional(&self) -> bool {
+        matches!(self, AggregationKey::Connection | AggregationKey::Subnets { .. })
+    }
+}
+
+/// Returns the network containing the given address and its prefix length (capped to the address length)
+fn network(ip: IpAddr, ipv4_prefix: u8, ipv6_prefix: u8) -> (IpAddr, u8) {
+    match ip {
+        IpAddr::V4(ip) => {
+            let prefix = ipv4_prefix.min(32);
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            (IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask)), prefix)
+        },
+        IpAddr::V6(ip) => {
+            let prefix = ipv6_prefix.min(128);
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            (IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask)), prefix)
+        },
+    }
+}
+
+/// Struct describing how an [Aggregator] groups the received [ParsedPacket]
+///
+/// # Examples
+/// Basic usage: aggregate both directions of a connection into a single entry
+/// ```rust
+/// use Network_analyzer::aggregator::{Aggregator, AggregatorConfig};
+///
+/// let aggregator=Aggregator::with_config(AggregatorConfig { bidirectional: true, ..Default::default() });
+/// ```
+///
+/// Subnet matrix of the /24 IPv4 and /64 IPv6 networks:
+/// ```rust
+/// use Network_analyzer::aggregator::{AggregationKey, Aggregator, AggregatorConfig};
+///
+/// let key = AggregationKey::Subnets { ipv4_prefix: 24, ipv6_prefix: 64 };
+/// let aggregator=Aggregator::with_config(AggregatorConfig { key, ..Default::default() });
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct AggregatorConfig {
+    /// The fields of the packets used as key of the aggregated data map
+    pub key: AggregationKey,
+    /// If `true` the packets of both directions of a connection are aggregated into the same entry, keyed by the
+    /// [Connection] going from the initiator to the responder. The initiator is the sender of the first packet,
+    /// unless a TCP SYN (or SYN-ACK) shows otherwise.
+    /// It is only meaningful for [AggregationKey::Connection] and [AggregationKey::Subnets] keys, it is ignored otherwise
+    pub bidirectional: bool,
+    /// If set, an entry that has not received packets for this long is considered finished and removed from the map
+    pub idle_timeout: Option<Duration>,
+    /// If set, an entry whose first packet is older than this is considered finished and removed from the map,
+    /// the following packets of the connection start a new entry
+    pub active_timeout: Option<Duration>,
+    /// If set, the maximum number of entries of the map, at least 1: when a new entry does not fit, entries are evicted
+    /// according to `eviction`
+    pub max_entries: Option<usize>,
+    /// The entries evicted first when the map is full
+    pub eviction: EvictionPolicy,
+    /// If set, the packets are also aggregated per time window, in addition to the lifetime totals of the map
+    pub window: Option<WindowConfig>,
+}
+
+/// Struct describing the time windows of a windowed [Aggregator]
+///
+/// A flow record of a [FlowCollector](crate::collector::FlowCollector) spanning several windows is spread over them:
+/// each window gets the share of its bytes and packets proportional to the part of the flow duration falling into it
+/// (the flow is assumed to be evenly paced), only the windows within the retention are accounted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowConfig {
+    /// The duration of each window (e.g. 1 second, 1 minute, 5 minutes), windows start at multiples of it since the epoch
+    pub width: Duration,
+    /// The number of most recent windows kept, older windows are dropped
+    pub retention: usize,
+}
+
+/// Aggregated data of a windowed [Aggregator]: for each window, identified by its start time, the data
+/// aggregated from the packets whose timestamp falls into it
+pub type WindowedData = BTreeMap<DateTime<Utc>, HashMap<Connection, ConnectionMetadata>>;
+
+/// Enum describing which entries are evicted first when the aggregated data map is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvictionPolicy {
+    /// The entries whose last packet is the oldest
+    #[default]
+    LeastRecentlySeen,
+    /// The entries with the smallest number of bytes
+    SmallestBytes,
+}
+
+impl AggregatorConfig {
+    /// Returns the reason why the given entry is finished at the time `now`, `None` if it is still alive
+    pub fn expiry(&self, value: &ConnectionMetadata, now: DateTime<Utc>) -> Option<ExpiryReason> {
+        let elapsed = |since: DateTime<Utc>| (now - since).to_std().unwrap_or(Duration::ZERO);
+        if self.idle_timeout.is_some_and(|timeout| elapsed(value.last_timestamp) >= timeout) {
+            Some(ExpiryReason::Idle)
+        } else if self.active_timeout.is_some_and(|timeout| elapsed(value.first_timestamp) >= timeout) {
+            Some(ExpiryReason::Active)
+        } else {
+            None
+        }
+    }
+}
+
+/// Enum describing why an entry has been removed from the aggregated data map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpiryReason {
+    /// No packets were received for the idle timeout
+    Idle,
+    /// The entry lasted longer than the active timeout
+    Active,
+    /// The entry was evicted to make room for a new one in a full map
+    Evicted,
+}
+
+/// Struct representing an entry removed from the aggregated data map because its timeout expired or it was evicted
+#[derive(Debug, Clone)]
+pub struct CompletedFlow {
+    pub connection: Connection,
+    pub metadata: ConnectionMetadata,
+    pub reason: ExpiryReason,
+}
+
+/// Interval between two checks of the timeouts of the aggregated data map
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 
 /// Struct that aggregates data from received [ParsedPacket] into an HashMap which has as a key: [Connection] and as a value: [ConnectionMetadata]
 ///
@@ -50,9 +241,25 @@ use crate::report_entry::{Connection, ConnectionMetadata};
 /// Each [Aggregator] runs in a separate thread, so you can create multiple [Parser] sending [ParsedPacket] to multiple [Aggregator]
 #[derive(Clone)]
 pub struct Aggregator{
+    config: AggregatorConfig,
     tx: Sender<ParsedPacket>,
-    aggregated_data: Arc<RwLock<HashMap<Connection,ConnectionMetadata>>>
+    aggregated_data: Arc<RwLock<HashMap<Connection,ConnectionMetadata>>>,
+    windowed_data: Arc<RwLock<WindowedData>>,
+    received: Arc<Mutex<ReceivedCounts>>,
+    cv_received: Arc<Condvar>,
+    evicted: Arc<Mutex<usize>>,
+    protocols: Arc<Mutex<BTreeMap<u8, ProtocolStats>>>,
+    completed_subscribers: Arc<Mutex<Vec<Sender<CompletedFlow>>>>
+}
+/// Number of [ParsedPacket] inserted into the aggregated data, per source
+#[derive(Debug, Default)]
+struct ReceivedCounts {
+    /// Captured packets, e.g. sent by a [Parser]
+    packets: usize,
+    /// Flow records sent by a [FlowCollector](crate::collector::FlowCollector)
+    flow_records: usize,
 }
+
 impl Aggregator{
     ///Creates the [Aggregator] and a thread that receives [ParsedPacket] via channel and inserts them into the [Aggregator] map
     ///
@@ -67,42 +274,295 @@ impl Aggregator{
     ///# Errors
     /// if the result taken from the channel does not return an Ok<[ParsedPacket]>.
     pub fn new() -> Self {
+        Aggregator::with_config(AggregatorConfig::default())
+    }
+
+    /// Creates a new [Aggregator] grouping the received [ParsedPacket] as described by the given [AggregatorConfig]
+    ///
+    /// # Examples
+    /// Basic usage:
+    /// ```rust
+    /// use Network_analyzer::aggregator::{Aggregator, AggregatorConfig};
+    ///
+    /// let aggregator=Aggregator::with_config(AggregatorConfig { bidirectional: true, ..Default::default() });
+    /// ```
+    ///# Errors
+    /// if the result taken from the channel does not return an Ok<[ParsedPacket]>.
+    ///
+    /// # Panics
+    /// If the width of the windows is shorter than a microsecond or `max_entries` is zero
+    pub fn with_config(config: AggregatorConfig) -> Self {
+        if let Some(window) = config.window {
+            //windows are aligned with microsecond precision
+            assert!(window.width.as_micros() > 0, "the width of the aggregation windows must be at least a microsecond");
+        }
+        assert_ne!(config.max_entries, Some(0), "the maximum number of entries must be greater than zero");
         let(tx,rx) = channel::<ParsedPacket>();
-        //declare an hashmap with key as tuple of (destination_ip,port) and value as tuple of (protocol, size, first_timestamp, last_timestamp)
+        //declare an hashmap with key the Connection and value its ConnectionMetadata (bytes, packets, sizes and timestamps)
         let aggregated_data = Arc::new(RwLock::new(HashMap::<Connection,ConnectionMetadata>::new()));
         let aggregated_data_clone = Arc::clone(&aggregated_data);
+        let windowed_data = Arc::new(RwLock::new(WindowedData::new()));
+        let windowed_data_clone = Arc::clone(&windowed_data);
+        let received = Arc::new(Mutex::new(ReceivedCounts::default()));
+        let received_clone = Arc::clone(&received);
+        let evicted = Arc::new(Mutex::new(0));
+        let evicted_clone = Arc::clone(&evicted);
+        let protocols = Arc::new(Mutex::new(BTreeMap::<u8, ProtocolStats>::new()));
+        let protocols_clone = Arc::clone(&protocols);
+        let cv_received = Arc::new(Condvar::new());
+        let cv_received_clone = Arc::clone(&cv_received);
+        let config_clone = config.clone();
+        let completed_subscribers = Arc::new(Mutex::new(Vec::<Sender<CompletedFlow>>::new()));
+        let completed_subscribers_clone = Arc::clone(&completed_subscribers);
 
         std::thread::spawn( move || {
+            let expiry = config_clone.idle_timeout.is_some() || config_clone.active_timeout.is_some();
+            //the clock used for the timeouts follows the packet timestamps, and wall clock time while no packets arrive
+            let mut last_packet: Option<(DateTime<Utc>, Instant)> = None;
+            let mut last_check = Instant::now();
             let mut loop1 = true;
             while loop1 {
-                let msg = rx.recv();
+                let msg = if expiry { rx.recv_timeout(EXPIRY_CHECK_INTERVAL) } else { rx.recv().map_err(|_| RecvTimeoutError::Disconnected) };
+                let mut completed = Vec::new();
                 match msg {
-                    Err(_) => {
+                    Err(RecvTimeoutError::Disconnected) => {
                         //All senders to this channel have been dropped
                         //the thread can die.
                         loop1 = false;
                     },
+                    Err(RecvTimeoutError::Timeout) => {},
                     Ok(p) => {
+                        if last_packet.is_none_or(|(timestamp, _)| p.timestamp >= timestamp) {
+                            last_packet = Some((p.timestamp, Instant::now()));
+                        }
 
-                        let key = Connection::new(p.source_ip, p.destination_ip, p.source_port, p.destination_port, p.protocol);
                         let mut aggregated_map = aggregated_data_clone.write().unwrap();
+                        let (key, initiator) = Aggregator::aggregate(&mut aggregated_map, &p, &config_clone, &mut completed);
+                        drop(aggregated_map);
+                        if let Some(window) = config_clone.window {
+                            let mut windowed_map = windowed_data_clone.write().unwrap();
+                            Aggregator::aggregate_window(&mut windowed_map, key, initiator, &p, &window);
+                        }
+                        let evicted_flows = completed.iter().filter(|flow| flow.reason == ExpiryReason::Evicted).count();
+                        if evicted_flows > 0 {
+                            *evicted_clone.lock().unwrap() += evicted_flows;
+                        }
+
+                        let mut protocols_map = protocols_clone.lock().unwrap();
+                        let protocol = protocols_map.entry(p.protocol.ip_number()).or_default();
+                        protocol.packets += p.flow.map_or(1, |flow| flow.packets);
+                        protocol.bytes += p.size;
+                        drop(protocols_map);
 
-                        if aggregated_map.contains_key(&key) {
-                            //Key already exists, updating value
-                            let mut value = aggregated_map.get_mut(&key).unwrap();
-                            (*value).size = p.size + (*value).size;
-                            (*value).last_timestamp = p.timestamp;
+                        let mut received = received_clone.lock().unwrap();
+                        if p.flow.is_some() {
+                            received.flow_records += 1;
                         } else {
-                            //Key does not exist, inserting new value
-                            let value = ConnectionMetadata::new(p.size,p.timestamp.clone(),p.timestamp);
-                            aggregated_map.insert(key,value);
+                            received.packets += 1;
                         }
+                        cv_received_clone.notify_all();
 
                     }
                 }
+                if expiry && last_check.elapsed() >= EXPIRY_CHECK_INTERVAL {
+                    if let Some((timestamp, instant)) = last_packet {
+                        let now = timestamp + chrono::Duration::from_std(instant.elapsed()).unwrap_or(chrono::Duration::zero());
+                        let mut aggregated_map = aggregated_data_clone.write().unwrap();
+                        Aggregator::expire(&mut aggregated_map, now, &config_clone, &mut completed);
+                    }
+                    last_check = Instant::now();
+                }
+                if !completed.is_empty() {
+                    //hand the completed flows to every subscriber still listening
+                    let mut subscribers = completed_subscribers_clone.lock().unwrap();
+                    subscribers.retain(|subscriber| completed.iter().all(|flow| subscriber.send(flow.clone()).is_ok()));
+                }
             }
         });
-        Aggregator { tx, aggregated_data }
+        Aggregator { config, tx, aggregated_data, windowed_data, received, cv_received, evicted, protocols, completed_subscribers }
+    }
+
+    /// Removes from the aggregated data map every entry finished at the time `now`, appending it to `completed`
+    fn expire(aggregated_map: &mut HashMap<Connection,ConnectionMetadata>, now: DateTime<Utc>, config: &AggregatorConfig, completed: &mut Vec<CompletedFlow>) {
+        aggregated_map.retain(|connection, metadata| match config.expiry(metadata, now) {
+            Some(reason) => {
+                completed.push(CompletedFlow { connection: connection.clone(), metadata: metadata.clone(), reason });
+                false
+            },
+            None => true,
+        });
+    }
+
+    /// Inserts a [ParsedPacket] into the aggregated data map, according to the given [AggregatorConfig].
+    /// If the entry of the packet is finished at the packet timestamp, it is appended to `completed` and a new entry is started
+    /// # Returns
+    /// The key of the entry the packet was accounted to and `true` if the packet was sent by the initiator of the entry
+    fn aggregate(aggregated_map: &mut HashMap<Connection,ConnectionMetadata>, p: &ParsedPacket, config: &AggregatorConfig, completed: &mut Vec<CompletedFlow>) -> (Connection, bool) {
+        let key = config.key.key(p);
+        let bidirectional = config.bidirectional && config.key.is_directional();
+        let mut keys = vec![key.clone()];
+        if bidirectional {
+            keys.push(key.reversed());
+        }
+        for connection in keys {
+            if let Some(reason) = aggregated_map.get(&connection).and_then(|metadata| config.expiry(metadata, p.timestamp)) {
+                let metadata = aggregated_map.remove(&connection).unwrap();
+                completed.push(CompletedFlow { connection, metadata, reason });
+            }
+        }
+        if !bidirectional {
+            if let Some(value) = aggregated_map.get_mut(&key) {
+                //Key already exists, updating value
+                Aggregator::account(value, p, true);
+            } else {
+                //Key does not exist, inserting new value
+                Aggregator::make_room(aggregated_map, config, completed);
+                aggregated_map.insert(key.clone(), Aggregator::new_metadata(p));
+            }
+            return (key, true);
+        }
+        //a SYN is sent by the initiator, a SYN-ACK by the responder
+        let sent_by_initiator = p.tcp_flags
+            .filter(|flags| flags & TCP_SYN != 0)
+            .map(|flags| flags & TCP_ACK == 0);
+        let reversed = key.reversed();
+        if let Some(value) = aggregated_map.get_mut(&key) {
+            //packet sent by the initiator of a known connection
+            Aggregator::account(value, p, true);
+            if sent_by_initiator == Some(false) {
+                //the connection was first seen from the responder side
+                let mut value = aggregated_map.remove(&key).unwrap();
+                value.swap_direction();
+                aggregated_map.insert(reversed.clone(), value);
+                return (reversed, false);
+            }
+            (key, true)
+        } else if let Some(value) = aggregated_map.get_mut(&reversed) {
+            //packet sent by the responder of a known connection
+            Aggregator::account(value, p, false);
+            if sent_by_initiator == Some(true) {
+                let mut value = aggregated_map.remove(&reversed).unwrap();
+                value.swap_direction();
+                aggregated_map.insert(key.clone(), value);
+                return (key, true);
+            }
+            (reversed, false)
+        } else if sent_by_initiator == Some(false) {
+            //new connection seen from a SYN-ACK, its destination is the initiator
+            let mut value = Aggregator::new_metadata(p);
+            value.swap_direction();
+            Aggregator::make_room(aggregated_map, config, completed);
+            aggregated_map.insert(reversed.clone(), value);
+            (reversed, false)
+        } else {
+            Aggregator::make_room(aggregated_map, config, completed);
+            aggregated_map.insert(key.clone(), Aggregator::new_metadata(p));
+            (key, true)
+        }
+    }
+
+    /// Returns the metadata of a new entry made of a [ParsedPacket], or of the flow record it stands for
+    fn new_metadata(p: &ParsedPacket) -> ConnectionMetadata {
+        match p.flow {
+            Some(flow) => ConnectionMetadata::from_flow(p.size, flow.packets, flow.first_timestamp, p.timestamp),
+            None => ConnectionMetadata::new(p.size, p.timestamp, p.timestamp),
+        }
+    }
+
+    /// Accounts a [ParsedPacket], or the flow record it stands for, to an existing entry
+    fn account(value: &mut ConnectionMetadata, p: &ParsedPacket, initiator: bool) {
+        match (p.flow, initiator) {
+            (Some(flow), true) => value.update_flow(p.size, flow.packets, flow.first_timestamp, p.timestamp),
+            (Some(flow), false) => value.update_flow_responder(p.size, flow.packets, flow.first_timestamp, p.timestamp),
+            (None, true) => value.update(p.size, p.timestamp),
+            (None, false) => value.update_responder(p.size, p.timestamp),
+        }
+    }
+
+    /// Accounts a [ParsedPacket] to the entry `key` of the window its timestamp falls into, or a flow record to the windows
+    /// it spans (see [WindowConfig]), dropping the oldest windows beyond the retention of the given [WindowConfig]
+    fn aggregate_window(windowed_map: &mut WindowedData, key: Connection, initiator: bool, p: &ParsedPacket, window: &WindowConfig) {
+        let width = window.width.as_micros() as i64;
+        let window_start = |micros: i64| micros.div_euclid(width) * width;
+        let last = p.timestamp.timestamp_micros();
+        match p.flow {
+            Some(flow) if window_start(flow.first_timestamp.timestamp_micros()) < window_start(last) => {
+                let first = flow.first_timestamp.timestamp_micros();
+                let duration = (last - first) as u128;
+                //cumulative share of a total from the start of the flow to `until`, the shares of the windows add up to the total
+                let share = |total: usize, until: i64| (total as u128 * (until.min(last) - first) as u128 / duration) as usize;
+                //start from the oldest window the retention keeps, relative to the most recent window of the map:
+                //the older windows would be dropped right away, walking them could take millions of steps under the write lock
+                let newest = windowed_map.last_key_value().map_or(last, |(start, _)| start.timestamp_micros().max(last));
+                let kept = i64::try_from(window.retention.saturating_sub(1)).unwrap_or(i64::MAX);
+                let oldest = window_start(newest).saturating_sub(kept.saturating_mul(width));
+                let mut start = window_start(first).max(oldest);
+                while start <= last {
+                    let (from, until) = (start.max(first), start + width);
+                    let (size, packets) = (share(p.size, until) - share(p.size, from), share(flow.packets, until) - share(flow.packets, from));
+                    if size > 0 || packets > 0 {
+                        let first_timestamp = Utc.timestamp_micros(from).unwrap();
+                        let last_timestamp = Utc.timestamp_micros((until - 1).min(last)).unwrap();
+                        let entries = windowed_map.entry(Utc.timestamp_micros(start).unwrap()).or_default();
+                        match entries.get_mut(&key) {
+                            Some(value) if initiator => value.update_flow(size, packets, first_timestamp, last_timestamp),
+                            Some(value) => value.update_flow_responder(size, packets, first_timestamp, last_timestamp),
+                            None => {
+                                let mut value = ConnectionMetadata::from_flow(size, packets, first_timestamp, last_timestamp);
+                                if !initiator {
+                                    value.swap_direction();
+                                }
+                                entries.insert(key.clone(), value);
+                            },
+                        }
+                    }
+                    start += width;
+                }
+            },
+            _ => {
+                let start = window_start(last);
+                let start = Utc.timestamp_opt(start.div_euclid(1_000_000), start.rem_euclid(1_000_000) as u32 * 1000).unwrap();
+                let entries = windowed_map.entry(start).or_default();
+                match entries.get_mut(&key) {
+                    Some(value) => Aggregator::account(value, p, initiator),
+                    None => {
+                        let mut value = Aggregator::new_metadata(p);
+                        if !initiator {
+                            value.swap_direction();
+                        }
+                        entries.insert(key, value);
+                    },
+                }
+            },
+        }
+        while windowed_map.len() > window.retention {
+            windowed_map.pop_first();
+        }
+    }
+
+    /// Evicts entries from a full aggregated data map, according to the eviction policy of the given [AggregatorConfig],
+    /// appending them to `completed`
+    /// # Remarks
+    /// To keep the cost of eviction low, 1% of the maximum number of entries (at least one) is evicted at once
+    fn make_room(aggregated_map: &mut HashMap<Connection,ConnectionMetadata>, config: &AggregatorConfig, completed: &mut Vec<CompletedFlow>) {
+        let max_entries = match config.max_entries {
+            Some(max_entries) if aggregated_map.len() >= max_entries => max_entries,
+            _ => return,
+        };
+        let count = (aggregated_map.len() + 1 - max_entries).max(max_entries / 100).max(1).min(aggregated_map.len());
+        let mut candidates: Vec<(Connection, i64)> = aggregated_map.iter()
+            .map(|(connection, metadata)| (connection.clone(), match config.eviction {
+                EvictionPolicy::LeastRecentlySeen => metadata.last_timestamp.timestamp_micros(),
+                EvictionPolicy::SmallestBytes => metadata.size as i64,
+            }))
+            .collect();
+        if count < candidates.len() {
+            candidates.select_nth_unstable_by_key(count, |(_, priority)| *priority);
+        }
+        for (connection, _) in candidates.into_iter().take(count) {
+            let metadata = aggregated_map.remove(&connection).unwrap();
+            completed.push(CompletedFlow { connection, metadata, reason: ExpiryReason::Evicted });
+        }
     }
 
     ///Allows a [ParsedPacket] to be sent to the aggregator via the [Aggregator] sender
@@ -116,6 +576,77 @@ impl Aggregator{
         Arc::clone(&self.aggregated_data)
     }
 
+    ///Blocks until the [Aggregator] has inserted at least `count` captured packets into its map
+    /// # Arguments
+    /// * `count` - The number of [ParsedPacket] to wait for, e.g. the value returned by [Parser::wait_for_completion](crate::parser::Parser::wait_for_completion)
+    /// # Remarks
+    /// The flow records of a [FlowCollector](crate::collector::FlowCollector) are not counted, see [Aggregator::wait_for_flow_records]
+    pub fn wait_for_packets(&self, count: usize) {
+        let received = self.received.lock().unwrap();
+        let _received = self.cv_received.wait_while(received, |received| received.packets < count).unwrap();
+    }
+
+    ///Blocks until the [Aggregator] has inserted at least `count` flow records of a [FlowCollector](crate::collector::FlowCollector) into its map
+    pub fn wait_for_flow_records(&self, count: usize) {
+        let received = self.received.lock().unwrap();
+        let _received = self.cv_received.wait_while(received, |received| received.flow_records < count).unwrap();
+    }
+
+    ///Returns the [AggregatorConfig] the [Aggregator] was created with
+    pub fn get_config(&self) -> AggregatorConfig {
+        self.config.clone()
+    }
+
+    ///Returns a reference to the data aggregated per time window, empty if the [AggregatorConfig] has no window
+    pub fn get_windowed_data(&self) -> Arc<RwLock<WindowedData>> {
+        Arc::clone(&self.windowed_data)
+    }
+
+    ///Returns the number of captured packets inserted into the aggregated data map since the [Aggregator] was created,
+    /// the flow records are counted by [Aggregator::get_flow_record_count]
+    pub fn get_received_count(&self) -> usize {
+        self.received.lock().unwrap().packets
+    }
+
+    ///Returns the number of flow records inserted into the aggregated data map since the [Aggregator] was created
+    pub fn get_flow_record_count(&self) -> usize {
+        self.received.lock().unwrap().flow_records
+    }
+
+    ///Returns the number of packets and bytes inserted into the aggregated data map for each IP protocol number,
+    /// these totals are not affected by the entries removed from the map
+    pub fn get_protocol_totals(&self) -> BTreeMap<u8, ProtocolStats> {
+        self.protocols.lock().unwrap().clone()
+    }
+
+    ///Returns the number of entries evicted from the aggregated data map because it was full
+    pub fn get_evicted_count(&self) -> usize {
+        *self.evicted.lock().unwrap()
+    }
+
+    ///Returns a [Receiver] of the entries removed from the aggregated data map because of the idle or active timeout
+    /// or evicted because the map was full,
+    /// each call creates a new subscription that receives the entries completed from then on
+    ///
+    /// # Examples
+    /// Basic usage:
+    /// ```rust
+    /// use std::time::Duration;
+    /// use Network_analyzer::aggregator::{Aggregator, AggregatorConfig};
+    ///
+    /// let config = AggregatorConfig { idle_timeout: Some(Duration::from_secs(15)), active_timeout: Some(Duration::from_secs(1800)), ..Default::default() };
+    /// let aggregator=Aggregator::with_config(config);
+    /// let completed = aggregator.subscribe_completed_flows();
+    /// for flow in completed.iter() {
+    ///     println!("{}{} finished ({:?})", flow.connection, flow.metadata, flow.reason);
+    /// }
+    /// ```
+    pub fn subscribe_completed_flows(&self) -> Receiver<CompletedFlow> {
+        let (tx, rx) = channel();
+        self.completed_subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
     ///Returns the [Aggregator] sender to allow it to send [ParsedPacket]
     pub fn get_sender(&self) -> Sender<ParsedPacket> {
         self.tx.clone()
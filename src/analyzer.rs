@@ -1,3 +1,7 @@
+use std::io;
+use crate::collector::FlowCollector;
+use crate::metrics::MetricsServer;
+use crate::parser::FilterError;
 use crate::report_writer::ReportWriter;
 use crate::socket_listener::SocketListener;
 
@@ -18,7 +22,7 @@ pub struct Analyzer{
 }
 
 impl Analyzer{
-    ///Creates the [SocketListener] and passes the aggregated data of its [Aggregator] to the [ReportWriter].
+    ///Creates the [SocketListener] and passes the aggregated data of its [Aggregator] and its capture statistics to the [ReportWriter].
     /// # Arguments
     /// * `filename` - the name of the file on which the aggregated data will be printed
     /// * `timer` - u64 representing the period of time that must elapse (in the absence of pauses) before the ReportWriter is printed
@@ -35,8 +39,110 @@ impl Analyzer{
     pub fn new(device:&str,filename:&str,timer:u64)->Self{
         let sl=SocketListener::new(device);
         let report_writer = ReportWriter::new(filename.to_string(), timer, sl.get_aggregated_data());
+        report_writer.set_capture_stats(Some(sl.get_capture_stats()));
         Analyzer{sl,report_writer}
     }
+    ///Creates the [SocketListener] capturing only the packets matching a BPF filter and passes the aggregated data of its [Aggregator] to the [ReportWriter].
+    /// # Arguments
+    /// * `device` - The name of device to listen to
+    /// * `filename` - the name of the file on which the aggregated data will be printed
+    /// * `timer` - u64 representing the period of time that must elapse (in the absence of pauses) before the ReportWriter is printed
+    /// * `filter` - The BPF filter expression, e.g. `tcp port 443 or udp port 53`
+    /// # Examples
+    /// Basic usage:
+    /// ```rust
+    /// use Network_analyzer::analyzer::Analyzer;
+    /// let a=Analyzer::with_filter("eth0", "file.txt", 5, "tcp port 443 or udp port 53").expect("invalid filter");
+    /// // the filter can be changed while the analyzer is running
+    /// a.set_filter(Some("tcp")).expect("invalid filter");
+    /// ```
+    /// # Errors
+    /// Returns a [FilterError] if the filter expression cannot be compiled
+    pub fn with_filter(device:&str,filename:&str,timer:u64,filter:&str)->Result<Self,FilterError>{
+        let sl=SocketListener::with_filter(device, filter)?;
+        let report_writer = ReportWriter::new(filename.to_string(), timer, sl.get_aggregated_data());
+        report_writer.set_capture_stats(Some(sl.get_capture_stats()));
+        Ok(Analyzer{sl,report_writer})
+    }
+    ///Creates the [SocketListener] reading from a capture file and passes the aggregated data of its [Aggregator] to the [ReportWriter].
+    /// # Arguments
+    /// * `path` - The path of the `.pcap` or `.pcapng` file to read
+    /// * `filename` - the name of the file on which the aggregated data will be printed
+    /// * `timer` - u64 representing the period of time that must elapse (in the absence of pauses) before the ReportWriter is printed
+    /// # Examples
+    /// Basic usage:
+    /// ```rust
+    /// use Network_analyzer::analyzer::Analyzer;
+    /// let a=Analyzer::from_file("capture.pcap", "file.txt", 5);
+    /// // I am waiting for the whole file to be analyzed, the final report is then written
+    /// a.wait_for_completion().expect("Error writing the final report");
+    /// ```
+    ///
+    pub fn from_file(path:&str,filename:&str,timer:u64)->Self{
+        let sl=SocketListener::from_file(path);
+        let report_writer = ReportWriter::new(filename.to_string(), timer, sl.get_aggregated_data());
+        report_writer.set_capture_stats(Some(sl.get_capture_stats()));
+        Analyzer{sl,report_writer}
+    }
+    ///Creates the [SocketListener] reading from a capture file only the packets matching a BPF filter and passes the aggregated data of its [Aggregator] to the [ReportWriter].
+    /// # Errors
+    /// Returns a [FilterError] if the filter expression cannot be compiled
+    pub fn from_file_with_filter(path:&str,filename:&str,timer:u64,filter:&str)->Result<Self,FilterError>{
+        let sl=SocketListener::from_file_with_filter(path, filter)?;
+        let report_writer = ReportWriter::new(filename.to_string(), timer, sl.get_aggregated_data());
+        report_writer.set_capture_stats(Some(sl.get_capture_stats()));
+        Ok(Analyzer{sl,report_writer})
+    }
+    /// Changing the BPF filter of the running SocketListener, `None` removes the current filter
+    /// # Errors
+    /// Returns a [FilterError] if the filter expression cannot be compiled, in that case the current filter is kept
+    pub fn set_filter(&self,filter:Option<&str>)->Result<(),FilterError>{
+        self.sl.set_filter(filter)
+    }
+    /// Returning the BPF filter currently applied by the SocketListener, if any
+    pub fn get_filter(&self)->Option<String>{
+        self.sl.get_filter()
+    }
+    /// Waiting for the SocketListener to complete and writing the final report
+    /// # Errors
+    /// see [ReportWriter::write_now]
+    /// # Remarks
+    /// When listening to a live device this function only returns if the device is closed
+    pub fn wait_for_completion(&self)->io::Result<()>{
+        self.sl.wait_for_completion();
+        self.report_writer.write_now()
+    }
+    /// Serving the aggregated data and the capture statistics in the Prometheus text exposition format on `http://[address]/metrics`,
+    /// until the returned [MetricsServer] is dropped
+    /// # Errors
+    /// If the server cannot listen on the address
+    pub fn serve_metrics(&self,address:&str)->io::Result<MetricsServer>{
+        let metrics = MetricsServer::new(address, self.sl.get_aggregator())?;
+        metrics.set_capture_stats(Some(self.sl.get_capture_stats()));
+        Ok(metrics)
+    }
+    /// Receiving NetFlow v5, NetFlow v9 and IPFIX messages on the UDP address and aggregating their flow records
+    /// together with the captured traffic, until the returned [FlowCollector] is dropped
+    /// # Errors
+    /// If the UDP socket cannot be bound to the address
+    pub fn collect_flows(&self,address:&str)->io::Result<FlowCollector>{
+        FlowCollector::new(address, self.sl.get_aggregator().get_sender())
+    }
+    /// Writing the report immediately, without waiting for the timer of the report writer
+    /// # Errors
+    /// see [ReportWriter::write_now]
+    pub fn write_report(&self)->io::Result<()>{
+        self.report_writer.write_now()
+    }
+    /// Stopping SocketListener and report writer, the final report is written
+    /// # Errors
+    /// see [ReportWriter::stop]
+    /// # Remarks
+    /// This is also done when the Analyzer is dropped
+    pub fn stop(&self)->io::Result<()>{
+        self.sl.pause();
+        self.report_writer.stop()
+    }
     /// Pausing SocketListener and report writer
     pub fn pause(&self){
         println!("PAUSE: Pausing Network Analyzer");
@@ -0,0 +1,469 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::parsed_packet::{FlowSummary, ParsedPacket, Protocol};
+
+//information elements decoded from NetFlow v9 and IPFIX records, NetFlow v9 field types have the same numbers
+const OCTET_DELTA_COUNT: u16 = 1;
+const PACKET_DELTA_COUNT: u16 = 2;
+const PROTOCOL_IDENTIFIER: u16 = 4;
+const SOURCE_TRANSPORT_PORT: u16 = 7;
+const SOURCE_IPV4_ADDRESS: u16 = 8;
+const DESTINATION_TRANSPORT_PORT: u16 = 11;
+const DESTINATION_IPV4_ADDRESS: u16 = 12;
+const FLOW_END_SYS_UP_TIME: u16 = 21;
+const FLOW_START_SYS_UP_TIME: u16 = 22;
+const SOURCE_IPV6_ADDRESS: u16 = 27;
+const DESTINATION_IPV6_ADDRESS: u16 = 28;
+const ICMP_TYPE_CODE_IPV4: u16 = 32;
+const OCTET_TOTAL_COUNT: u16 = 85;
+const PACKET_TOTAL_COUNT: u16 = 86;
+const ICMP_TYPE_CODE_IPV6: u16 = 139;
+const FLOW_START_SECONDS: u16 = 150;
+const FLOW_END_SECONDS: u16 = 151;
+const FLOW_START_MILLISECONDS: u16 = 152;
+const FLOW_END_MILLISECONDS: u16 = 153;
+const SYSTEM_INIT_TIME_MILLISECONDS: u16 = 160;
+
+/// Field length of the variable-length IPFIX information elements
+const VARIABLE_LENGTH: u16 = 65535;
+
+/// Struct holding the statistics of the datagrams received by a [FlowCollector]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CollectorStats {
+    /// Number of datagrams received
+    pub packets: usize,
+    /// Number of flow records sent to the [Aggregator](crate::aggregator::Aggregator)
+    pub records: usize,
+    /// Number of flow records skipped because they have no addresses or are truncated
+    pub skipped: usize,
+    /// Number of NetFlow v9 and IPFIX data sets skipped because their template is not known yet,
+    /// the number of records they hold cannot be known without it
+    pub unknown_template_sets: usize,
+    /// Number of datagrams that are not NetFlow v5, v9 or IPFIX messages or are truncated
+    pub malformed: usize,
+}
+
+/// Struct describing a field of a NetFlow v9 or IPFIX template
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TemplateField {
+    id: u16,
+    length: u16,
+    /// Private Enterprise Number of an enterprise-specific IPFIX information element
+    enterprise: Option<u32>,
+}
+
+/// Templates learned from the exporters, by exporter address, observation domain (source id) and template id
+type Templates = HashMap<(SocketAddr, u32, u16), Vec<TemplateField>>;
+
+/// Struct describing the values of a flow record, decoded from any of the supported formats
+#[derive(Debug, Default)]
+struct FlowValues {
+    source: Option<IpAddr>,
+    destination: Option<IpAddr>,
+    source_port: Option<u16>,
+    destination_port: Option<u16>,
+    protocol: Option<u8>,
+    icmp_type_code: Option<u16>,
+    packets: Option<u64>,
+    bytes: Option<u64>,
+    first_timestamp: Option<DateTime<Utc>>,
+    last_timestamp: Option<DateTime<Utc>>,
+    /// System uptime of the exporter at the start and at the end of the flow, in milliseconds
+    first_uptime: Option<u32>,
+    last_uptime: Option<u32>,
+    /// Time at which the system uptime of an IPFIX exporter was zero
+    system_init_time: Option<DateTime<Utc>>,
+}
+
+impl FlowValues {
+    /// Returns the [ParsedPacket] standing for the flow record, `None` if the record has no addresses
+    /// # Arguments
+    /// * `export_time` - The time of the message carrying the record, used when the record has no timestamps
+    /// * `interface` - The interface of the packet, the address of the exporter
+    fn into_packet(self, export_time: DateTime<Utc>, interface: &str) -> Option<ParsedPacket> {
+        let (source, destination) = (self.source?, self.destination?);
+        let icmp = self.icmp_type_code.map(|icmp_type_code| ((icmp_type_code >> 8) as u8, icmp_type_code as u8));
+        //without its type and code an ICMP flow gets an unknown type instead of being mistaken for an echo reply
+        let protocol = match (self.protocol.unwrap_or(0), icmp) {
+            (1, Some((icmp_type, code))) => Protocol::Icmpv4 { icmp_type, code },
+            (58, Some((icmp_type, code))) => Protocol::Icmpv6 { icmp_type, code },
+            (number, _) => Protocol::from_ip_number(number),
+        };
+        //ports are only meaningful for TCP and UDP, as for the captured packets
+        let ports = matches!(protocol, Protocol::Tcp | Protocol::Udp);
+        let last_timestamp = self.last_timestamp.or(self.first_timestamp).unwrap_or(export_time);
+        let first_timestamp = self.first_timestamp.unwrap_or(last_timestamp).min(last_timestamp);
+        let mut packet = ParsedPacket::new(
+            last_timestamp,
+            source,
+            destination,
+            self.source_port.filter(|_| ports),
+            self.destination_port.filter(|_| ports),
+            protocol,
+            self.bytes.unwrap_or(0) as usize,
+        ).with_interface(Some(interface.to_string()));
+        packet.flow = Some(FlowSummary { packets: self.packets.unwrap_or(1).max(1) as usize, first_timestamp });
+        Some(packet)
+    }
+}
+
+/// Struct that receives NetFlow v5, NetFlow v9 and IPFIX messages over UDP and sends their flow records to an
+/// [Aggregator](crate::aggregator::Aggregator), in a separate thread, as an input alternative or additional to a [Parser](crate::parser::Parser)
+///
+/// Each flow record becomes a [ParsedPacket] whose [FlowSummary] carries the number of packets and the start of the flow,
+/// so that the aggregated data account for all the packets and bytes of the flow. The interface of the records is the
+/// address of the router that exported them, to tell them apart from the traffic captured locally.
+///
+/// # Examples
+/// Combining the flows exported by the routers with the traffic of a local device:
+/// ```rust
+/// use Network_analyzer::aggregator::Aggregator;
+/// use Network_analyzer::collector::FlowCollector;
+/// use Network_analyzer::parser::Parser;
+///
+/// let aggregator = Aggregator::new();
+/// let parser = Parser::new("eth0", aggregator.get_sender());
+/// let collector = FlowCollector::new("0.0.0.0:2055", aggregator.get_sender()).expect("cannot listen on the port");
+/// ```
+///
+/// # Remarks
+/// NetFlow v9 and IPFIX data records can only be decoded once the template describing them has been received,
+/// the records received before are skipped. Options templates and their records are ignored. A truncated data record
+/// ends its data set, the records before it are kept and it is counted as skipped.
+pub struct FlowCollector {
+    address: SocketAddr,
+    stats: Arc<Mutex<CollectorStats>>,
+    stopped: Arc<Mutex<bool>>,
+}
+
+impl FlowCollector {
+    /// Creates a [FlowCollector] listening on the given UDP address and a thread that sends the flow records received
+    /// to the [Aggregator](crate::aggregator::Aggregator), until it is stopped or dropped
+    /// # Arguments
+    /// * `address` - The address to listen on, e.g. `0.0.0.0:2055`, with port 0 an unused port is chosen
+    /// * `aggregator_tx` - The sender of the [Aggregator](crate::aggregator::Aggregator)
+    /// # Errors
+    /// If the UDP socket cannot be bound to the address
+    pub fn new(address: &str, aggregator_tx: Sender<ParsedPacket>) -> io::Result<Self> {
+        let socket = UdpSocket::bind(address)?;
+        //wake up periodically to check if the collector has been stopped
+        socket.set_read_timeout(Some(Duration::from_millis(200)))?;
+        let address = socket.local_addr()?;
+        let stats = Arc::new(Mutex::new(CollectorStats::default()));
+        let stats_clone = stats.clone();
+        let stopped = Arc::new(Mutex::new(false));
+        let stopped_clone = stopped.clone();
+
+        std::thread::spawn(move || {
+            let mut templates = Templates::new();
+            let mut buffer = vec![0u8; 65535];
+            while !*stopped_clone.lock().unwrap() {
+                let (length, exporter) = match socket.recv_from(&mut buffer) {
+                    Ok(received) => received,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => continue,
+                    Err(e) => {
+                        eprintln!("Error receiving flow records: {}", e);
+                        continue;
+                    },
+                };
+                let decoded = decode(&buffer[..length], exporter, &mut templates);
+                let mut stats = stats_clone.lock().unwrap();
+                stats.packets += 1;
+                let Some((export_time, records, unknown_template_sets, truncated_records)) = decoded else {
+                    stats.malformed += 1;
+                    continue;
+                };
+                stats.unknown_template_sets += unknown_template_sets;
+                stats.skipped += truncated_records;
+                for record in records {
+                    match record.into_packet(export_time, &exporter.ip().to_string()) {
+                        Some(packet) => {
+                            if aggregator_tx.send(packet).is_err() {
+                                //the aggregator is gone, nothing left to do
+                                return;
+                            }
+                            stats.records += 1;
+                        },
+                        None => stats.skipped += 1,
+                    }
+                }
+            }
+        });
+        Ok(FlowCollector { address, stats, stopped })
+    }
+
+    /// Returns the address the [FlowCollector] is listening on
+    pub fn get_address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Returns the statistics of the datagrams received so far
+    pub fn get_collector_stats(&self) -> CollectorStats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Stops receiving flow records, the thread of the [FlowCollector] exits and the UDP port is released shortly after
+    pub fn stop(&self) {
+        *self.stopped.lock().unwrap() = true;
+    }
+}
+
+impl Drop for FlowCollector {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Decodes a NetFlow or IPFIX message, learning the templates it carries
+/// # Returns
+/// The export time of the message, its flow records, the number of data sets skipped because their template is unknown
+/// and the number of data sets ended by a truncated record, `None` if the message is malformed
+fn decode(message: &[u8], exporter: SocketAddr, templates: &mut Templates) -> Option<(DateTime<Utc>, Vec<FlowValues>, usize, usize)> {
+    match read_uint(message, 0, 2)? {
+        5 => decode_v5(message).map(|(export_time, records)| (export_time, records, 0, 0)),
+        9 => decode_v9(message, exporter, templates),
+        10 => decode_ipfix(message, exporter, templates),
+        _ => None,
+    }
+}
+
+/// Reads an unsigned integer in network byte order of up to 8 bytes, `None` if the data is too short
+fn read_uint(data: &[u8], offset: usize, length: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset.checked_add(length)?)?;
+    Some(bytes.iter().fold(0u64, |value, byte| value << 8 | *byte as u64))
+}
+
+fn timestamp_millis(millis: i64) -> Option<DateTime<Utc>> {
+    Utc.timestamp_millis_opt(millis).single()
+}
+
+/// Returns the time at which the system uptime of the exporter was `uptime`, knowing that it was `now_uptime` at `now`
+fn from_uptime(now: DateTime<Utc>, now_uptime: u32, uptime: u32) -> DateTime<Utc> {
+    //the uptime wraps around, an uptime after the export time is a clock skew of the exporter
+    let elapsed = now_uptime.wrapping_sub(uptime);
+    if elapsed > u32::MAX / 2 { now } else { now - chrono::Duration::milliseconds(elapsed as i64) }
+}
+
+fn decode_v5(message: &[u8]) -> Option<(DateTime<Utc>, Vec<FlowValues>)> {
+    let count = read_uint(message, 2, 2)? as usize;
+    let uptime = read_uint(message, 4, 4)? as u32;
+    let export_time = Utc.timestamp_opt(read_uint(message, 8, 4)? as i64, read_uint(message, 12, 4)? as u32).single()?;
+    if message.len() < 24 + count * 48 {
+        return None;
+    }
+    let records = message[24..24 + count * 48].chunks(48).map(|record| {
+        let address = |offset: usize| IpAddr::V4(Ipv4Addr::new(record[offset], record[offset + 1], record[offset + 2], record[offset + 3]));
+        let u32_at = |offset: usize| read_uint(record, offset, 4).unwrap_or(0);
+        let u16_at = |offset: usize| read_uint(record, offset, 2).unwrap_or(0) as u16;
+        let protocol = record[38];
+        FlowValues {
+            source: Some(address(0)),
+            destination: Some(address(4)),
+            source_port: Some(u16_at(32)),
+            destination_port: Some(u16_at(34)),
+            protocol: Some(protocol),
+            //ICMP type and code are carried by the destination port
+            icmp_type_code: Some(u16_at(34)).filter(|_| protocol == 1),
+            packets: Some(u32_at(16)),
+            bytes: Some(u32_at(20)),
+            first_timestamp: Some(from_uptime(export_time, uptime, u32_at(24) as u32)),
+            last_timestamp: Some(from_uptime(export_time, uptime, u32_at(28) as u32)),
+            ..Default::default()
+        }
+    }).collect();
+    Some((export_time, records))
+}
+
+fn decode_v9(message: &[u8], exporter: SocketAddr, templates: &mut Templates) -> Option<(DateTime<Utc>, Vec<FlowValues>, usize, usize)> {
+    let uptime = read_uint(message, 4, 4)? as u32;
+    let export_time = Utc.timestamp_opt(read_uint(message, 8, 4)? as i64, 0).single()?;
+    let source_id = read_uint(message, 16, 4)? as u32;
+    let mut records = Vec::new();
+    let mut unknown_template_sets = 0;
+    let mut truncated_records = 0;
+    let mut offset = 20;
+    while offset + 4 <= message.len() {
+        let set_id = read_uint(message, offset, 2)? as u16;
+        let length = read_uint(message, offset + 2, 2)? as usize;
+        if length < 4 || offset + length > message.len() {
+            return None;
+        }
+        let set = &message[offset + 4..offset + length];
+        match set_id {
+            0 => {
+                let mut position = 0;
+                while position + 4 <= set.len() {
+                    let template_id = read_uint(set, position, 2)? as u16;
+                    let field_count = read_uint(set, position + 2, 2)? as usize;
+                    let mut fields = Vec::with_capacity(field_count);
+                    for index in 0..field_count {
+                        let field = position + 4 + index * 4;
+                        fields.push(TemplateField { id: read_uint(set, field, 2)? as u16, length: read_uint(set, field + 2, 2)? as u16, enterprise: None });
+                    }
+                    position += 4 + field_count * 4;
+                    templates.insert((exporter, source_id, template_id), fields);
+                }
+            },
+            //options templates
+            1 => {},
+            id if id >= 256 => match templates.get(&(exporter, source_id, id)) {
+                Some(fields) => {
+                    let (decoded, truncated) = decode_data_set(set, fields);
+                    truncated_records += truncated as usize;
+                    records.extend(decoded.into_iter().map(|mut values| {
+                        values.first_timestamp = values.first_timestamp.or(values.first_uptime.map(|first| from_uptime(export_time, uptime, first)));
+                        values.last_timestamp = values.last_timestamp.or(values.last_uptime.map(|last| from_uptime(export_time, uptime, last)));
+                        values
+                    }));
+                },
+                None => unknown_template_sets += 1,
+            },
+            _ => {},
+        }
+        offset += length;
+    }
+    Some((export_time, records, unknown_template_sets, truncated_records))
+}
+
+fn decode_ipfix(message: &[u8], exporter: SocketAddr, templates: &mut Templates) -> Option<(DateTime<Utc>, Vec<FlowValues>, usize, usize)> {
+    let length = read_uint(message, 2, 2)? as usize;
+    if length < 16 || length > message.len() {
+        return None;
+    }
+    let message = &message[..length];
+    let export_time = Utc.timestamp_opt(read_uint(message, 4, 4)? as i64, 0).single()?;
+    let domain = read_uint(message, 12, 4)? as u32;
+    let mut records = Vec::new();
+    let mut unknown_template_sets = 0;
+    let mut truncated_records = 0;
+    let mut offset = 16;
+    while offset + 4 <= message.len() {
+        let set_id = read_uint(message, offset, 2)? as u16;
+        let length = read_uint(message, offset + 2, 2)? as usize;
+        if length < 4 || offset + length > message.len() {
+            return None;
+        }
+        let set = &message[offset + 4..offset + length];
+        match set_id {
+            2 => {
+                let mut position = 0;
+                while position + 4 <= set.len() {
+                    let template_id = read_uint(set, position, 2)? as u16;
+                    let field_count = read_uint(set, position + 2, 2)? as usize;
+                    position += 4;
+                    if field_count == 0 {
+                        //template withdrawal
+                        templates.remove(&(exporter, domain, template_id));
+                        continue;
+                    }
+                    let mut fields = Vec::with_capacity(field_count);
+                    for _ in 0..field_count {
+                        let id = read_uint(set, position, 2)? as u16;
+                        let length = read_uint(set, position + 2, 2)? as u16;
+                        position += 4;
+                        let enterprise = if id & 0x8000 != 0 {
+                            position += 4;
+                            Some(read_uint(set, position - 4, 4)? as u32)
+                        } else {
+                            None
+                        };
+                        fields.push(TemplateField { id: id & 0x7FFF, length, enterprise });
+                    }
+                    templates.insert((exporter, domain, template_id), fields);
+                }
+            },
+            //options templates
+            3 => {},
+            id if id >= 256 => match templates.get(&(exporter, domain, id)) {
+                //uptimes are relative to the system init time, without it the export time is used
+                Some(fields) => {
+                    let (decoded, truncated) = decode_data_set(set, fields);
+                    truncated_records += truncated as usize;
+                    records.extend(decoded.into_iter().map(|mut values| {
+                        let from_init = |uptime: Option<u32>| values.system_init_time.zip(uptime)
+                            .map(|(init, uptime)| init + chrono::Duration::milliseconds(uptime as i64));
+                        values.first_timestamp = values.first_timestamp.or(from_init(values.first_uptime));
+                        values.last_timestamp = values.last_timestamp.or(from_init(values.last_uptime));
+                        values
+                    }));
+                },
+                None => unknown_template_sets += 1,
+            },
+            _ => {},
+        }
+        offset += length;
+    }
+    Some((export_time, records, unknown_template_sets, truncated_records))
+}
+
+/// Decodes the records of a data set described by a template, until the rest of the set is padding
+/// # Returns
+/// The records and whether the set ends with a truncated record. The records following a truncated one cannot be
+/// delimited, the decoding stops there and keeps the records before it
+fn decode_data_set(set: &[u8], fields: &[TemplateField]) -> (Vec<FlowValues>, bool) {
+    let min_length: usize = fields.iter().map(|field| if field.length == VARIABLE_LENGTH { 1 } else { field.length as usize }).sum();
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while min_length > 0 && offset + min_length <= set.len() {
+        match decode_record(set, &mut offset, fields) {
+            Some(values) => records.push(values),
+            None => return (records, true),
+        }
+    }
+    (records, false)
+}
+
+/// Decodes the record of a data set starting at the offset and moves the offset after it
+/// # Returns
+/// The values of the record, `None` if it is truncated
+fn decode_record(set: &[u8], offset: &mut usize, fields: &[TemplateField]) -> Option<FlowValues> {
+    let mut values = FlowValues::default();
+    for field in fields {
+        let mut length = field.length as usize;
+        if field.length == VARIABLE_LENGTH {
+            length = read_uint(set, *offset, 1)? as usize;
+            *offset += 1;
+            if length == 255 {
+                length = read_uint(set, *offset, 2)? as usize;
+                *offset += 2;
+            }
+        }
+        let value = set.get(*offset..*offset + length)?;
+        *offset += length;
+        if field.enterprise.is_some() {
+            continue;
+        }
+        let number = if length <= 8 { read_uint(value, 0, length) } else { None };
+        match (field.id, value.len()) {
+            (SOURCE_IPV4_ADDRESS, 4) => values.source = Some(IpAddr::V4(Ipv4Addr::new(value[0], value[1], value[2], value[3]))),
+            (DESTINATION_IPV4_ADDRESS, 4) => values.destination = Some(IpAddr::V4(Ipv4Addr::new(value[0], value[1], value[2], value[3]))),
+            (SOURCE_IPV6_ADDRESS, 16) => values.source = Some(IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(value).unwrap()))),
+            (DESTINATION_IPV6_ADDRESS, 16) => values.destination = Some(IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(value).unwrap()))),
+            (SOURCE_TRANSPORT_PORT, _) => values.source_port = number.map(|port| port as u16),
+            (DESTINATION_TRANSPORT_PORT, _) => values.destination_port = number.map(|port| port as u16),
+            (PROTOCOL_IDENTIFIER, _) => values.protocol = number.map(|protocol| protocol as u8),
+            (ICMP_TYPE_CODE_IPV4 | ICMP_TYPE_CODE_IPV6, _) => values.icmp_type_code = number.map(|type_code| type_code as u16),
+            (OCTET_DELTA_COUNT, _) => values.bytes = number,
+            (PACKET_DELTA_COUNT, _) => values.packets = number,
+            //total counts are only used if the record has no delta counts
+            (OCTET_TOTAL_COUNT, _) => values.bytes = values.bytes.or(number),
+            (PACKET_TOTAL_COUNT, _) => values.packets = values.packets.or(number),
+            (FLOW_START_SECONDS, _) => values.first_timestamp = number.and_then(|seconds| Utc.timestamp_opt(seconds as i64, 0).single()),
+            (FLOW_END_SECONDS, _) => values.last_timestamp = number.and_then(|seconds| Utc.timestamp_opt(seconds as i64, 0).single()),
+            (FLOW_START_MILLISECONDS, _) => values.first_timestamp = number.and_then(|millis| timestamp_millis(millis as i64)),
+            (FLOW_END_MILLISECONDS, _) => values.last_timestamp = number.and_then(|millis| timestamp_millis(millis as i64)),
+            (FLOW_START_SYS_UP_TIME, _) => values.first_uptime = number.map(|uptime| uptime as u32),
+            (FLOW_END_SYS_UP_TIME, _) => values.last_uptime = number.map(|uptime| uptime as u32),
+            (SYSTEM_INIT_TIME_MILLISECONDS, _) => values.system_init_time = number.and_then(|millis| timestamp_millis(millis as i64)),
+            _ => {},
+        }
+    }
+    Some(values)
+}
@@ -0,0 +1,391 @@
+use std::io;
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+
+use crate::aggregator::{CompletedFlow, ExpiryReason};
+use crate::netflow::{bind_for, resolve, ExportStats, FlowRecord, MAX_PACKET_SIZE};
+use crate::netflow::{DST_MASK, ICMP_TYPE, IN_BYTES, IN_PKTS, IPV4_DST_ADDR, IPV4_SRC_ADDR, IPV6_DST_ADDR, IPV6_DST_MASK,
+                     IPV6_SRC_ADDR, IPV6_SRC_MASK, L4_DST_PORT, L4_SRC_PORT, PROTOCOL, SRC_MASK};
+use crate::parsed_packet::Protocol;
+
+/// Private Enterprise Number of the enterprise-specific information elements, the number reserved for documentation
+/// by RFC 5612: a deployment with its own number can change it here
+pub const ENTERPRISE_NUMBER: u32 = 32473;
+
+/// Enterprise-specific information element: the name of the ICMP message type (e.g. `ICMPv4: Echo Request`),
+/// empty for the other protocols
+pub const ICMP_TYPE_NAME: u16 = 1;
+/// Enterprise-specific information element: the application of the flow, guessed from the well-known port of the
+/// service (e.g. `https` or `dns`), empty if the port is not known
+pub const APPLICATION_LABEL: u16 = 2;
+
+const IPFIX_VERSION: u16 = 10;
+const TEMPLATE_SET_ID: u16 = 2;
+const IPV4_TEMPLATE_ID: u16 = 256;
+const IPV6_TEMPLATE_ID: u16 = 257;
+/// Field length of the variable-length information elements
+const VARIABLE_LENGTH: u16 = 65535;
+/// Bit of the information element identifier marking an enterprise-specific information element
+const ENTERPRISE_BIT: u16 = 0x8000;
+
+const INTERFACE_NAME: u16 = 82;
+const FLOW_END_REASON: u16 = 136;
+const ICMP_TYPE_CODE_IPV6: u16 = 139;
+const FLOW_START_MILLISECONDS: u16 = 152;
+const FLOW_END_MILLISECONDS: u16 = 153;
+
+/// Information elements of the template of the IPv4 flows, with their length in bytes
+const IPV4_FIELDS: [(u16, u16); 16] = [
+    (IPV4_SRC_ADDR, 4), (IPV4_DST_ADDR, 4), (SRC_MASK, 1), (DST_MASK, 1), (L4_SRC_PORT, 2), (L4_DST_PORT, 2),
+    (PROTOCOL, 1), (ICMP_TYPE, 2), (IN_PKTS, 8), (IN_BYTES, 8), (FLOW_START_MILLISECONDS, 8), (FLOW_END_MILLISECONDS, 8),
+    (FLOW_END_REASON, 1), (INTERFACE_NAME, VARIABLE_LENGTH),
+    (ENTERPRISE_BIT | ICMP_TYPE_NAME, VARIABLE_LENGTH), (ENTERPRISE_BIT | APPLICATION_LABEL, VARIABLE_LENGTH),
+];
+
+/// Information elements of the template of the IPv6 flows, with their length in bytes
+const IPV6_FIELDS: [(u16, u16); 16] = [
+    (IPV6_SRC_ADDR, 16), (IPV6_DST_ADDR, 16), (IPV6_SRC_MASK, 1), (IPV6_DST_MASK, 1), (L4_SRC_PORT, 2), (L4_DST_PORT, 2),
+    (PROTOCOL, 1), (ICMP_TYPE_CODE_IPV6, 2), (IN_PKTS, 8), (IN_BYTES, 8), (FLOW_START_MILLISECONDS, 8), (FLOW_END_MILLISECONDS, 8),
+    (FLOW_END_REASON, 1), (INTERFACE_NAME, VARIABLE_LENGTH),
+    (ENTERPRISE_BIT | ICMP_TYPE_NAME, VARIABLE_LENGTH), (ENTERPRISE_BIT | APPLICATION_LABEL, VARIABLE_LENGTH),
+];
+
+/// Services of the well-known ports, used for the application labels: IP protocol number, port and name
+const APPLICATIONS: [(u8, u16, &str); 24] = [
+    (6, 20, "ftp-data"), (6, 21, "ftp"), (6, 22, "ssh"), (6, 23, "telnet"), (6, 25, "smtp"), (6, 53, "dns"), (17, 53, "dns"),
+    (17, 67, "dhcp"), (17, 68, "dhcp"), (6, 80, "http"), (6, 110, "pop3"), (17, 123, "ntp"), (6, 143, "imap"), (17, 161, "snmp"),
+    (6, 443, "https"), (17, 443, "quic"), (6, 465, "smtps"), (6, 587, "submission"), (6, 993, "imaps"), (6, 995, "pop3s"),
+    (6, 3306, "mysql"), (6, 3389, "rdp"), (6, 5432, "postgresql"), (17, 5353, "mdns"),
+];
+
+/// Enum describing the transport protocol used by an [IpfixExporter] to reach the collector
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IpfixTransport {
+    /// Every message is a datagram, the templates are sent again every template refresh interval (the default)
+    #[default]
+    Udp,
+    /// Messages are sent over a single connection, the templates are sent once at the start of the connection
+    Tcp,
+}
+
+/// Struct that sends the flows completed by an [Aggregator](crate::aggregator::Aggregator) to an IPFIX collector
+/// (RFC 7011) over UDP or TCP, in a separate thread
+///
+/// Each flow is exported as a record for the packets sent by the initiator and, for bidirectional aggregation, a record
+/// for the packets sent by the responder, with a template for IPv4 flows (id 256) and one for IPv6 flows (id 257).
+/// The records carry the 5-tuple with the prefix lengths, the ICMP type and code, the packet and octet counts,
+/// the flow start and end in milliseconds, the flow end reason (idle timeout, active timeout or lack of resources) and the
+/// interface name, plus the enterprise-specific elements [ICMP_TYPE_NAME] and [APPLICATION_LABEL] of [ENTERPRISE_NUMBER].
+/// The sequence number of each message is the number of data records sent before it.
+///
+/// # Examples
+/// Basic usage:
+/// ```rust
+/// use std::time::Duration;
+/// use Network_analyzer::aggregator::{Aggregator, AggregatorConfig};
+/// use Network_analyzer::ipfix::{IpfixExporter, IpfixTransport};
+///
+/// let config = AggregatorConfig { idle_timeout: Some(Duration::from_secs(15)), ..Default::default() };
+/// let aggregator = Aggregator::with_config(config);
+/// let exporter = IpfixExporter::new("192.168.1.10:4739", IpfixTransport::Tcp, aggregator.subscribe_completed_flows()).unwrap();
+/// ```
+pub struct IpfixExporter {
+    collector: SocketAddr,
+    transport: IpfixTransport,
+    template_refresh: Arc<Mutex<Duration>>,
+    stats: Arc<Mutex<ExportStats>>,
+    finished: Arc<Mutex<bool>>,
+    cv_finished: Arc<Condvar>,
+}
+
+/// The connection of an [IpfixExporter] to the collector.
+/// The TCP stream is dropped after an error and established again with the next export
+enum CollectorConnection {
+    Udp(UdpSocket, SocketAddr),
+    Tcp(Option<TcpStream>, SocketAddr),
+}
+
+impl CollectorConnection {
+    /// Establishes again the TCP connection dropped after an error
+    /// # Returns
+    /// `true` if a new connection has been established, so the templates have to be sent again
+    fn reconnect(&mut self) -> io::Result<bool> {
+        match self {
+            CollectorConnection::Tcp(stream @ None, collector) => {
+                *stream = Some(TcpStream::connect(*collector)?);
+                Ok(true)
+            },
+            _ => Ok(false),
+        }
+    }
+
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        match self {
+            CollectorConnection::Udp(socket, collector) => socket.send_to(message, *collector).map(|_| ()),
+            CollectorConnection::Tcp(stream, _) => {
+                let result = match stream {
+                    Some(tcp) => tcp.write_all(message),
+                    None => Err(io::Error::from(io::ErrorKind::NotConnected)),
+                };
+                //a message may have been written in part, the stream cannot be used anymore
+                if result.is_err() {
+                    *stream = None;
+                }
+                result
+            },
+        }
+    }
+}
+
+impl IpfixExporter {
+    /// Creates an [IpfixExporter] and a thread that sends the flows received from the channel to the collector,
+    /// until all the senders of the channel have been dropped
+    /// # Arguments
+    /// * `collector` - The address of the collector, e.g. `192.168.1.10:4739`
+    /// * `transport` - The [IpfixTransport] used to reach the collector
+    /// * `completed` - The flows to export, e.g. the [Receiver] returned by
+    ///   [Aggregator::subscribe_completed_flows](crate::aggregator::Aggregator::subscribe_completed_flows)
+    /// # Errors
+    /// If the address of the collector cannot be resolved, the UDP socket cannot be created or the TCP connection
+    /// cannot be established
+    /// # Remarks
+    /// The templates are refreshed every 10 minutes by default, see [IpfixExporter::set_template_refresh].
+    /// The sequence number of a message counts the data records sent before it, the ones of the messages that could not
+    /// be sent are not counted. After a TCP error the connection is established again, followed by the templates, with
+    /// the next export
+    pub fn new(collector: &str, transport: IpfixTransport, completed: Receiver<CompletedFlow>) -> io::Result<Self> {
+        let collector = resolve(collector)?;
+        let mut connection = match transport {
+            IpfixTransport::Udp => CollectorConnection::Udp(bind_for(&collector)?, collector),
+            IpfixTransport::Tcp => CollectorConnection::Tcp(Some(TcpStream::connect(collector)?), collector),
+        };
+        let template_refresh = Arc::new(Mutex::new(Duration::from_secs(600)));
+        let template_refresh_clone = template_refresh.clone();
+        let stats = Arc::new(Mutex::new(ExportStats::default()));
+        let stats_clone = stats.clone();
+        let finished = Arc::new(Mutex::new(false));
+        let finished_clone = finished.clone();
+        let cv_finished = Arc::new(Condvar::new());
+        let cv_finished_clone = cv_finished.clone();
+
+        std::thread::spawn(move || {
+            let boot_time = Utc::now();
+            let mut sequence: u32 = 0;
+            let mut templates_sent: Option<Instant> = None;
+            while let Ok(flow) = completed.recv() {
+                //export together the flows already waiting, to fill the messages
+                let mut flows = vec![flow];
+                flows.extend(completed.try_iter());
+                match connection.reconnect() {
+                    Ok(true) => templates_sent = None,
+                    Ok(false) => {},
+                    Err(e) => eprintln!("Error connecting to the IPFIX collector {}: {}", collector, e),
+                }
+                let refresh = *template_refresh_clone.lock().unwrap();
+                let send_templates = match transport {
+                    IpfixTransport::Udp => templates_sent.is_none_or(|sent| sent.elapsed() >= refresh),
+                    IpfixTransport::Tcp => templates_sent.is_none(),
+                };
+                let messages = encode_messages(&flows, &boot_time, send_templates);
+                let mut stats = stats_clone.lock().unwrap();
+                for (index, (mut message, count)) in messages.into_iter().enumerate() {
+                    message[8..12].copy_from_slice(&sequence.to_be_bytes());
+                    match connection.send(&message) {
+                        Ok(_) => {
+                            stats.packets += 1;
+                            stats.records += count;
+                            sequence = sequence.wrapping_add(count as u32);
+                            if send_templates && index == 0 {
+                                templates_sent = Some(Instant::now());
+                            }
+                        },
+                        Err(e) => {
+                            stats.errors += 1;
+                            eprintln!("Error sending IPFIX message to {}: {}", collector, e);
+                        },
+                    }
+                }
+            }
+            *finished_clone.lock().unwrap() = true;
+            cv_finished_clone.notify_all();
+        });
+        Ok(IpfixExporter { collector, transport, template_refresh, stats, finished, cv_finished })
+    }
+
+    /// Returns the address of the collector
+    pub fn get_collector(&self) -> SocketAddr {
+        self.collector
+    }
+
+    /// Returns the [IpfixTransport] used to reach the collector
+    pub fn get_transport(&self) -> IpfixTransport {
+        self.transport
+    }
+
+    /// Sets the interval after which the templates are sent again over UDP, with the next message
+    pub fn set_template_refresh(&self, template_refresh: Duration) {
+        *self.template_refresh.lock().unwrap() = template_refresh;
+    }
+
+    /// Returns the interval after which the templates are sent again over UDP
+    pub fn get_template_refresh(&self) -> Duration {
+        *self.template_refresh.lock().unwrap()
+    }
+
+    /// Returns the statistics of the flows exported so far
+    pub fn get_export_stats(&self) -> ExportStats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Blocks until all the senders of the channel have been dropped and every flow received has been exported
+    /// # Returns
+    /// The statistics of the flows exported
+    pub fn wait_for_completion(&self) -> ExportStats {
+        let finished = self.finished.lock().unwrap();
+        let _finished = self.cv_finished.wait_while(finished, |finished| !*finished).unwrap();
+        self.get_export_stats()
+    }
+}
+
+/// Returns the label of the application of a flow, from the service of its destination port or else of its source port
+fn application_label(record: &FlowRecord) -> &'static str {
+    [record.destination_port, record.source_port].into_iter()
+        .find_map(|port| APPLICATIONS.iter().find(|(protocol, known, _)| *protocol == record.protocol && *known == port))
+        .map_or("", |(_, _, name)| name)
+}
+
+/// Appends a variable-length string: its length in one byte (or 255 followed by the length in two bytes), then its bytes
+fn write_string(output: &mut Vec<u8>, value: &str) {
+    let value = &value.as_bytes()[..value.len().min(u16::MAX as usize)];
+    if value.len() < 255 {
+        output.push(value.len() as u8);
+    } else {
+        output.push(255);
+        output.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    }
+    output.extend_from_slice(value);
+}
+
+/// Appends the value of an information element of a record of the given flow
+fn write_field(output: &mut Vec<u8>, field: u16, record: &FlowRecord, flow: &CompletedFlow, boot_time: &DateTime<Utc>) {
+    match field {
+        FLOW_START_MILLISECONDS => output.extend_from_slice(&(record.first_timestamp.timestamp_millis() as u64).to_be_bytes()),
+        FLOW_END_MILLISECONDS => output.extend_from_slice(&(record.last_timestamp.timestamp_millis() as u64).to_be_bytes()),
+        FLOW_END_REASON => output.push(match flow.reason {
+            ExpiryReason::Idle => 1,
+            ExpiryReason::Active => 2,
+            ExpiryReason::Evicted => 5,
+        }),
+        INTERFACE_NAME => write_string(output, flow.connection.interface.as_deref().unwrap_or_default()),
+        ICMP_TYPE_CODE_IPV6 => record.write_field(output, ICMP_TYPE, boot_time),
+        x if x == ENTERPRISE_BIT | ICMP_TYPE_NAME => {
+            let name = match flow.connection.protocol {
+                Some(protocol @ (Protocol::Icmpv4 { .. } | Protocol::Icmpv6 { .. })) => protocol.to_string(),
+                _ => String::new(),
+            };
+            write_string(output, &name)
+        },
+        x if x == ENTERPRISE_BIT | APPLICATION_LABEL => write_string(output, application_label(record)),
+        _ => record.write_field(output, field, boot_time),
+    }
+}
+
+/// Appends the template set with the templates of the IPv4 and IPv6 flows
+fn write_template_set(message: &mut Vec<u8>) {
+    let start = message.len();
+    message.extend_from_slice(&TEMPLATE_SET_ID.to_be_bytes());
+    message.extend_from_slice(&[0, 0]);
+    for (template_id, fields) in [(IPV4_TEMPLATE_ID, &IPV4_FIELDS), (IPV6_TEMPLATE_ID, &IPV6_FIELDS)] {
+        message.extend_from_slice(&template_id.to_be_bytes());
+        message.extend_from_slice(&(fields.len() as u16).to_be_bytes());
+        for (field, length) in fields.iter() {
+            message.extend_from_slice(&field.to_be_bytes());
+            message.extend_from_slice(&length.to_be_bytes());
+            if field & ENTERPRISE_BIT != 0 {
+                message.extend_from_slice(&ENTERPRISE_NUMBER.to_be_bytes());
+            }
+        }
+    }
+    finish_set(message, start);
+}
+
+/// Starts a new message with its header, the length is written by [finish_message]
+fn start_message() -> Vec<u8> {
+    let mut message = Vec::with_capacity(MAX_PACKET_SIZE);
+    message.extend_from_slice(&IPFIX_VERSION.to_be_bytes());
+    message.extend_from_slice(&[0, 0]);
+    message.extend_from_slice(&(Utc::now().timestamp() as u32).to_be_bytes());
+    //the sequence number is written when the message is sent
+    message.extend_from_slice(&[0; 4]);
+    //observation domain id
+    message.extend_from_slice(&[0; 4]);
+    message
+}
+
+/// Writes the length of the set starting at the given offset, that ends at the end of the message
+fn finish_set(message: &mut [u8], start: usize) {
+    let length = (message.len() - start) as u16;
+    message[start + 2..start + 4].copy_from_slice(&length.to_be_bytes());
+}
+
+/// Writes the length of the last set, if it is open, and of the message
+fn finish_message(message: &mut [u8], set_start: Option<usize>) {
+    if let Some(start) = set_start {
+        finish_set(message, start);
+    }
+    let length = message.len() as u16;
+    message[2..4].copy_from_slice(&length.to_be_bytes());
+}
+
+/// Encodes the records of the flows in IPFIX messages, each with the number of data records it holds.
+/// The IPv4 records are followed by the IPv6 ones, each group in its own data sets, and the templates precede them
+/// in the first message if requested
+fn encode_messages(flows: &[CompletedFlow], boot_time: &DateTime<Utc>, send_templates: bool) -> Vec<(Vec<u8>, usize)> {
+    let mut records: Vec<(u16, Vec<u8>)> = Vec::new();
+    for flow in flows {
+        for record in FlowRecord::from_completed_flow(flow) {
+            let (template_id, fields) = if record.source.is_ipv4() { (IPV4_TEMPLATE_ID, &IPV4_FIELDS) } else { (IPV6_TEMPLATE_ID, &IPV6_FIELDS) };
+            let mut data = Vec::new();
+            for (field, _) in fields.iter() {
+                write_field(&mut data, *field, &record, flow, boot_time);
+            }
+            records.push((template_id, data));
+        }
+    }
+    records.sort_by_key(|(template_id, _)| *template_id);
+
+    let mut messages = Vec::new();
+    let mut message = start_message();
+    if send_templates {
+        write_template_set(&mut message);
+    }
+    let mut set: Option<(u16, usize)> = None;
+    let mut count = 0;
+    for (template_id, data) in records {
+        let new_set = set.is_none_or(|(id, _)| id != template_id);
+        if message.len() + data.len() + if new_set { 4 } else { 0 } > MAX_PACKET_SIZE && count > 0 {
+            finish_message(&mut message, set.map(|(_, start)| start));
+            messages.push((message, count));
+            message = start_message();
+            set = None;
+            count = 0;
+        }
+        if set.is_none_or(|(id, _)| id != template_id) {
+            if let Some((_, start)) = set {
+                finish_set(&mut message, start);
+            }
+            set = Some((template_id, message.len()));
+            message.extend_from_slice(&template_id.to_be_bytes());
+            message.extend_from_slice(&[0, 0]);
+        }
+        message.extend_from_slice(&data);
+        count += 1;
+    }
+    finish_message(&mut message, set.map(|(_, start)| start));
+    messages.push((message, count));
+    messages
+}
@@ -7,9 +7,18 @@ use pcap::{Active, Capture, Device};
 pub mod aggregator;
 pub mod parsed_packet;
 pub mod parser;
+pub mod pcapng;
 pub mod socket_listener;
 pub mod analyzer;
+pub mod metrics;
+pub mod netflow;
+pub mod ipfix;
+pub mod collector;
 pub mod report_writer;
+pub mod report_format;
+mod report_html;
+pub mod report_options;
+pub mod report_sink;
 pub mod report_entry;
 
 pub fn select_default() -> Capture<Active> {
@@ -1,5 +1,7 @@
 use std::{io, num::ParseIntError};
 use network_analyzer::analyzer::Analyzer;
+use network_analyzer::report_format::ReportFormat;
+use network_analyzer::report_options::{ReportOptions, SortKey};
 use network_analyzer::select_device;
 
 
@@ -32,15 +34,119 @@ fn main() {
             .read_line(&mut name_input)
             .expect("Failed to read line");
     let name_input = name_input.trim();
-    println!("Report will be saved in 'report/{name_input}.md'\n");
 
-    let a=Analyzer::new(&device_name, name_input, time as u64);
+    println!("\nChoose the report format: 1 - Markdown, 2 - CSV, 3 - JSON, 4 - NDJSON, 5 - HTML (leave empty for Markdown):");
+    let format = loop {
+        let mut format_input = String::new();
+        io::stdin()
+            .read_line(&mut format_input)
+            .expect("Failed to read line");
+        match format_input.trim() {
+            "" | "1" => break ReportFormat::Markdown,
+            "2" => break ReportFormat::Csv,
+            "3" => break ReportFormat::Json,
+            "4" => break ReportFormat::Ndjson,
+            "5" => break ReportFormat::Html,
+            _ => println!("Invalid choice: select either 1 (Markdown), 2 (CSV), 3 (JSON), 4 (NDJSON) or 5 (HTML). Please insert again:")
+        }
+    };
+    println!("Report will be saved in 'report/{name_input}.{}'\n", format.extension());
+
+    println!("Choose how to sort the report: 1 - Bytes, 2 - Packets, 3 - First seen, 4 - Last seen, 5 - Address (leave empty to not sort):");
+    let sort = loop {
+        let mut sort_input = String::new();
+        io::stdin()
+            .read_line(&mut sort_input)
+            .expect("Failed to read line");
+        match sort_input.trim() {
+            "" => break None,
+            "1" => break Some(SortKey::Bytes),
+            "2" => break Some(SortKey::Packets),
+            "3" => break Some(SortKey::FirstSeen),
+            "4" => break Some(SortKey::LastSeen),
+            "5" => break Some(SortKey::Address),
+            _ => println!("Invalid choice: select a number between 1 and 5 or leave empty. Please insert again:")
+        }
+    };
+
+    println!("\nInsert the maximum number of connections per table (leave empty to write all of them):");
+    let top = loop {
+        let mut top_input = String::new();
+        io::stdin()
+            .read_line(&mut top_input)
+            .expect("Failed to read line");
+        let top_input = top_input.trim();
+        if top_input.is_empty() {
+            break None;
+        }
+        match top_input.parse::<usize>() {
+            Ok(x) => break Some(x),
+            Err(_) => println!("The number of connections must be a number. Please insert again:")
+        }
+    };
+    println!();
+
+    println!("Insert a BPF capture filter (e.g. 'tcp port 443 or udp port 53') or leave empty to capture all the traffic:");
+    let a = loop {
+        let mut filter_input = String::new();
+        io::stdin()
+            .read_line(&mut filter_input)
+            .expect("Failed to read line");
+        let filter_input = filter_input.trim();
+        if filter_input.is_empty() {
+            break Analyzer::new(&device_name, name_input, time as u64);
+        }
+        match Analyzer::with_filter(&device_name, name_input, time as u64, filter_input) {
+            Ok(a) => {println!("Capture filter selected: {filter_input}\n");
+                            break a;},
+            Err(e) => println!("{e}. Please insert again:")
+        }
+    };
+    //the report writer is running: configure it before the next prompts, so that the first report is already in the chosen format and order
+    a.report_writer.set_format(format);
+    a.report_writer.set_options(ReportOptions { sort, top, ..Default::default() });
+
+    println!("Insert the UDP address where to collect NetFlow/IPFIX flows (e.g. '0.0.0.0:2055') or leave empty to not collect them:");
+    let _collector = loop {
+        let mut collector_input = String::new();
+        io::stdin()
+            .read_line(&mut collector_input)
+            .expect("Failed to read line");
+        let collector_input = collector_input.trim();
+        if collector_input.is_empty() {
+            break None;
+        }
+        match a.collect_flows(collector_input) {
+            Ok(collector) => {println!("Collecting flows on udp://{}\n", collector.get_address());
+                              break Some(collector);},
+            Err(e) => println!("{e}. Please insert again:")
+        }
+    };
+
+    println!("Insert the address where to serve the Prometheus metrics (e.g. '127.0.0.1:9184') or leave empty to not serve them:");
+    let _metrics = loop {
+        let mut metrics_input = String::new();
+        io::stdin()
+            .read_line(&mut metrics_input)
+            .expect("Failed to read line");
+        let metrics_input = metrics_input.trim();
+        if metrics_input.is_empty() {
+            break None;
+        }
+        match a.serve_metrics(metrics_input) {
+            Ok(metrics) => {println!("Metrics served on http://{}/metrics\n", metrics.get_address());
+                            break Some(metrics);},
+            Err(e) => println!("{e}. Please insert again:")
+        }
+    };
 
     loop {
         println!("Options");
         println!("1 - PAUSE");
         println!("2 - RESUME");
         println!("3 - EXIT");
+        println!("4 - FILTER");
+        println!("5 - WRITE REPORT");
         let mut input_line = String::new();
         io::stdin()
             .read_line(&mut input_line)
@@ -52,8 +158,27 @@ fn main() {
                 match number{
                     1 => a.pause(),
                     2 => a.resume(),
-                    3 => {println!("EXIT: Stopping Network Analyzer"); break;}
-                    _ => println!("Invalid choice: select either 1 (PAUSE), 2 (RESUME) or 3 (EXIT)")
+                    3 => {println!("EXIT: Stopping Network Analyzer");
+                            //the sinks that cannot write the final report have already been logged
+                            let _ = a.stop();
+                            break;}
+                    4 => {
+                        println!("Insert the new BPF capture filter or leave empty to remove it (current: {}):", a.get_filter().unwrap_or_else(|| String::from("none")));
+                        let mut filter_input = String::new();
+                        io::stdin()
+                            .read_line(&mut filter_input)
+                            .expect("Failed to read line");
+                        let filter_input = filter_input.trim();
+                        let filter = if filter_input.is_empty() { None } else { Some(filter_input) };
+                        match a.set_filter(filter) {
+                            Ok(_) => println!("FILTER: Capture filter updated"),
+                            Err(e) => println!("{e}, the current filter is kept")
+                        }
+                    }
+                    5 => if a.write_report().is_err() {
+                        println!("WRITE REPORT: The report could not be written to every output")
+                    },
+                    _ => println!("Invalid choice: select either 1 (PAUSE), 2 (RESUME), 3 (EXIT), 4 (FILTER) or 5 (WRITE REPORT)")
                 }
             },
             Err(_) => {println!("Option choice must be a number!")}
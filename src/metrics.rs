@@ -0,0 +1,213 @@
+use std::fmt::Write as FmtWrite;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::aggregator::Aggregator;
+use crate::parsed_packet::Protocol;
+use crate::parser::CaptureStats;
+use crate::report_format::protocol_name;
+use crate::report_options::{ReportOptions, SortKey};
+
+/// Content type of the Prometheus text exposition format
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Struct that serves the data of an [Aggregator] in the Prometheus text exposition format on `/metrics`,
+/// handling the requests in a separate thread until it is stopped or dropped
+///
+/// The following metrics are exposed:
+/// * `network_analyzer_protocol_packets_total`, `network_analyzer_protocol_bytes_total`: counters of the packets and bytes aggregated
+///   for each protocol (label `protocol`)
+/// * `network_analyzer_connection_packets`, `network_analyzer_connection_bytes`: gauges of the packets and bytes of
+///   the connections that exchanged the most bytes (labels `interface`, `source`, `destination`, `source_port`, `destination_port`,
+///   `protocol`, `icmp_type` and `icmp_code`, empty when not part of the aggregation key or not ICMP), they drop when a connection
+///   is removed by a timeout or evicted and a connection leaving the top has no series
+/// * `network_analyzer_connections`: gauge of the number of entries of the aggregated data
+/// * `network_analyzer_aggregated_packets_total`, `network_analyzer_aggregated_flow_records_total`, `network_analyzer_evicted_connections_total`:
+///   counters of the captured packets and of the flow records inserted by the [Aggregator] and of the entries evicted because the aggregated data was full
+///
+/// If the statistics of the capture are set with [MetricsServer::set_capture_stats] the health of the capture is exposed as well:
+/// * `network_analyzer_packets_parsed_total`, `network_analyzer_packets_skipped_total`: counters of the packets sent to the
+///   [Aggregator] and of the ones skipped by the parser
+/// * `network_analyzer_pcap_dropped_packets_total`, `network_analyzer_interface_dropped_packets_total`: counters of the packets
+///   dropped by libpcap and by the interface, only when reported by libpcap
+/// * `network_analyzer_channel_backlog`: gauge of the packets parsed but not yet inserted by the [Aggregator]
+///
+/// # Examples
+/// Basic usage:
+/// ```rust
+/// use Network_analyzer::aggregator::Aggregator;
+/// use Network_analyzer::metrics::MetricsServer;
+///
+/// let aggregator = Aggregator::new();
+/// let metrics = MetricsServer::new("127.0.0.1:9184", &aggregator).expect("cannot listen on the address");
+/// metrics.set_top(20);
+/// // scrape http://127.0.0.1:9184/metrics
+/// ```
+pub struct MetricsServer {
+    server: Arc<Server>,
+    address: SocketAddr,
+    top: Arc<Mutex<usize>>,
+    capture_stats: Arc<Mutex<Option<Arc<Mutex<CaptureStats>>>>>,
+}
+
+impl MetricsServer {
+    /// Creates a [MetricsServer] listening on the given address and a thread that answers the requests
+    /// # Arguments
+    /// * `address` - The address to listen on, e.g. `0.0.0.0:9184`, with port 0 an unused port is chosen
+    /// * `aggregator` - The [Aggregator] whose data is exposed
+    /// # Errors
+    /// If the server cannot listen on the address
+    /// # Remarks
+    /// By default the 10 connections that exchanged the most bytes are exposed, see [MetricsServer::set_top]
+    pub fn new(address: &str, aggregator: &Aggregator) -> io::Result<Self> {
+        let server = Arc::new(Server::http(address).map_err(io::Error::other)?);
+        let address = server.server_addr().to_ip()
+            .ok_or_else(|| io::Error::other("the metrics server is not listening on an IP address"))?;
+        let top = Arc::new(Mutex::new(10));
+        let capture_stats: Arc<Mutex<Option<Arc<Mutex<CaptureStats>>>>> = Arc::new(Mutex::new(None));
+
+        let server_clone = server.clone();
+        let top_clone = top.clone();
+        let capture_stats_clone = capture_stats.clone();
+        let aggregator = aggregator.clone();
+        std::thread::spawn(move || {
+            //the iterator ends when the server is unblocked by stop
+            for request in server_clone.incoming_requests() {
+                let top = *top_clone.lock().unwrap();
+                //copy the statistics so that the parser is not blocked while the metrics are encoded
+                let capture_stats = capture_stats_clone.lock().unwrap().as_ref().map(|capture_stats| capture_stats.lock().unwrap().clone());
+                //a client that disconnects early is not an error of the server
+                let _ = MetricsServer::respond(request, &aggregator, capture_stats.as_ref(), top);
+            }
+        });
+        Ok(MetricsServer { server, address, top, capture_stats })
+    }
+
+    /// Returns the address the [MetricsServer] is listening on
+    pub fn get_address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Sets the number of connections exposed, the ones that exchanged the most bytes
+    pub fn set_top(&self, top: usize) {
+        *self.top.lock().unwrap() = top;
+    }
+
+    /// Returns the number of connections exposed
+    pub fn get_top(&self) -> usize {
+        *self.top.lock().unwrap()
+    }
+
+    /// Sets the statistics of the capture exposed as health metrics, `None` to not expose them
+    pub fn set_capture_stats(&self, capture_stats: Option<Arc<Mutex<CaptureStats>>>) {
+        *self.capture_stats.lock().unwrap() = capture_stats;
+    }
+
+    /// Returns the statistics of the capture exposed as health metrics, if any
+    pub fn get_capture_stats(&self) -> Option<Arc<Mutex<CaptureStats>>> {
+        self.capture_stats.lock().unwrap().clone()
+    }
+
+    /// Stops answering the requests, the thread of the [MetricsServer] exits
+    pub fn stop(&self) {
+        self.server.unblock();
+    }
+
+    fn respond(request: Request, aggregator: &Aggregator, capture_stats: Option<&CaptureStats>, top: usize) -> io::Result<()> {
+        let path = request.url().split('?').next().unwrap_or_default();
+        if path != "/metrics" {
+            return request.respond(Response::from_string("Not Found\n").with_status_code(404));
+        }
+        if *request.method() != Method::Get && *request.method() != Method::Head {
+            return request.respond(Response::from_string("Method Not Allowed\n").with_status_code(405));
+        }
+        let header = Header::from_bytes("Content-Type", CONTENT_TYPE).expect("valid header");
+        request.respond(Response::from_string(encode(aggregator, capture_stats, top)).with_header(header))
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Returns the metrics in the Prometheus text exposition format
+fn encode(aggregator: &Aggregator, capture_stats: Option<&CaptureStats>, top: usize) -> String {
+    let mut output = String::new();
+    let protocols = aggregator.get_protocol_totals();
+    write_header(&mut output, "network_analyzer_protocol_packets_total", "counter", "Packets aggregated per protocol.");
+    for (ip_number, protocol) in protocols.iter() {
+        writeln!(output, "network_analyzer_protocol_packets_total{{protocol=\"{}\"}} {}", escape(&protocol_name(*ip_number)), protocol.packets).unwrap();
+    }
+    write_header(&mut output, "network_analyzer_protocol_bytes_total", "counter", "Bytes aggregated per protocol.");
+    for (ip_number, protocol) in protocols.iter() {
+        writeln!(output, "network_analyzer_protocol_bytes_total{{protocol=\"{}\"}} {}", escape(&protocol_name(*ip_number)), protocol.bytes).unwrap();
+    }
+
+    let aggregated_data = aggregator.get_aggregated_data();
+    let aggregated_data = aggregated_data.read().unwrap();
+    let options = ReportOptions { sort: Some(SortKey::Bytes), top: Some(top), ..Default::default() };
+    let connections: Vec<(String, usize, usize)> = options.select(&aggregated_data).into_iter().map(|(conn, data)| {
+        let port = |port: Option<u16>| port.map(|port| port.to_string()).unwrap_or_default();
+        //ICMP connections that differ by type or code only must not share their labels
+        let (icmp_type, icmp_code) = match conn.protocol {
+            Some(Protocol::Icmpv4 { icmp_type, code } | Protocol::Icmpv6 { icmp_type, code }) => (icmp_type.to_string(), code.to_string()),
+            _ => (String::new(), String::new()),
+        };
+        let labels = format!("interface=\"{}\",source=\"{}\",destination=\"{}\",source_port=\"{}\",destination_port=\"{}\",protocol=\"{}\",icmp_type=\"{}\",icmp_code=\"{}\"",
+                             escape(conn.interface.as_deref().unwrap_or_default()),
+                             escape(&conn.source_address().unwrap_or_default()),
+                             escape(&conn.destination_address().unwrap_or_default()),
+                             port(conn.source_port), port(conn.destination_port),
+                             escape(&conn.protocol.map(|protocol| protocol_name(protocol.ip_number())).unwrap_or_default()),
+                             icmp_type, icmp_code);
+        (labels, data.packets, data.size)
+    }).collect();
+    let entries = aggregated_data.len();
+    drop(aggregated_data);
+    write_header(&mut output, "network_analyzer_connection_packets", "gauge", "Packets of the connections that exchanged the most bytes.");
+    for (labels, packets, _) in connections.iter() {
+        writeln!(output, "network_analyzer_connection_packets{{{}}} {}", labels, packets).unwrap();
+    }
+    write_header(&mut output, "network_analyzer_connection_bytes", "gauge", "Bytes of the connections that exchanged the most bytes.");
+    for (labels, _, bytes) in connections.iter() {
+        writeln!(output, "network_analyzer_connection_bytes{{{}}} {}", labels, bytes).unwrap();
+    }
+
+    let received = aggregator.get_received_count();
+    write_metric(&mut output, "network_analyzer_connections", "gauge", "Entries of the aggregated data.", entries);
+    write_metric(&mut output, "network_analyzer_aggregated_packets_total", "counter", "Packets inserted into the aggregated data.", received);
+    write_metric(&mut output, "network_analyzer_aggregated_flow_records_total", "counter", "Flow records of the flow collectors inserted into the aggregated data.", aggregator.get_flow_record_count());
+    write_metric(&mut output, "network_analyzer_evicted_connections_total", "counter", "Entries evicted because the aggregated data was full.", aggregator.get_evicted_count());
+    if let Some(capture_stats) = capture_stats {
+        //the skipped packets are counted by the capture statistics but never reach the aggregator
+        let parsed = capture_stats.packets.saturating_sub(capture_stats.skipped);
+        write_metric(&mut output, "network_analyzer_packets_parsed_total", "counter", "Packets parsed and sent to the aggregator.", parsed);
+        write_metric(&mut output, "network_analyzer_packets_skipped_total", "counter", "Packets skipped because not IP or with invalid headers.", capture_stats.skipped);
+        if let Some(dropped) = capture_stats.dropped {
+            write_metric(&mut output, "network_analyzer_pcap_dropped_packets_total", "counter", "Packets dropped by libpcap.", dropped as usize);
+        }
+        if let Some(interface_dropped) = capture_stats.interface_dropped {
+            write_metric(&mut output, "network_analyzer_interface_dropped_packets_total", "counter", "Packets dropped by the interface.", interface_dropped as usize);
+        }
+        write_metric(&mut output, "network_analyzer_channel_backlog", "gauge", "Packets parsed but not yet inserted into the aggregated data.", parsed.saturating_sub(received));
+    }
+    output
+}
+
+fn write_header(output: &mut String, name: &str, metric_type: &str, help: &str) {
+    writeln!(output, "# HELP {} {}\n# TYPE {} {}", name, help, name, metric_type).unwrap();
+}
+
+fn write_metric(output: &mut String, name: &str, metric_type: &str, help: &str, value: usize) {
+    write_header(output, name, metric_type, help);
+    writeln!(output, "{} {}", name, value).unwrap();
+}
+
+/// Escapes a label value as required by the text exposition format
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
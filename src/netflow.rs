@@ -0,0 +1,431 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Condvar, Mutex};
+use chrono::{DateTime, Utc};
+
+use crate::aggregator::CompletedFlow;
+use crate::parsed_packet::Protocol;
+
+/// Enum describing the version of the NetFlow packets sent by a [NetflowExporter]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NetflowVersion {
+    /// NetFlow v5: fixed records of IPv4 flows, at most 30 per packet. IPv6 flows cannot be exported and are skipped
+    #[default]
+    V5,
+    /// NetFlow v9 (RFC 3954): records described by templates, one for IPv4 and one for IPv6 flows.
+    /// The templates are sent in the first packet and then every [TEMPLATE_REFRESH_PACKETS] packets
+    V9,
+}
+
+/// Number of packets after which a [NetflowExporter] sends the NetFlow v9 templates again,
+/// so that a collector started after the exporter learns them
+pub const TEMPLATE_REFRESH_PACKETS: usize = 20;
+
+/// Maximum size of a NetFlow v9 packet, to avoid the fragmentation of the datagrams
+pub(crate) const MAX_PACKET_SIZE: usize = 1400;
+
+const V5_MAX_RECORDS: usize = 30;
+const V9_TEMPLATE_FLOWSET_ID: u16 = 0;
+const IPV4_TEMPLATE_ID: u16 = 256;
+const IPV6_TEMPLATE_ID: u16 = 257;
+
+//field types of NetFlow v9, the same numbers are used by the information elements of IPFIX
+pub(crate) const IN_BYTES: u16 = 1;
+pub(crate) const IN_PKTS: u16 = 2;
+pub(crate) const PROTOCOL: u16 = 4;
+pub(crate) const L4_SRC_PORT: u16 = 7;
+pub(crate) const IPV4_SRC_ADDR: u16 = 8;
+pub(crate) const SRC_MASK: u16 = 9;
+pub(crate) const L4_DST_PORT: u16 = 11;
+pub(crate) const IPV4_DST_ADDR: u16 = 12;
+pub(crate) const DST_MASK: u16 = 13;
+pub(crate) const LAST_SWITCHED: u16 = 21;
+pub(crate) const FIRST_SWITCHED: u16 = 22;
+pub(crate) const IPV6_SRC_ADDR: u16 = 27;
+pub(crate) const IPV6_DST_ADDR: u16 = 28;
+pub(crate) const IPV6_SRC_MASK: u16 = 29;
+pub(crate) const IPV6_DST_MASK: u16 = 30;
+pub(crate) const ICMP_TYPE: u16 = 32;
+
+/// Fields of the NetFlow v9 template of the IPv4 flows, with their length in bytes
+const V9_IPV4_FIELDS: [(u16, u16); 12] = [
+    (IPV4_SRC_ADDR, 4), (IPV4_DST_ADDR, 4), (SRC_MASK, 1), (DST_MASK, 1), (L4_SRC_PORT, 2), (L4_DST_PORT, 2),
+    (PROTOCOL, 1), (ICMP_TYPE, 2), (IN_PKTS, 8), (IN_BYTES, 8), (FIRST_SWITCHED, 4), (LAST_SWITCHED, 4),
+];
+
+/// Fields of the NetFlow v9 template of the IPv6 flows, with their length in bytes
+const V9_IPV6_FIELDS: [(u16, u16); 12] = [
+    (IPV6_SRC_ADDR, 16), (IPV6_DST_ADDR, 16), (IPV6_SRC_MASK, 1), (IPV6_DST_MASK, 1), (L4_SRC_PORT, 2), (L4_DST_PORT, 2),
+    (PROTOCOL, 1), (ICMP_TYPE, 2), (IN_PKTS, 8), (IN_BYTES, 8), (FIRST_SWITCHED, 4), (LAST_SWITCHED, 4),
+];
+
+/// Struct holding the statistics of the flows sent by an exporter
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExportStats {
+    /// Number of datagrams sent to the collector
+    pub packets: usize,
+    /// Number of flow records sent to the collector
+    pub records: usize,
+    /// Number of flow records that cannot be represented in the export format, e.g. IPv6 flows in NetFlow v5
+    pub skipped: usize,
+    /// Number of datagrams that could not be sent
+    pub errors: usize,
+}
+
+/// Struct describing a unidirectional flow, as carried by a NetFlow or IPFIX record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FlowRecord {
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    pub source_prefix: u8,
+    pub destination_prefix: u8,
+    pub source_port: u16,
+    pub destination_port: u16,
+    pub protocol: u8,
+    /// ICMP type multiplied by 256 plus ICMP code, 0 for the other protocols
+    pub icmp_type_code: u16,
+    pub packets: u64,
+    pub bytes: u64,
+    pub first_timestamp: DateTime<Utc>,
+    pub last_timestamp: DateTime<Utc>,
+}
+
+impl FlowRecord {
+    /// Returns the records of a completed flow: one for the packets sent by the initiator
+    /// and one for the packets sent by the responder, if any.
+    /// The fields that are not part of the aggregation key are zero, e.g. the address `0.0.0.0` with prefix length 0
+    pub(crate) fn from_completed_flow(flow: &CompletedFlow) -> Vec<FlowRecord> {
+        let conn = &flow.connection;
+        let data = &flow.metadata;
+        let ipv6 = conn.source_ip.or(conn.destination_ip).is_some_and(|ip| ip.is_ipv6());
+        let unspecified = if ipv6 { IpAddr::V6(Ipv6Addr::UNSPECIFIED) } else { IpAddr::V4(Ipv4Addr::UNSPECIFIED) };
+        let address = |ip: Option<IpAddr>, prefix: Option<u8>| match ip {
+            Some(ip) => (ip, prefix.unwrap_or(if ip.is_ipv4() { 32 } else { 128 })),
+            None => (unspecified, 0),
+        };
+        let (source, source_prefix) = address(conn.source_ip, conn.source_prefix);
+        let (destination, destination_prefix) = address(conn.destination_ip, conn.destination_prefix);
+        let icmp_type_code = match conn.protocol {
+            Some(Protocol::Icmpv4 { icmp_type, code }) | Some(Protocol::Icmpv6 { icmp_type, code }) => (icmp_type as u16) << 8 | code as u16,
+            _ => 0,
+        };
+        let initiator = FlowRecord {
+            source,
+            destination,
+            source_prefix,
+            destination_prefix,
+            source_port: conn.source_port.unwrap_or(0),
+            destination_port: conn.destination_port.unwrap_or(0),
+            protocol: conn.protocol.map_or(0, |protocol| protocol.ip_number()),
+            icmp_type_code,
+            packets: data.initiator_packets as u64,
+            bytes: data.initiator_size as u64,
+            first_timestamp: data.first_timestamp,
+            last_timestamp: data.last_timestamp,
+        };
+        let mut records = Vec::new();
+        if data.responder_packets > 0 {
+            records.push(FlowRecord {
+                source: destination,
+                destination: source,
+                source_prefix: destination_prefix,
+                destination_prefix: source_prefix,
+                source_port: initiator.destination_port,
+                destination_port: initiator.source_port,
+                packets: data.responder_packets as u64,
+                bytes: data.responder_size as u64,
+                ..initiator.clone()
+            });
+        }
+        records.insert(0, initiator);
+        records
+    }
+
+    /// Writes the value of a field of the record, in network byte order
+    /// # Panics
+    /// If the field is not one of the fields that can be exported
+    pub(crate) fn write_field(&self, output: &mut Vec<u8>, field: u16, boot_time: &DateTime<Utc>) {
+        let address = |ip: IpAddr| match ip {
+            IpAddr::V4(ip) => ip.octets().to_vec(),
+            IpAddr::V6(ip) => ip.octets().to_vec(),
+        };
+        match field {
+            IN_BYTES => output.extend_from_slice(&self.bytes.to_be_bytes()),
+            IN_PKTS => output.extend_from_slice(&self.packets.to_be_bytes()),
+            PROTOCOL => output.push(self.protocol),
+            L4_SRC_PORT => output.extend_from_slice(&self.source_port.to_be_bytes()),
+            L4_DST_PORT => output.extend_from_slice(&self.destination_port.to_be_bytes()),
+            IPV4_SRC_ADDR | IPV6_SRC_ADDR => output.extend_from_slice(&address(self.source)),
+            IPV4_DST_ADDR | IPV6_DST_ADDR => output.extend_from_slice(&address(self.destination)),
+            SRC_MASK | IPV6_SRC_MASK => output.push(self.source_prefix),
+            DST_MASK | IPV6_DST_MASK => output.push(self.destination_prefix),
+            ICMP_TYPE => output.extend_from_slice(&self.icmp_type_code.to_be_bytes()),
+            FIRST_SWITCHED => output.extend_from_slice(&uptime(boot_time, &self.first_timestamp).to_be_bytes()),
+            LAST_SWITCHED => output.extend_from_slice(&uptime(boot_time, &self.last_timestamp).to_be_bytes()),
+            _ => panic!("field {} cannot be exported", field),
+        }
+    }
+}
+
+/// Returns the milliseconds elapsed from the boot time of the exporter to the given time, wrapping around as the
+/// system uptime of a router does, times before the boot time are 0
+pub(crate) fn uptime(boot_time: &DateTime<Utc>, time: &DateTime<Utc>) -> u32 {
+    (*time - *boot_time).num_milliseconds().max(0) as u32
+}
+
+/// Struct that sends the flows completed by an [Aggregator](crate::aggregator::Aggregator) to a NetFlow collector over UDP,
+/// in a separate thread, acting as a software flow probe
+///
+/// Each flow is exported as a record for the packets sent by the initiator and, for bidirectional aggregation, a record
+/// for the packets sent by the responder. The fields that are not part of the aggregation key (see
+/// [AggregationKey](crate::aggregator::AggregationKey)) are exported as zero.
+///
+/// # Examples
+/// Basic usage:
+/// ```rust
+/// use std::time::Duration;
+/// use Network_analyzer::aggregator::{Aggregator, AggregatorConfig};
+/// use Network_analyzer::netflow::{NetflowExporter, NetflowVersion};
+/// use Network_analyzer::parser::Parser;
+///
+/// let config = AggregatorConfig { idle_timeout: Some(Duration::from_secs(15)), active_timeout: Some(Duration::from_secs(1800)), ..Default::default() };
+/// let aggregator = Aggregator::with_config(config);
+/// let exporter = NetflowExporter::new("192.168.1.10:2055", NetflowVersion::V9, aggregator.subscribe_completed_flows()).unwrap();
+/// let parser = Parser::new("eth0", aggregator.get_sender());
+/// ```
+///
+/// # Remarks
+/// The first and last switched times of the records are relative to the creation of the exporter, that is the boot time
+/// reported to the collector: the packets captured before it (e.g. when reading a capture file) have time 0
+pub struct NetflowExporter {
+    collector: SocketAddr,
+    version: NetflowVersion,
+    stats: Arc<Mutex<ExportStats>>,
+    finished: Arc<Mutex<bool>>,
+    cv_finished: Arc<Condvar>,
+}
+
+impl NetflowExporter {
+    /// Creates a [NetflowExporter] and a thread that sends the flows received from the channel to the collector,
+    /// until all the senders of the channel have been dropped
+    /// # Arguments
+    /// * `collector` - The address of the collector, e.g. `192.168.1.10:2055`
+    /// * `version` - The [NetflowVersion] of the packets sent
+    /// * `completed` - The flows to export, e.g. the [Receiver] returned by
+    ///   [Aggregator::subscribe_completed_flows](crate::aggregator::Aggregator::subscribe_completed_flows)
+    /// # Errors
+    /// If the address of the collector cannot be resolved or the UDP socket cannot be created
+    pub fn new(collector: &str, version: NetflowVersion, completed: Receiver<CompletedFlow>) -> io::Result<Self> {
+        let collector = resolve(collector)?;
+        let socket = bind_for(&collector)?;
+        let stats = Arc::new(Mutex::new(ExportStats::default()));
+        let stats_clone = stats.clone();
+        let finished = Arc::new(Mutex::new(false));
+        let finished_clone = finished.clone();
+        let cv_finished = Arc::new(Condvar::new());
+        let cv_finished_clone = cv_finished.clone();
+
+        std::thread::spawn(move || {
+            let boot_time = Utc::now();
+            let mut flow_sequence: u32 = 0;
+            //NetFlow v9 counts the packets exported, sent or not, so that the collector can detect the lost ones
+            let mut packet_sequence: u32 = 0;
+            while let Ok(flow) = completed.recv() {
+                //export together the flows already waiting, to fill the packets
+                let mut records = FlowRecord::from_completed_flow(&flow);
+                for flow in completed.try_iter() {
+                    records.extend(FlowRecord::from_completed_flow(&flow));
+                }
+                let (packets, skipped) = match version {
+                    NetflowVersion::V5 => encode_v5(&records, &boot_time, &mut flow_sequence),
+                    NetflowVersion::V9 => (encode_v9(&records, &boot_time, &mut packet_sequence), 0),
+                };
+                let mut stats = stats_clone.lock().unwrap();
+                stats.skipped += skipped;
+                for (packet, count) in packets {
+                    match socket.send_to(&packet, collector) {
+                        Ok(_) => {
+                            stats.packets += 1;
+                            stats.records += count;
+                        },
+                        Err(e) => {
+                            stats.errors += 1;
+                            eprintln!("Error sending NetFlow packet to {}: {}", collector, e);
+                        },
+                    }
+                }
+            }
+            *finished_clone.lock().unwrap() = true;
+            cv_finished_clone.notify_all();
+        });
+        Ok(NetflowExporter { collector, version, stats, finished, cv_finished })
+    }
+
+    /// Returns the address of the collector
+    pub fn get_collector(&self) -> SocketAddr {
+        self.collector
+    }
+
+    /// Returns the [NetflowVersion] of the packets sent
+    pub fn get_version(&self) -> NetflowVersion {
+        self.version
+    }
+
+    /// Returns the statistics of the flows exported so far
+    pub fn get_export_stats(&self) -> ExportStats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Blocks until all the senders of the channel have been dropped and every flow received has been exported
+    /// # Returns
+    /// The statistics of the flows exported
+    pub fn wait_for_completion(&self) -> ExportStats {
+        let finished = self.finished.lock().unwrap();
+        let _finished = self.cv_finished.wait_while(finished, |finished| !*finished).unwrap();
+        self.get_export_stats()
+    }
+}
+
+/// Returns the first address the given host and port resolve to
+pub(crate) fn resolve(address: &str) -> io::Result<SocketAddr> {
+    address.to_socket_addrs()?.next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("cannot resolve '{}'", address)))
+}
+
+/// Returns a UDP socket bound to an unused port, of the same family as the given address
+pub(crate) fn bind_for(address: &SocketAddr) -> io::Result<UdpSocket> {
+    let local: SocketAddr = if address.is_ipv4() { (Ipv4Addr::UNSPECIFIED, 0).into() } else { (Ipv6Addr::UNSPECIFIED, 0).into() };
+    UdpSocket::bind(local)
+}
+
+/// Returns the seconds and nanoseconds since the epoch of the given time
+pub(crate) fn unix_time(time: &DateTime<Utc>) -> (u32, u32) {
+    (time.timestamp() as u32, time.timestamp_subsec_nanos())
+}
+
+/// Encodes the IPv4 records in NetFlow v5 packets, each with the number of records it holds
+/// # Returns
+/// The packets and the number of records skipped because they are IPv6 flows
+fn encode_v5(records: &[FlowRecord], boot_time: &DateTime<Utc>, flow_sequence: &mut u32) -> (Vec<(Vec<u8>, usize)>, usize) {
+    let ipv4: Vec<&FlowRecord> = records.iter().filter(|record| record.source.is_ipv4()).collect();
+    let skipped = records.len() - ipv4.len();
+    let now = Utc::now();
+    let (unix_secs, unix_nsecs) = unix_time(&now);
+    let packets = ipv4.chunks(V5_MAX_RECORDS).map(|chunk| {
+        let mut packet = Vec::with_capacity(24 + 48 * chunk.len());
+        packet.extend_from_slice(&5u16.to_be_bytes());
+        packet.extend_from_slice(&(chunk.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&uptime(boot_time, &now).to_be_bytes());
+        packet.extend_from_slice(&unix_secs.to_be_bytes());
+        packet.extend_from_slice(&unix_nsecs.to_be_bytes());
+        packet.extend_from_slice(&flow_sequence.to_be_bytes());
+        //engine type, engine id and sampling interval
+        packet.extend_from_slice(&[0; 4]);
+        for record in chunk {
+            record.write_field(&mut packet, IPV4_SRC_ADDR, boot_time);
+            record.write_field(&mut packet, IPV4_DST_ADDR, boot_time);
+            //next hop, input and output interface indexes
+            packet.extend_from_slice(&[0; 8]);
+            packet.extend_from_slice(&(record.packets.min(u32::MAX as u64) as u32).to_be_bytes());
+            packet.extend_from_slice(&(record.bytes.min(u32::MAX as u64) as u32).to_be_bytes());
+            record.write_field(&mut packet, FIRST_SWITCHED, boot_time);
+            record.write_field(&mut packet, LAST_SWITCHED, boot_time);
+            if record.protocol == 1 || record.protocol == 58 {
+                //ICMP type and code are carried by the destination port
+                packet.extend_from_slice(&[0, 0]);
+                record.write_field(&mut packet, ICMP_TYPE, boot_time);
+            } else {
+                record.write_field(&mut packet, L4_SRC_PORT, boot_time);
+                record.write_field(&mut packet, L4_DST_PORT, boot_time);
+            }
+            //padding and TCP flags, not tracked by the aggregator
+            packet.extend_from_slice(&[0, 0]);
+            record.write_field(&mut packet, PROTOCOL, boot_time);
+            //type of service, source and destination AS
+            packet.extend_from_slice(&[0; 5]);
+            record.write_field(&mut packet, SRC_MASK, boot_time);
+            record.write_field(&mut packet, DST_MASK, boot_time);
+            packet.extend_from_slice(&[0, 0]);
+        }
+        *flow_sequence = flow_sequence.wrapping_add(chunk.len() as u32);
+        (packet, chunk.len())
+    }).collect();
+    (packets, skipped)
+}
+
+/// Appends a template set to the packet: a header with the given set id, then for each template its id,
+/// the number of fields and the type and length of each field
+pub(crate) fn write_template_set(packet: &mut Vec<u8>, set_id: u16, templates: &[(u16, &[(u16, u16)])]) {
+    let start = packet.len();
+    packet.extend_from_slice(&set_id.to_be_bytes());
+    packet.extend_from_slice(&[0, 0]);
+    for (template_id, fields) in templates {
+        packet.extend_from_slice(&template_id.to_be_bytes());
+        packet.extend_from_slice(&(fields.len() as u16).to_be_bytes());
+        for (field, length) in fields.iter() {
+            packet.extend_from_slice(&field.to_be_bytes());
+            packet.extend_from_slice(&length.to_be_bytes());
+        }
+    }
+    let length = (packet.len() - start) as u16;
+    packet[start + 2..start + 4].copy_from_slice(&length.to_be_bytes());
+}
+
+/// Appends a data set with the given records to the packet, padded to a multiple of 4 bytes
+pub(crate) fn write_data_set(packet: &mut Vec<u8>, template_id: u16, fields: &[(u16, u16)], records: &[&FlowRecord], boot_time: &DateTime<Utc>) {
+    let start = packet.len();
+    packet.extend_from_slice(&template_id.to_be_bytes());
+    packet.extend_from_slice(&[0, 0]);
+    for record in records {
+        for (field, _) in fields {
+            record.write_field(packet, *field, boot_time);
+        }
+    }
+    packet.resize(start + (packet.len() - start).div_ceil(4) * 4, 0);
+    let length = (packet.len() - start) as u16;
+    packet[start + 2..start + 4].copy_from_slice(&length.to_be_bytes());
+}
+
+/// Returns the length in bytes of a record of a template
+pub(crate) fn record_length(fields: &[(u16, u16)]) -> usize {
+    fields.iter().map(|(_, length)| *length as usize).sum()
+}
+
+/// Encodes the records in NetFlow v9 packets, each with the number of flow records it holds.
+/// Every packet holds the records of a single address family, preceded by the templates in the packets whose
+/// sequence number is a multiple of [TEMPLATE_REFRESH_PACKETS].
+/// `packet_sequence` is the sequence number of the next packet, it is advanced for every packet encoded
+fn encode_v9(records: &[FlowRecord], boot_time: &DateTime<Utc>, packet_sequence: &mut u32) -> Vec<(Vec<u8>, usize)> {
+    let now = Utc::now();
+    let mut packets = Vec::new();
+    for (template_id, fields) in [(IPV4_TEMPLATE_ID, &V9_IPV4_FIELDS), (IPV6_TEMPLATE_ID, &V9_IPV6_FIELDS)] {
+        let family: Vec<&FlowRecord> = records.iter().filter(|record| record.source.is_ipv4() == (template_id == IPV4_TEMPLATE_ID)).collect();
+        let mut remaining = family.as_slice();
+        while !remaining.is_empty() {
+            let sequence = *packet_sequence;
+            *packet_sequence = packet_sequence.wrapping_add(1);
+            let mut packet = Vec::with_capacity(MAX_PACKET_SIZE);
+            packet.extend_from_slice(&9u16.to_be_bytes());
+            //the count is written once the records are known
+            packet.extend_from_slice(&[0, 0]);
+            packet.extend_from_slice(&uptime(boot_time, &now).to_be_bytes());
+            packet.extend_from_slice(&unix_time(&now).0.to_be_bytes());
+            packet.extend_from_slice(&sequence.to_be_bytes());
+            //source id
+            packet.extend_from_slice(&[0; 4]);
+            let mut count = 0;
+            if (sequence as usize).is_multiple_of(TEMPLATE_REFRESH_PACKETS) {
+                write_template_set(&mut packet, V9_TEMPLATE_FLOWSET_ID, &[(IPV4_TEMPLATE_ID, &V9_IPV4_FIELDS), (IPV6_TEMPLATE_ID, &V9_IPV6_FIELDS)]);
+                count += 2;
+            }
+            let fitting = ((MAX_PACKET_SIZE - packet.len() - 4) / record_length(fields)).min(remaining.len());
+            let (chunk, rest) = remaining.split_at(fitting);
+            write_data_set(&mut packet, template_id, fields, chunk, boot_time);
+            packet[2..4].copy_from_slice(&((count + chunk.len()) as u16).to_be_bytes());
+            packets.push((packet, chunk.len()));
+            remaining = rest;
+        }
+    }
+    packets
+}
@@ -1,22 +1,120 @@
+use core::fmt;
+use std::fmt::Formatter;
+use std::net::IpAddr;
+use chrono::{DateTime, Utc};
+
+/// Transport protocol carried by an IP packet.
+/// ICMP messages keep their type and code, protocols the parser does not decode keep their IP protocol number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+    Icmpv4 { icmp_type: u8, code: u8 },
+    Icmpv6 { icmp_type: u8, code: u8 },
+    Other(u8),
+}
+
+/// ICMP type of the messages whose header is not available (e.g. non-first fragments or truncated headers),
+/// reserved in both ICMPv4 and ICMPv6 so that it is reported as an unknown type
+pub const UNKNOWN_ICMP_TYPE: u8 = 255;
+
+impl Protocol {
+    /// Returns the protocol identified by an IP protocol number when the transport header is not available,
+    /// ICMP messages get the [UNKNOWN_ICMP_TYPE] since their type is unknown
+    pub fn from_ip_number(number: u8) -> Protocol {
+        match number {
+            6 => Protocol::Tcp,
+            17 => Protocol::Udp,
+            1 => Protocol::Icmpv4 { icmp_type: UNKNOWN_ICMP_TYPE, code: 0 },
+            58 => Protocol::Icmpv6 { icmp_type: UNKNOWN_ICMP_TYPE, code: 0 },
+            _ => Protocol::Other(number),
+        }
+    }
+
+    /// Returns the IP protocol number (IPv4 protocol field or IPv6 next header) of the protocol
+    pub fn ip_number(&self) -> u8 {
+        match self {
+            Protocol::Tcp => 6,
+            Protocol::Udp => 17,
+            Protocol::Icmpv4 { .. } => 1,
+            Protocol::Icmpv6 { .. } => 58,
+            Protocol::Other(number) => *number,
+        }
+    }
+}
+
+/// Display implementation for the Protocol enum.
+/// It produces the textual form used in the reports, e.g. `TCP` or `ICMPv4: Echo Request`.
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            Protocol::Tcp => f.pad("TCP"),
+            Protocol::Udp => f.pad("UDP"),
+            Protocol::Icmpv4 { icmp_type, .. } => f.pad(match icmp_type {
+                0 => "ICMPv4: Echo Reply",
+                3 => "ICMPv4: Destination Unreachable",
+                5 => "ICMPv4: Redirect",
+                8 => "ICMPv4: Echo Request",
+                11 => "ICMPv4: Time Exceeded",
+                12 => "ICMPv4: Parameter Problem",
+                13 => "ICMPv4: Timestamp Request",
+                14 => "ICMPv4: Timestamp Reply",
+                _ => "ICMPv4: Type Unknown",
+            }),
+            Protocol::Icmpv6 { icmp_type, .. } => f.pad(match icmp_type {
+                1 => "ICMPv6: Destination Unreachable",
+                2 => "ICMPv6: Packet Too Big",
+                3 => "ICMPv6: Time Exceeded",
+                4 => "ICMPv6: Parameter Problem",
+                128 => "ICMPv6: Echo Request",
+                129 => "ICMPv6: Echo Reply",
+                _ => "ICMPv6: Type Unknown",
+            }),
+            Protocol::Other(number) => f.pad(&format!("IP protocol {}", number)),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ParsedPacket {
-    pub timestamp: String,
-    pub source_ip: String,
-    pub destination_ip: String,
-    pub source_port: Option<usize>,
-    pub destination_port: Option<usize>,
-    pub protocol: String,
+    pub timestamp: DateTime<Utc>,
+    pub source_ip: IpAddr,
+    pub destination_ip: IpAddr,
+    pub source_port: Option<u16>,
+    pub destination_port: Option<u16>,
+    pub protocol: Protocol,
     pub size: usize,
+    pub interface: Option<String>,
+    /// Flags byte of the TCP header (CWR, ECE, URG, ACK, PSH, RST, SYN, FIN), `None` for non TCP packets
+    /// or when the TCP header was not captured
+    pub tcp_flags: Option<u8>,
+    /// `Some` if the packet stands for a flow record received by a [FlowCollector](crate::collector::FlowCollector):
+    /// `size` is then the number of bytes of the flow and `timestamp` the time of its last packet
+    pub flow: Option<FlowSummary>,
+}
+
+/// Struct describing the packets of a flow record carried by a [ParsedPacket]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlowSummary {
+    /// Number of packets of the flow
+    pub packets: usize,
+    /// Timestamp of the first packet of the flow
+    pub first_timestamp: DateTime<Utc>,
 }
 
+/// SYN flag of the TCP header
+pub const TCP_SYN: u8 = 0x02;
+/// ACK flag of the TCP header
+pub const TCP_ACK: u8 = 0x10;
+
 impl ParsedPacket {
     pub fn new(
-        timestamp: String,
-        source_ip: String,
-        destination_ip: String,
-        source_port: Option<usize>,
-        destination_port:Option<usize>,
-        protocol: String,
+        timestamp: DateTime<Utc>,
+        source_ip: IpAddr,
+        destination_ip: IpAddr,
+        source_port: Option<u16>,
+        destination_port:Option<u16>,
+        protocol: Protocol,
         size: usize,
     ) -> Self {
         ParsedPacket {
@@ -27,6 +125,21 @@ impl ParsedPacket {
             destination_port,
             protocol,
             size,
+            interface: None,
+            tcp_flags: None,
+            flow: None,
         }
     }
+
+    /// Sets the capture interface the packet was read from
+    pub fn with_interface(mut self, interface: Option<String>) -> Self {
+        self.interface = interface;
+        self
+    }
+
+    /// Sets the flags byte of the TCP header
+    pub fn with_tcp_flags(mut self, tcp_flags: Option<u8>) -> Self {
+        self.tcp_flags = tcp_flags;
+        self
+    }
 }
\ No newline at end of file
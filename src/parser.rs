@@ -1,11 +1,20 @@
+use core::fmt;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Formatter;
+use std::fs::File;
+use std::io::{BufReader, Read};
 use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{Sender};
-use chrono::{NaiveDateTime, Local, FixedOffset, TimeZone};
-use etherparse::{Icmpv4Type, Icmpv6Type, PacketHeaders};
-use etherparse::IpHeader::{Version4, Version6};
-use etherparse::TransportHeader::{Icmpv4, Icmpv6, Tcp, Udp};
-use pcap::{Capture, Packet};
-use crate::parsed_packet::ParsedPacket;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, TimeZone, Utc};
+use etherparse::PacketHeaders;
+use etherparse::IpHeader::Version4;
+use etherparse::TransportHeader::{Icmpv4, Tcp, Udp};
+use pcap::{Activated, BpfProgram, Capture, Linktype, Stat};
+use crate::parsed_packet::{ParsedPacket, Protocol};
+use crate::pcapng::{PcapngReader, SECTION_HEADER_BLOCK};
 
 ///enum to indicate the state to be assumed by the parsing thread
 #[derive(PartialEq,Debug)]
@@ -15,6 +24,226 @@ enum Command {
     EXIT
 }
 
+/// Read timeout of live devices in milliseconds, so that the parsing thread regularly handles commands
+/// and filter changes even when no packet arrives
+const READ_TIMEOUT_MS: i32 = 100;
+
+const LINKTYPE_ETHERNET: u16 = 1;
+const DLT_RAW: u16 = 12;
+const LINKTYPE_RAW: u16 = 101;
+const LINKTYPE_IPV4: u16 = 228;
+const LINKTYPE_IPV6: u16 = 229;
+
+const ETHER_TYPE_VLAN: u16 = 0x8100;
+const ETHER_TYPE_QINQ: u16 = 0x88A8;
+const ETHER_TYPE_IPV6: u16 = 0x86DD;
+
+const IPV6_HOP_BY_HOP: u8 = 0;
+const IPV6_ROUTING: u8 = 43;
+const IPV6_FRAGMENT: u8 = 44;
+const IP_AUTHENTICATION: u8 = 51;
+const IPV6_DESTINATION_OPTIONS: u8 = 60;
+
+/// Error returned when a BPF capture filter expression cannot be compiled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterError {
+    pub filter: String,
+    pub reason: String,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid capture filter '{}': {}", self.filter, self.reason)
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+/// Struct holding the number of packets and bytes of a protocol in [CaptureStats]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProtocolStats {
+    pub packets: usize,
+    pub bytes: usize,
+}
+
+/// Struct holding the statistics of the packets read by a [Parser], to judge the completeness of the aggregated data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureStats {
+    /// The name of the device or the path of the capture file read
+    pub device: String,
+    /// The time the [Parser] started reading packets
+    pub start_time: DateTime<Utc>,
+    /// The time the [Parser] stopped reading packets, `None` while it is running
+    pub end_time: Option<DateTime<Utc>>,
+    /// Number of packets read that match the capture filter, including the skipped ones
+    pub packets: usize,
+    /// Number of bytes of the packets read, as their original length
+    pub bytes: usize,
+    /// Number of packets read that were not forwarded because they are not IP packets or their headers could not be parsed
+    pub skipped: usize,
+    /// Number of packets dropped because they were not read fast enough, as reported by libpcap,
+    /// `None` if not available (e.g. when reading a capture file)
+    pub dropped: Option<u32>,
+    /// Number of packets dropped by the network interface or its driver, as reported by libpcap, `None` if not available
+    pub interface_dropped: Option<u32>,
+    /// Packets and bytes forwarded for each IP protocol number
+    pub protocols: BTreeMap<u8, ProtocolStats>,
+    /// The error that stopped the [Parser] before the end of the capture, e.g. a truncated capture file,
+    /// `None` while it is running or if it stopped normally
+    pub error: Option<String>,
+}
+
+impl CaptureStats {
+    /// Creates the statistics of a capture that starts now
+    /// # Arguments
+    /// * `device` - The name of the device or the path of the capture file read
+    pub fn new(device: &str) -> Self {
+        CaptureStats {
+            device: device.to_string(),
+            start_time: Utc::now(),
+            end_time: None,
+            packets: 0,
+            bytes: 0,
+            skipped: 0,
+            dropped: None,
+            interface_dropped: None,
+            protocols: BTreeMap::new(),
+            error: None,
+        }
+    }
+
+    /// Returns the time elapsed from the start of the capture to its end, or to now if the capture is running
+    pub fn elapsed(&self) -> Duration {
+        (self.end_time.unwrap_or_else(Utc::now) - self.start_time).to_std().unwrap_or_default()
+    }
+
+    fn record_parsed(&mut self, packet: &ParsedPacket) {
+        self.packets += 1;
+        self.bytes += packet.size;
+        let protocol = self.protocols.entry(packet.protocol.ip_number()).or_default();
+        protocol.packets += 1;
+        protocol.bytes += packet.size;
+    }
+
+    fn record_skipped(&mut self, size: usize) {
+        self.packets += 1;
+        self.bytes += size;
+        self.skipped += 1;
+    }
+
+    fn record_pcap_stats(&mut self, stat: Stat) {
+        self.dropped = Some(stat.dropped);
+        self.interface_dropped = Some(stat.if_dropped);
+    }
+}
+
+///enum to indicate the outcome of reading a packet from a [PacketSource]
+enum ReadPacket {
+    /// Packet parsed into a [ParsedPacket]
+    Parsed(ParsedPacket),
+    /// Packet not valid for parsing (e.g. not an IP packet), with its original length
+    Skipped(usize),
+    /// Packet not matching the capture filter
+    Filtered,
+    /// No packet arrived from a live device within the read timeout
+    Timeout,
+}
+
+///enum to indicate where the parsing thread reads its packets from
+enum PacketSource {
+    /// Live device or classic pcap file read through libpcap, with the name of the device if any
+    Pcap(Capture<dyn Activated>, Option<String>),
+    /// pcapng file read block by block to keep track of the interface of each packet,
+    /// the filter is compiled lazily for each link layer type found in the file
+    Pcapng {
+        reader: PcapngReader<BufReader<File>>,
+        filter: Option<String>,
+        programs: HashMap<u16, Option<BpfProgram>>,
+    },
+}
+
+impl PacketSource {
+    /// Reads and parses the next packet of the source
+    /// # Returns
+    /// `None` if no more packets can be read
+    /// # Errors
+    /// If the device fails or the capture file is truncated or malformed
+    fn next_parsed(&mut self) -> Result<Option<ReadPacket>, String> {
+        match self {
+            PacketSource::Pcap(cap, device) => {
+                let linktype = cap.get_datalink().0 as u16;
+                match cap.next_packet() {
+                    Ok(packet) => {
+                        let timestamp = Utc.timestamp_opt(packet.header.ts.tv_sec as i64, packet.header.ts.tv_usec as u32 * 1000).single();
+                        let parsed = timestamp.and_then(|ts| Parser::parse_packet(packet.data, linktype, ts, packet.header.len as usize, device.clone()));
+                        Ok(Some(parsed.map_or(ReadPacket::Skipped(packet.header.len as usize), ReadPacket::Parsed)))
+                    },
+                    Err(pcap::Error::TimeoutExpired) => Ok(Some(ReadPacket::Timeout)),
+                    Err(pcap::Error::NoMorePackets) => Ok(None),
+                    Err(e) => Err(e.to_string()),
+                }
+            },
+            PacketSource::Pcapng { reader, filter, programs } => match reader.next_packet() {
+                Ok(Some(packet)) => {
+                    //the reader only returns packets whose interface has been declared
+                    let interface = reader.get_interface(packet.interface_id)
+                        .ok_or_else(|| format!("packet of undeclared interface {}", packet.interface_id))?;
+                    if let Some(filter) = filter {
+                        let program = programs.entry(interface.linktype)
+                            .or_insert_with(|| Capture::dead(Linktype(interface.linktype as i32)).ok()?.compile(filter, true).ok());
+                        match program {
+                            Some(program) if program.filter(&packet.data) => {},
+                            //filtered out, or filter not applicable to this link layer type
+                            _ => return Ok(Some(ReadPacket::Filtered)),
+                        }
+                    }
+                    let name = interface.name.clone().unwrap_or_else(|| format!("if{}", packet.interface_id));
+                    let timestamp = Utc.timestamp_opt(packet.ts_sec, packet.ts_nsec).single();
+                    let parsed = timestamp.and_then(|ts| Parser::parse_packet(&packet.data, interface.linktype, ts, packet.len as usize, Some(name)));
+                    Ok(Some(parsed.map_or(ReadPacket::Skipped(packet.len as usize), ReadPacket::Parsed)))
+                },
+                Ok(None) => Ok(None),
+                Err(e) => Err(e.to_string()),
+            },
+        }
+    }
+
+    /// Returns the statistics of libpcap, if available (they are not for capture files)
+    fn pcap_stats(&mut self) -> Option<Stat> {
+        match self {
+            PacketSource::Pcap(cap, _) => cap.stats().ok(),
+            PacketSource::Pcapng { .. } => None,
+        }
+    }
+
+    /// Returns the link layer type used to validate filters for this source
+    fn get_linktype(&self) -> u16 {
+        match self {
+            PacketSource::Pcap(cap, _) => cap.get_datalink().0 as u16,
+            PacketSource::Pcapng { .. } => LINKTYPE_ETHERNET,
+        }
+    }
+
+    /// Applies the given BPF filter to the source, `None` removes the current filter
+    fn set_filter(&mut self, new_filter: Option<&str>) -> Result<(), FilterError> {
+        match self {
+            PacketSource::Pcap(cap, _) => {
+                //the empty expression accepts every packet
+                let expression = new_filter.unwrap_or("");
+                cap.filter(expression, true).map_err(|e| FilterError { filter: expression.to_string(), reason: e.to_string() })
+            },
+            PacketSource::Pcapng { filter, programs, .. } => {
+                if let Some(expression) = new_filter {
+                    Parser::validate_filter(expression, LINKTYPE_ETHERNET)?;
+                }
+                *filter = new_filter.map(|x| x.to_string());
+                programs.clear();
+                Ok(())
+            },
+        }
+    }
+}
+
 /// Struct to handle parsing from pcap Packets into [ParsedPacket] from a network device and sends them through a channel
 ///
 /// # Examples
@@ -54,7 +283,14 @@ enum Command {
 /// Each [Parser] runs in a separate thread, so you can create multiple [Parser] listening to multiple devices simultaneously
 pub struct Parser{
     cmd:Arc<Mutex<Command>>,
-    cv:Arc<Condvar>
+    cv:Arc<Condvar>,
+    finished:Arc<Mutex<Option<usize>>>,
+    cv_finished:Arc<Condvar>,
+    filter:Arc<Mutex<Option<String>>>,
+    filter_changed:Arc<AtomicBool>,
+    filter_applied:Arc<Condvar>,
+    capture_stats:Arc<Mutex<CaptureStats>>,
+    linktype:u16
 }
 impl Parser{
     /// Creates a new Parser that receives pcap Packets through a channel and forwards ParsedPackets to the given Sender
@@ -77,53 +313,254 @@ impl Parser{
     /// This function spawns a new thread that will run forever until the Parser is stopped with the `stop_iter_cap()` function
     /// or is dropped
     pub fn new(device: &str, aggregator_tx: Sender<ParsedPacket>) -> Parser {
+        Self::spawn(Self::open_device(device), device, None, aggregator_tx)
+    }
+
+    /// Creates a new Parser listening to a network device that only forwards the packets matching a BPF filter
+    /// # Arguments
+    /// * `device` - The name of device to listen to
+    /// * `filter` - The BPF filter expression, e.g. `tcp port 443 or udp port 53`
+    /// * `aggregator_tx` - The Sender to forward the parsed packets to, is intended to correspond to a Receiver in an Aggregator
+    /// # Example
+    /// Basic usage:
+    /// ```rust
+    /// use std::sync::mpsc::{channel, Sender};
+    /// use Network_analyzer::parser::Parser;
+    /// let (tx, rx) = channel();
+    /// // I am creating a new parser listening only to the HTTPS and DNS traffic of device "eth0"
+    /// let parser = Parser::with_filter("eth0", "tcp port 443 or udp port 53", tx).expect("invalid filter");
+    /// ```
+    /// # Errors
+    /// Returns a [FilterError] if the filter expression cannot be compiled
+    /// # Panics
+    /// Panics if it fails to open the device with the given name
+    pub fn with_filter(device: &str, filter: &str, aggregator_tx: Sender<ParsedPacket>) -> Result<Parser, FilterError> {
+        let mut source = Self::open_device(device);
+        source.set_filter(Some(filter))?;
+        Ok(Self::spawn(source, device, Some(filter.to_string()), aggregator_tx))
+    }
+
+    /// Creates a new Parser that reads the pcap Packets stored in a capture file and forwards ParsedPackets to the given Sender
+    /// # Arguments
+    /// * `path` - The path of the `.pcap` or `.pcapng` file to read
+    /// * `aggregator_tx` - The Sender to forward the parsed packets to, is intended to correspond to a Receiver in an Aggregator
+    /// # Example
+    /// Basic usage:
+    /// ```rust
+    /// use std::sync::mpsc::{channel, Sender};
+    /// use Network_analyzer::parser::Parser;
+    /// let (tx, rx) = channel();
+    /// // I am creating a new parser reading the file "capture.pcap" and sending the parsed packets to the channel I just created
+    /// let parser = Parser::from_file("capture.pcap", tx);
+    /// // I wait for the whole file to be parsed
+    /// let parsed = parser.wait_for_completion();
+    /// ```
+    /// # Panics
+    /// Panics if it fails to open the file with the given path
+    /// # Remarks
+    /// This function spawns a new thread that ends when all the packets of the file have been read,
+    /// use `wait_for_completion()` to know when this happens.
+    /// pcapng files are recognized by their magic number and read without libpcap, so that each [ParsedPacket]
+    /// carries the name of the interface it was captured on
+    pub fn from_file(path: &str, aggregator_tx: Sender<ParsedPacket>) -> Parser {
+        Self::spawn(Self::open_file(path), path, None, aggregator_tx)
+    }
 
-        let mut cap = Capture::from_device(device).expect("ERR: no such device found")
+    /// Creates a new Parser reading a capture file that only forwards the packets matching a BPF filter
+    /// # Arguments
+    /// * `path` - The path of the `.pcap` or `.pcapng` file to read
+    /// * `filter` - The BPF filter expression, e.g. `tcp port 443 or udp port 53`
+    /// * `aggregator_tx` - The Sender to forward the parsed packets to, is intended to correspond to a Receiver in an Aggregator
+    /// # Errors
+    /// Returns a [FilterError] if the filter expression cannot be compiled
+    /// # Panics
+    /// Panics if it fails to open the file with the given path
+    pub fn from_file_with_filter(path: &str, filter: &str, aggregator_tx: Sender<ParsedPacket>) -> Result<Parser, FilterError> {
+        let mut source = Self::open_file(path);
+        source.set_filter(Some(filter))?;
+        Ok(Self::spawn(source, path, Some(filter.to_string()), aggregator_tx))
+    }
+
+    /// Opens the device with the given name in promiscuous mode
+    fn open_device(device: &str) -> PacketSource {
+        let cap = Capture::from_device(device).expect("ERR: no such device found")
             .promisc(true)
+            .timeout(READ_TIMEOUT_MS)
             .open().expect("Failed to open device");
+        PacketSource::Pcap(cap.into(), Some(device.to_string()))
+    }
+
+    /// Opens the capture file at the given path, choosing the reader from its magic number
+    fn open_file(path: &str) -> PacketSource {
+        let mut magic = [0u8; 4];
+        File::open(path).and_then(|mut f| f.read_exact(&mut magic)).expect("ERR: failed to open capture file");
+        if u32::from_le_bytes(magic) == SECTION_HEADER_BLOCK {
+            let reader = PcapngReader::open(path).expect("ERR: failed to open pcapng file");
+            PacketSource::Pcapng { reader, filter: None, programs: HashMap::new() }
+        } else {
+            let cap = Capture::from_file(path).expect("ERR: failed to open capture file");
+            PacketSource::Pcap(cap.into(), None)
+        }
+    }
+
+    /// Spawns the parsing thread reading from the given source, used by both the live and the offline constructors
+    fn spawn(mut source: PacketSource, device: &str, filter: Option<String>, aggregator_tx: Sender<ParsedPacket>) -> Parser {
+
+        let linktype=source.get_linktype();
+        let filter=Arc::new(Mutex::new(filter));
+        let filter_clone=filter.clone();
+        let filter_changed=Arc::new(AtomicBool::new(false));
+        let filter_changed_clone=filter_changed.clone();
+        let filter_applied=Arc::new(Condvar::new());
+        let filter_applied_clone=filter_applied.clone();
 
         let a=Arc::new(Mutex::new(Command::PROCEED));
         let cmd=a.clone();
         let cv=Arc::new(Condvar::new());
         let cv1=cv.clone();
+        let finished=Arc::new(Mutex::new(None));
+        let finished_clone=finished.clone();
+        let cv_finished=Arc::new(Condvar::new());
+        let cv_finished_clone=cv_finished.clone();
+        let capture_stats=Arc::new(Mutex::new(CaptureStats::new(device)));
+        let capture_stats_clone=capture_stats.clone();
+        let device=device.to_string();
 
         std::thread::spawn( move || {
            // println!("Parser thread started");
+            let mut sent:usize=0;
+            //libpcap statistics are refreshed once per second, live devices are read with a timeout so the loop comes around
+            let mut last_pcap_stats=Instant::now();
             loop {
-                match cap.next_packet() {
-                    Ok(packet) => {
-
-                        let cmd = cmd.lock().unwrap();
-                        match *cmd {
-                            Command::EXIT => {
-                                break;
-                            },
-                            Command::PAUSE => {
-                                let _cmd = cv.wait_while(cmd, |cmd| *cmd == Command::PAUSE).unwrap();
-                            },
-                            Command::PROCEED => {
-                                let p=Self::parse_packet(packet);
-                                match p {
-                                    None => //Packet not valid for parsing (neither IP/TCP, IP/UDP or IP/ICMP)
-                                    {},
-                                    Some(x) => match aggregator_tx.send(x) {
-                                        Ok(x) => x,
-                                        Err(_) => {
-                                            //Error sending parsed packet, receiver dropped, terminating parser thread
-                                            break;
-                                        },
+                if filter_changed_clone.load(Ordering::SeqCst) {
+                    //the new filter has already been validated by set_filter, which waits for it to be applied
+                    let filter = filter_clone.lock().unwrap();
+                    let _ = source.set_filter(filter.as_deref());
+                    filter_changed_clone.store(false, Ordering::SeqCst);
+                    filter_applied_clone.notify_all();
+                }
+                //refreshed on a timer, so that the drops are up to date even on an idle link or while paused
+                if last_pcap_stats.elapsed() >= Duration::from_secs(1) {
+                    last_pcap_stats=Instant::now();
+                    if let Some(stat) = source.pcap_stats() {
+                        capture_stats_clone.lock().unwrap().record_pcap_stats(stat);
+                    }
+                }
+                {
+                    let cmd = cmd.lock().unwrap();
+                    match *cmd {
+                        Command::EXIT => {
+                            break;
+                        },
+                        Command::PAUSE => {
+                            //a filter change wakes the thread up to be applied while paused
+                            let _cmd = cv.wait_timeout_while(cmd, Duration::from_secs(1), |cmd| *cmd == Command::PAUSE && !filter_changed_clone.load(Ordering::SeqCst)).unwrap();
+                            continue;
+                        },
+                        Command::PROCEED => {},
+                    }
+                }
+                match source.next_parsed() {
+                    Ok(Some(p)) => {
+                        match p {
+                            ReadPacket::Filtered | ReadPacket::Timeout => {},
+                            ReadPacket::Skipped(size) => //Packet not valid for parsing (not an IP packet)
+                                capture_stats_clone.lock().unwrap().record_skipped(size),
+                            ReadPacket::Parsed(x) => {
+                                capture_stats_clone.lock().unwrap().record_parsed(&x);
+                                match aggregator_tx.send(x) {
+                                    Ok(_) => sent+=1,
+                                    Err(_) => {
+                                        //Error sending parsed packet, receiver dropped, terminating parser thread
+                                        break;
                                     },
                                 }
-                            }
+                            },
                         }
                     },
-                    Err(_) => { 
+                    Ok(None) => {
+                        //End of the capture file reached
+                        break },
+                    Err(e) => {
+                        //Device closed or capture file truncated, the capture ends early
+                        eprintln!("Error reading packets from {}: {}", device, e);
+                        capture_stats_clone.lock().unwrap().error = Some(e);
                         break }
                 }
             }
+            let mut capture_stats = capture_stats_clone.lock().unwrap();
+            if let Some(stat) = source.pcap_stats() {
+                capture_stats.record_pcap_stats(stat);
+            }
+            capture_stats.end_time = Some(Utc::now());
+            drop(capture_stats);
+            //notify whoever is waiting for the capture to be completed
+            let mut finished = finished_clone.lock().unwrap();
+            *finished=Some(sent);
+            cv_finished_clone.notify_all();
+            drop(finished);
+            //a filter change requested now has nothing left to be applied to
+            let _filter = filter_clone.lock().unwrap();
+            filter_changed_clone.store(false, Ordering::SeqCst);
+            filter_applied_clone.notify_all();
         });
 
-        Parser{cmd:a,cv:cv1}
+        Parser{cmd:a,cv:cv1,finished,cv_finished,filter,filter_changed,filter_applied,capture_stats,linktype}
+    }
+
+    /// Changes the BPF filter applied by the running [Parser], `None` removes the current filter
+    /// # Arguments
+    /// * `filter` - The new BPF filter expression, e.g. `tcp port 443 or udp port 53`
+    /// # Errors
+    /// Returns a [FilterError] if the filter expression cannot be compiled, in that case the current filter is kept
+    /// # Remarks
+    /// The filter is applied by the [Parser] thread, this function returns once it has been applied (or the [Parser] has finished).
+    /// Live devices are read with a timeout, so the change takes effect even if no packet matches the current filter
+    pub fn set_filter(&self, filter: Option<&str>) -> Result<(), FilterError> {
+        if let Some(expression) = filter {
+            Self::validate_filter(expression, self.linktype)?;
+        }
+        let mut current = self.filter.lock().unwrap();
+        *current = filter.map(|x| x.to_string());
+        self.filter_changed.store(true, Ordering::SeqCst);
+        //wake up the thread if it is paused
+        drop(self.cmd.lock().unwrap());
+        self.cv.notify_all();
+        let _current = self.filter_applied.wait_while(current, |_| self.filter_changed.load(Ordering::SeqCst) && !self.is_finished()).unwrap();
+        Ok(())
+    }
+
+    /// Returns the BPF filter currently applied by the [Parser], if any
+    pub fn get_filter(&self) -> Option<String> {
+        self.filter.lock().unwrap().clone()
+    }
+
+    /// Checks that a BPF filter expression can be compiled for the given link layer type
+    fn validate_filter(filter: &str, linktype: u16) -> Result<(), FilterError> {
+        let error = |e: pcap::Error| FilterError { filter: filter.to_string(), reason: e.to_string() };
+        let dead = Capture::dead(Linktype(linktype as i32)).map_err(error)?;
+        dead.compile(filter, true).map(|_| ()).map_err(error)
+    }
+
+    /// Returns the statistics of the packets read by the [Parser], updated while it is running
+    pub fn get_capture_stats(&self) -> Arc<Mutex<CaptureStats>> {
+        Arc::clone(&self.capture_stats)
+    }
+
+    /// Returns true if the [Parser] thread has stopped reading packets, e.g. because the end of the capture file has been reached
+    pub fn is_finished(&self) -> bool {
+        self.finished.lock().unwrap().is_some()
+    }
+
+    /// Blocks until the [Parser] thread has stopped reading packets, e.g. because the end of the capture file has been reached
+    /// # Returns
+    /// The number of [ParsedPacket] forwarded to the Sender
+    /// # Remarks
+    /// When reading from a live device this function only returns if the device is closed or the [Parser] is dropped
+    pub fn wait_for_completion(&self) -> usize {
+        let finished = self.finished.lock().unwrap();
+        let finished = self.cv_finished.wait_while(finished, |finished| finished.is_none()).unwrap();
+        finished.unwrap()
     }
 
     /// Pauses the [Parser] from receiving packets if it is not already paused
@@ -146,95 +583,149 @@ impl Parser{
         self.cv.notify_one();
     }
 
-    /// Parses a pcap Packet into a [ParsedPacket]
+    /// Parses the bytes of a captured packet into a [ParsedPacket]
     /// # Arguments
-    /// * `packet` - The pcap Packet to be parsed
+    /// * `data` - The captured bytes of the packet, starting from the link layer header
+    /// * `linktype` - The link layer type of the capture the packet comes from
+    /// * `timestamp` - The capture timestamp of the packet
+    /// * `size` - The original length of the packet
+    /// * `interface` - The name of the interface the packet was captured on, if known
     /// # Returns
-    /// A [ParsedPacket] if the packet is  a valid IP packet from an ethernet slice, `None` otherwise
+    /// A [ParsedPacket] if the packet is  a valid IP packet from an ethernet or raw IP slice, `None` otherwise
     /// # Remarks
     /// This function is used internally by the [Parser] to parse the packets it receives
-    fn parse_packet(packet:Packet) -> Option<ParsedPacket> {
-        let ph=PacketHeaders::from_ethernet_slice(&packet).unwrap_or(PacketHeaders{ link: None, vlan: None, ip:None, transport: None, payload: &[] });
-        let source;
-        let destination;
-        let size;
-        let ts;
-        let trs_protocol;
+    fn parse_packet(data:&[u8], linktype:u16, timestamp:DateTime<Utc>, size:usize, interface:Option<String>) -> Option<ParsedPacket> {
+        if let Some(offset) = Self::ipv6_offset(data, linktype) {
+            //IPv6 packets are decoded by hand to walk any chain of extension headers
+            return Self::parse_ipv6(&data[offset..], timestamp, size, interface);
+        }
+        let ph=match linktype {
+            LINKTYPE_ETHERNET => PacketHeaders::from_ethernet_slice(data),
+            DLT_RAW | LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => PacketHeaders::from_ip_slice(data),
+            _ => return None,
+        }.unwrap_or(PacketHeaders{ link: None, vlan: None, ip:None, transport: None, payload: &[] });
+        let source:IpAddr;
+        let destination:IpAddr;
         let mut src_port = None;
         let mut dest_port = None;
+        let mut tcp_flags = None;
         match ph.ip {
             Some(Version4(ref h, _)) =>{
-                let mut s=h.source.into_iter().map(|i| i.to_string() + ".").collect::<String>();
-                s.pop();
-                source=s;
-                let mut d=h.destination.into_iter().map(|i| i.to_string() + ".").collect::<String>();
-                d.pop();
-                destination=d;
-                size=packet.header.len as usize;
-                let time_number=packet.header.ts.tv_sec as i64;
-                let local_tz:FixedOffset = TimeZone::from_offset(Local::now().offset());
-                let nt = NaiveDateTime::from_timestamp_opt(time_number, 0);
-                let dt = local_tz.from_utc_datetime(&nt.unwrap());
-                ts = dt.format("%Y-%m-%d %H:%M:%S").to_string();
+                source=h.source.into();
+                destination=h.destination.into();
             },
-            Some(Version6(ref h, _)) => {
-                let mut s=h.source.into_iter().map(|i| i.to_string() + ".").collect::<String>();
-                s.pop();
-                source=s;
-                let mut d=h.destination.into_iter().map(|i| i.to_string() + ".").collect::<String>();
-                d.pop();
-                destination=d;
-                size=packet.header.len as usize;
-                let time_number=packet.header.ts.tv_sec as i64;
-                let local_tz:FixedOffset = TimeZone::from_offset(Local::now().offset());
-                let nt = NaiveDateTime::from_timestamp_opt(time_number, 0);
-                let dt = local_tz.from_utc_datetime(&nt.unwrap());
-                ts = dt.format("%Y-%m-%d %H:%M:%S").to_string();
-            },
-            None => {
-                //NO IP HEADER
+            _ => {
+                //NO IPv4 HEADER
                 return  None;
             }
         }
-        match  ph.transport {
+        let protocol = match  ph.transport {
             Some(Tcp(th))=> {
-                trs_protocol = String::from("TCP");
-                src_port = Some(th.source_port as usize);
-                dest_port = Some(th.destination_port as usize);
+                src_port = Some(th.source_port);
+                dest_port = Some(th.destination_port);
+                tcp_flags = Some([th.fin, th.syn, th.rst, th.psh, th.ack, th.urg, th.ece, th.cwr].iter()
+                    .enumerate()
+                    .fold(0u8, |flags, (bit, &set)| flags | ((set as u8) << bit)));
+                Protocol::Tcp
             },
             Some(Udp(th)) => {
-                trs_protocol = String::from("UDP");
-                src_port = Some(th.source_port as usize);
-                dest_port = Some(th.destination_port as usize);
-            },
-            Some(Icmpv4(th)) => trs_protocol = match th.icmp_type {
-                Icmpv4Type::Unknown { .. } => String::from("ICMPv4: Type Unknown"),
-                Icmpv4Type::DestinationUnreachable(_) => String::from("ICMPv4: Destination Unreachable"),
-                Icmpv4Type::Redirect(_) => String::from("ICMPv4: Redirect"),
-                Icmpv4Type::TimeExceeded(_) => String::from("ICMPv4: Time Exceeded"),
-                Icmpv4Type::ParameterProblem(_) => String::from("ICMPv4: Parameter Problem"),
-                Icmpv4Type::TimestampRequest(_) => String::from("ICMPv4: Timestamp Request"),
-                Icmpv4Type::TimestampReply(_) => String::from("ICMPv4: Timestamp Reply"),
-                Icmpv4Type::EchoReply(_) => String::from("ICMPv4: Echo Reply"),
-                Icmpv4Type::EchoRequest(_) => String::from("ICMPv4: Echo Request"),
+                src_port = Some(th.source_port);
+                dest_port = Some(th.destination_port);
+                Protocol::Udp
             },
-            Some(Icmpv6(th)) => trs_protocol = match th.icmp_type {
-                Icmpv6Type::Unknown { .. } => String::from("ICMPv6: Type Unknown"),
-                Icmpv6Type::DestinationUnreachable(_) => String::from("ICMPv6: Destination Unreachable"),
-                Icmpv6Type::PacketTooBig { .. } => String::from("ICMPv6: Packet Too Big"),
-                Icmpv6Type::TimeExceeded(_) => String::from("ICMPv6: Time Exceeded"),
-                Icmpv6Type::ParameterProblem(_) => String::from("ICMPv6: Parameter Problem"),
-                Icmpv6Type::EchoRequest(_) => String::from("ICMPv6: Echo Request"),
-                Icmpv6Type::EchoReply(_) => String::from("ICMPv6: Echo Reply"),
+            Some(Icmpv4(th)) => {
+                //the first two bytes of the ICMP header are its type and code
+                let bytes = th.to_bytes();
+                Protocol::Icmpv4 { icmp_type: bytes[0], code: bytes[1] }
             },
-            None => {
-                //NO TRANSPORT LEVEL
-                return None;
+            _ => match ph.ip.as_ref().map(|ip| ip.next_header()) {
+                //transport level not parsed (e.g. fragmented or truncated payload), only the IP protocol number is known
+                Some(Ok(number)) => Protocol::from_ip_number(number),
+                _ => return None,
             }
-        }
+        };
+
+        let packet = ParsedPacket::new(timestamp, source, destination, src_port, dest_port, protocol, size).with_interface(interface).with_tcp_flags(tcp_flags);
+        Some(packet)
 
-        return  Some(ParsedPacket::new(ts, source, destination, src_port, dest_port, trs_protocol, size));
+    }
 
+    /// Returns the offset of the IPv6 header in the packet, `None` if the packet does not carry IPv6
+    /// # Arguments
+    /// * `data` - The captured bytes of the packet, starting from the link layer header
+    /// * `linktype` - The link layer type of the capture the packet comes from
+    fn ipv6_offset(data:&[u8], linktype:u16) -> Option<usize> {
+        match linktype {
+            LINKTYPE_ETHERNET => {
+                let mut offset = 12;
+                let mut ether_type = u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]);
+                //skip any 802.1Q/802.1ad VLAN tag
+                while ether_type == ETHER_TYPE_VLAN || ether_type == ETHER_TYPE_QINQ {
+                    offset += 4;
+                    ether_type = u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]);
+                }
+                if ether_type == ETHER_TYPE_IPV6 { Some(offset + 2) } else { None }
+            },
+            DLT_RAW | LINKTYPE_RAW | LINKTYPE_IPV6 if data.first()? >> 4 == 6 => Some(0),
+            _ => None,
+        }
+    }
+
+    /// Parses an IPv6 packet walking its extension headers (hop-by-hop, routing, fragment, destination options, authentication)
+    /// until the transport header is found
+    /// # Arguments
+    /// * `data` - The bytes of the packet, starting from the IPv6 header
+    /// * `timestamp`, `size`, `interface` - Capture information of the packet, as in [Parser::parse_packet]
+    /// # Returns
+    /// The [ParsedPacket], `None` if the IPv6 header is truncated
+    /// # Remarks
+    /// Ports are only available in unfragmented packets and first fragments, a truncated extension header chain
+    /// is reported with the protocol number of the first header that could not be read
+    fn parse_ipv6(data:&[u8], timestamp:DateTime<Utc>, size:usize, interface:Option<String>) -> Option<ParsedPacket> {
+        if data.len() < 40 || data[0] >> 4 != 6 {
+            return None;
+        }
+        let source:[u8;16] = data[8..24].try_into().ok()?;
+        let destination:[u8;16] = data[24..40].try_into().ok()?;
+        let mut next_header = data[6];
+        let mut rest = &data[40..];
+        let mut first_fragment = true;
+        loop {
+            let length = match next_header {
+                IPV6_HOP_BY_HOP | IPV6_ROUTING | IPV6_DESTINATION_OPTIONS if rest.len() >= 2 => (rest[1] as usize + 1) * 8,
+                IPV6_FRAGMENT if rest.len() >= 8 => {
+                    //the fragment offset is stored in the 13 most significant bits
+                    first_fragment = u16::from_be_bytes([rest[2], rest[3]]) >> 3 == 0;
+                    8
+                },
+                IP_AUTHENTICATION if rest.len() >= 2 => (rest[1] as usize + 2) * 4,
+                _ => break,
+            };
+            if rest.len() < length {
+                break;
+            }
+            next_header = rest[0];
+            rest = &rest[length..];
+        }
+        let mut src_port = None;
+        let mut dest_port = None;
+        let mut tcp_flags = None;
+        let protocol = match Protocol::from_ip_number(next_header) {
+            Protocol::Icmpv6 { .. } if first_fragment && rest.len() >= 2 => Protocol::Icmpv6 { icmp_type: rest[0], code: rest[1] },
+            protocol @ (Protocol::Tcp | Protocol::Udp) => {
+                if first_fragment && rest.len() >= 4 {
+                    src_port = Some(u16::from_be_bytes([rest[0], rest[1]]));
+                    dest_port = Some(u16::from_be_bytes([rest[2], rest[3]]));
+                }
+                if first_fragment && protocol == Protocol::Tcp && rest.len() >= 14 {
+                    tcp_flags = Some(rest[13]);
+                }
+                protocol
+            },
+            protocol => protocol,
+        };
+        let packet = ParsedPacket::new(timestamp, source.into(), destination.into(), src_port, dest_port, protocol, size).with_interface(interface).with_tcp_flags(tcp_flags);
+        Some(packet)
     }
 
 }
@@ -0,0 +1,256 @@
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read};
+
+/// Block type of the Section Header Block, it is also the magic number of every pcapng file
+pub const SECTION_HEADER_BLOCK: u32 = 0x0A0D0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x00000001;
+const SIMPLE_PACKET_BLOCK: u32 = 0x00000003;
+const ENHANCED_PACKET_BLOCK: u32 = 0x00000006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B3C4D;
+
+const OPT_END_OF_OPT: u16 = 0;
+const OPT_IF_NAME: u16 = 2;
+const OPT_IF_DESCRIPTION: u16 = 3;
+const OPT_IF_TSRESOL: u16 = 9;
+
+/// Struct describing a network interface declared by an Interface Description Block of a pcapng file
+#[derive(Debug, Clone)]
+pub struct PcapngInterface {
+    pub linktype: u16,
+    pub snaplen: u32,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /// Number of timestamp units per second, as declared by the `if_tsresol` option (default: microseconds)
+    pub ts_units_per_second: u64,
+}
+
+/// Struct representing a packet read from a pcapng file, with the index of the interface it was captured on
+#[derive(Debug, Clone)]
+pub struct PcapngPacket {
+    pub interface_id: u32,
+    pub ts_sec: i64,
+    pub ts_nsec: u32,
+    /// Original length of the packet on the wire
+    pub len: u32,
+    /// Captured bytes of the packet, starting from the link layer header
+    pub data: Vec<u8>,
+}
+
+/// Struct that reads the packets of a pcapng file block by block, keeping track of the interfaces declared in each section
+///
+/// # Examples
+/// Basic usage:
+/// ```rust
+/// use Network_analyzer::pcapng::PcapngReader;
+///
+/// let mut reader = PcapngReader::open("capture.pcapng").unwrap();
+/// while let Some(packet) = reader.next_packet().unwrap() {
+///     let interface = reader.get_interface(packet.interface_id).unwrap();
+///     println!("{} bytes captured on {:?}", packet.len, interface.name);
+/// }
+/// ```
+///
+/// # Remarks
+/// Only Section Header, Interface Description, Enhanced Packet and Simple Packet blocks are interpreted,
+/// all the other blocks are skipped
+pub struct PcapngReader<R: Read> {
+    reader: R,
+    big_endian: bool,
+    interfaces: Vec<PcapngInterface>,
+}
+
+impl PcapngReader<BufReader<File>> {
+    /// Opens the pcapng file at the given path
+    /// # Errors
+    /// If the file cannot be opened or does not start with a Section Header Block
+    pub fn open(path: &str) -> Result<Self, Error> {
+        PcapngReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> PcapngReader<R> {
+    /// Creates a new PcapngReader reading the first Section Header Block from the given reader
+    /// # Errors
+    /// If the data does not start with a valid Section Header Block
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        let mut block_type = [0u8; 4];
+        reader.read_exact(&mut block_type)?;
+        if u32::from_le_bytes(block_type) != SECTION_HEADER_BLOCK {
+            return Err(Error::new(ErrorKind::InvalidData, "not a pcapng file"));
+        }
+        let mut pcapng = PcapngReader { reader, big_endian: false, interfaces: Vec::new() };
+        pcapng.read_section_header()?;
+        Ok(pcapng)
+    }
+
+    /// Returns the interface with the given index in the current section, if declared
+    pub fn get_interface(&self, interface_id: u32) -> Option<&PcapngInterface> {
+        self.interfaces.get(interface_id as usize)
+    }
+
+    /// Reads blocks until the next packet is found
+    /// # Returns
+    /// The next [PcapngPacket] or `None` if the end of the file has been reached
+    /// # Errors
+    /// If the file is truncated or a block is malformed
+    pub fn next_packet(&mut self) -> Result<Option<PcapngPacket>, Error> {
+        loop {
+            let mut block_type = [0u8; 4];
+            match self.reader.read_exact(&mut block_type) {
+                Ok(_) => {},
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e),
+            }
+            if u32::from_le_bytes(block_type) == SECTION_HEADER_BLOCK {
+                //a new section starts: interface indexes restart from 0
+                self.interfaces.clear();
+                self.read_section_header()?;
+                continue;
+            }
+            let block_type = self.u32_from(block_type);
+            let body = self.read_block_body()?;
+            match block_type {
+                INTERFACE_DESCRIPTION_BLOCK => {
+                    let interface = self.parse_interface(&body)?;
+                    self.interfaces.push(interface);
+                },
+                ENHANCED_PACKET_BLOCK => return self.parse_enhanced_packet(&body).map(Some),
+                SIMPLE_PACKET_BLOCK => return self.parse_simple_packet(&body).map(Some),
+                _ => {
+                    //block not relevant for the analysis, skipped
+                }
+            }
+        }
+    }
+
+    /// Reads the rest of a Section Header Block once its block type has been consumed, setting the byte order of the section
+    fn read_section_header(&mut self) -> Result<(), Error> {
+        let mut length = [0u8; 4];
+        self.reader.read_exact(&mut length)?;
+        let mut magic = [0u8; 4];
+        self.reader.read_exact(&mut magic)?;
+        self.big_endian = match u32::from_le_bytes(magic) {
+            BYTE_ORDER_MAGIC => false,
+            x if x.swap_bytes() == BYTE_ORDER_MAGIC => true,
+            _ => return Err(Error::new(ErrorKind::InvalidData, "invalid pcapng byte order magic")),
+        };
+        let length = self.u32_from(length) as usize;
+        if length < 28 || !length.is_multiple_of(4) {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid pcapng section header length"));
+        }
+        //skip versions, section length, options and the trailing block length
+        self.read_bytes(length - 12)?;
+        Ok(())
+    }
+
+    /// Reads the length of the current block and returns its body, consuming the trailing block length
+    fn read_block_body(&mut self) -> Result<Vec<u8>, Error> {
+        let mut length = [0u8; 4];
+        self.reader.read_exact(&mut length)?;
+        let length = self.u32_from(length) as usize;
+        if length < 12 || !length.is_multiple_of(4) {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid pcapng block length"));
+        }
+        let body = self.read_bytes(length - 12)?;
+        let mut trailer = [0u8; 4];
+        self.reader.read_exact(&mut trailer)?;
+        Ok(body)
+    }
+
+    /// Reads `length` bytes of the current block, growing the buffer as the bytes are read so that a corrupt block length
+    /// does not allocate more memory than the file holds
+    fn read_bytes(&mut self, length: usize) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::new();
+        (&mut self.reader).take(length as u64).read_to_end(&mut bytes)?;
+        if bytes.len() < length {
+            return Err(Error::new(ErrorKind::InvalidData, "truncated pcapng block"));
+        }
+        Ok(bytes)
+    }
+
+    fn parse_interface(&self, body: &[u8]) -> Result<PcapngInterface, Error> {
+        if body.len() < 8 {
+            return Err(Error::new(ErrorKind::InvalidData, "truncated pcapng interface description block"));
+        }
+        let mut interface = PcapngInterface {
+            linktype: self.u16_at(body, 0),
+            snaplen: self.u32_at(body, 4),
+            name: None,
+            description: None,
+            ts_units_per_second: 1_000_000,
+        };
+        let mut offset = 8;
+        while offset + 4 <= body.len() {
+            let code = self.u16_at(body, offset);
+            let length = self.u16_at(body, offset + 2) as usize;
+            offset += 4;
+            if code == OPT_END_OF_OPT || offset + length > body.len() {
+                break;
+            }
+            let value = &body[offset..offset + length];
+            match code {
+                OPT_IF_NAME => interface.name = Some(String::from_utf8_lossy(value).trim_end_matches('\0').to_string()),
+                OPT_IF_DESCRIPTION => interface.description = Some(String::from_utf8_lossy(value).trim_end_matches('\0').to_string()),
+                OPT_IF_TSRESOL if length >= 1 => {
+                    let exponent = (value[0] & 0x7F) as u32;
+                    interface.ts_units_per_second = if value[0] & 0x80 == 0 {
+                        10u64.checked_pow(exponent)
+                    } else {
+                        2u64.checked_pow(exponent)
+                    }.ok_or_else(|| Error::new(ErrorKind::InvalidData, "unsupported pcapng timestamp resolution"))?;
+                },
+                _ => {},
+            }
+            //option values are padded to 32 bits
+            offset += (length + 3) & !3;
+        }
+        Ok(interface)
+    }
+
+    fn parse_enhanced_packet(&self, body: &[u8]) -> Result<PcapngPacket, Error> {
+        if body.len() < 20 {
+            return Err(Error::new(ErrorKind::InvalidData, "truncated pcapng enhanced packet block"));
+        }
+        let interface_id = self.u32_at(body, 0);
+        let interface = self.get_interface(interface_id)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "pcapng packet refers to an undeclared interface"))?;
+        let ts = ((self.u32_at(body, 4) as u64) << 32) | self.u32_at(body, 8) as u64;
+        let caplen = self.u32_at(body, 12) as usize;
+        let len = self.u32_at(body, 16);
+        if 20 + caplen > body.len() {
+            return Err(Error::new(ErrorKind::InvalidData, "truncated pcapng packet data"));
+        }
+        let units = interface.ts_units_per_second;
+        let ts_sec = (ts / units) as i64;
+        let ts_nsec = ((ts % units) as u128 * 1_000_000_000 / units as u128) as u32;
+        Ok(PcapngPacket { interface_id, ts_sec, ts_nsec, len, data: body[20..20 + caplen].to_vec() })
+    }
+
+    fn parse_simple_packet(&self, body: &[u8]) -> Result<PcapngPacket, Error> {
+        if body.len() < 4 {
+            return Err(Error::new(ErrorKind::InvalidData, "truncated pcapng simple packet block"));
+        }
+        let interface = self.get_interface(0)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "pcapng packet refers to an undeclared interface"))?;
+        let len = self.u32_at(body, 0);
+        let mut caplen = (len as usize).min(body.len() - 4);
+        if interface.snaplen != 0 {
+            caplen = caplen.min(interface.snaplen as usize);
+        }
+        //simple packet blocks carry no timestamp
+        Ok(PcapngPacket { interface_id: 0, ts_sec: 0, ts_nsec: 0, len, data: body[4..4 + caplen].to_vec() })
+    }
+
+    fn u32_from(&self, bytes: [u8; 4]) -> u32 {
+        if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+    }
+
+    fn u32_at(&self, body: &[u8], offset: usize) -> u32 {
+        self.u32_from([body[offset], body[offset + 1], body[offset + 2], body[offset + 3]])
+    }
+
+    fn u16_at(&self, body: &[u8], offset: usize) -> u16 {
+        let bytes = [body[offset], body[offset + 1]];
+        if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) }
+    }
+}
@@ -1,92 +1,395 @@
 use core::fmt;
+use std::collections::HashMap;
 use std::fmt::Formatter;
+use std::net::IpAddr;
+use std::time::Duration;
+use chrono::{DateTime, Local, Utc};
+use crate::parsed_packet::Protocol;
 
 /// This struct represents a directional connection between two hosts.
 /// It is used as a key in the aggregated data map.
 /// It represents source and destination hosts through their IP addresses and ports and specifies the type of connection through the `protocol` attribute.
 /// The source IP address and the source port are Option to allow representation of ICMP communications that are not associated to ports.
-#[derive(Debug, PartialEq, Eq, Hash)]
+/// The interface is the name of the device or pcapng interface the connection was seen on, if known.
+///
+/// When the aggregated data map is not keyed by the full connection (see [AggregationKey](crate::aggregator::AggregationKey)),
+/// the addresses and the protocol that are not part of the key are `None`, and the addresses can be networks:
+/// `source_prefix` and `destination_prefix` hold their prefix length.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Connection {
-    pub source_ip: String,
-    pub destination_ip: String,
-    pub source_port: Option<usize>,
-    pub destination_port: Option<usize>,
-    pub protocol: String,
+    pub source_ip: Option<IpAddr>,
+    pub source_prefix: Option<u8>,
+    pub destination_ip: Option<IpAddr>,
+    pub destination_prefix: Option<u8>,
+    pub source_port: Option<u16>,
+    pub destination_port: Option<u16>,
+    pub protocol: Option<Protocol>,
+    pub interface: Option<String>,
 }
 
 impl Connection {
-    /// Creates a new Connection struct.
+    /// Creates a new Connection struct between two hosts.
     /// # Arguments
     /// * `source_ip` - The source IP address.
     /// * `destination_ip` - The destination IP address.
     /// * `source_port` - The source port.
     /// * `destination_port` - The destination port.
     /// * `protocol` - The protocol.
+    /// * `interface` - The name of the interface the connection was seen on.
     /// # Returns
     /// A new Connection struct.
     pub fn new(
-        source_ip: String,
-        destination_ip: String,
-        source_port: Option<usize>,
-        destination_port: Option<usize>,
-        protocol: String,
+        source_ip: IpAddr,
+        destination_ip: IpAddr,
+        source_port: Option<u16>,
+        destination_port: Option<u16>,
+        protocol: Protocol,
+        interface: Option<String>,
     ) -> Self {
         Connection {
-            source_ip,
-            destination_ip,
+            source_ip: Some(source_ip),
+            source_prefix: None,
+            destination_ip: Some(destination_ip),
+            destination_prefix: None,
             source_port,
             destination_port,
-            protocol,
+            protocol: Some(protocol),
+            interface,
+        }
+    }
+
+    /// Returns the source address, in CIDR notation if it is a network, `None` if it is not part of the key.
+    pub fn source_address(&self) -> Option<String> {
+        format_address(self.source_ip, self.source_prefix)
+    }
+
+    /// Returns the destination address, in CIDR notation if it is a network, `None` if it is not part of the key.
+    pub fn destination_address(&self) -> Option<String> {
+        format_address(self.destination_ip, self.destination_prefix)
+    }
+
+    /// Returns the textual form of a port of the Connection used in the reports: `*` if the port is not part of the key,
+    /// `-` if the protocol has no ports (e.g. ICMP)
+    pub fn port_label(&self, port: Option<u16>) -> String {
+        match (port, self.protocol) {
+            (Some(port), _) => port.to_string(),
+            (None, None | Some(Protocol::Tcp) | Some(Protocol::Udp)) => String::from("*"),
+            (None, Some(_)) => String::from("-"),
+        }
+    }
+
+    /// Returns the Connection in the opposite direction, swapping source and destination addresses and ports.
+    pub fn reversed(&self) -> Self {
+        Connection {
+            source_ip: self.destination_ip,
+            source_prefix: self.destination_prefix,
+            destination_ip: self.source_ip,
+            destination_prefix: self.source_prefix,
+            source_port: self.destination_port,
+            destination_port: self.source_port,
+            protocol: self.protocol,
+            interface: self.interface.clone(),
         }
     }
 }
 
+/// Formats an address of a [Connection], in CIDR notation if it is a network
+fn format_address(ip: Option<IpAddr>, prefix: Option<u8>) -> Option<String> {
+    match (ip, prefix) {
+        (Some(ip), Some(prefix)) => Some(format!("{}/{}", ip, prefix)),
+        (Some(ip), None) => Some(ip.to_string()),
+        (None, _) => None,
+    }
+}
+
 /// Display implementation for the Connection struct.
 /// It is used to print the Connection struct as a markdown table entry.
 impl fmt::Display for Connection {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        let port_src = match self.source_port {
-            Some(x) => x.to_string(),
-            None => String::from("-"),
+        let port_src = self.port_label(self.source_port);
+        let port_dst = self.port_label(self.destination_port);
+        let interface = match self.interface {
+            Some(ref x) => x.as_str(),
+            None => "-",
         };
-        let port_dst = match self.destination_port {
+        let protocol = match self.protocol {
             Some(x) => x.to_string(),
-            None => String::from("-"),
+            None => String::from("*"),
         };
-        write!(f,"| {0:<15} \t| {1:<15} \t| {2:<5} \t | {3:<5} \t| {4:<7} \t|", self.source_ip, self.destination_ip, port_src, port_dst, self.protocol)
+        write!(f,"| {0:<10} \t| {1:<15} \t| {2:<15} \t| {3:<5} \t | {4:<5} \t| {5:<7} \t|", interface,
+               self.source_address().unwrap_or_else(|| String::from("*")), self.destination_address().unwrap_or_else(|| String::from("*")), port_src, port_dst, protocol)
     }
 }
 
 /// This struct represents aggregated data collected about a connection.
 /// It is used as a value in the aggregated data map.
-/// It contains the number of bytes exchanged between the two hosts and the timestamp of the first and last packet exchanged.
-#[derive(Debug)]
+/// It contains the number of bytes and packets exchanged between the two hosts, the smallest and largest packet size
+/// and the timestamp of the first and last packet exchanged.
+/// Bytes and packets are also counted per direction: the initiator is the source of the [Connection] key,
+/// the responder its destination (in directional aggregation every packet is sent by the initiator).
+#[derive(Debug, Clone)]
 pub struct ConnectionMetadata {
 pub size: usize,
-pub first_timestamp: String,
-pub last_timestamp: String,
+pub packets: usize,
+pub initiator_size: usize,
+pub initiator_packets: usize,
+pub responder_size: usize,
+pub responder_packets: usize,
+pub min_size: usize,
+pub max_size: usize,
+pub first_timestamp: DateTime<Utc>,
+pub last_timestamp: DateTime<Utc>,
 }
 
 impl ConnectionMetadata {
-    /// Creates a new ConnectionMetadata struct.
+    /// Creates a new ConnectionMetadata struct describing a connection made of a single packet.
     /// # Arguments
     /// * `size` - The number of bytes exchanged.
     /// * `first_timestamp` - The timestamp of the first packet exchanged.
     /// * `last_timestamp` - The timestamp of the last packet exchanged.
     /// # Returns
     /// A new ConnectionMetadata struct.
-    pub fn new(size: usize, first_timestamp: String, last_timestamp: String) -> Self {
+    pub fn new(size: usize, first_timestamp: DateTime<Utc>, last_timestamp: DateTime<Utc>) -> Self {
+        ConnectionMetadata {
+            size,
+            packets: 1,
+            initiator_size: size,
+            initiator_packets: 1,
+            responder_size: 0,
+            responder_packets: 0,
+            min_size: size,
+            max_size: size,
+            first_timestamp,
+            last_timestamp,
+        }
+    }
+
+    /// Creates a new ConnectionMetadata struct describing a flow record, e.g. received from a router.
+    /// Flow records do not carry the size of each packet: the mean size of the packets of the flow is used
+    /// as smallest and largest packet size.
+    /// # Arguments
+    /// * `size` - The number of bytes of the flow.
+    /// * `packets` - The number of packets of the flow.
+    /// * `first_timestamp` - The timestamp of the first packet of the flow.
+    /// * `last_timestamp` - The timestamp of the last packet of the flow.
+    pub fn from_flow(size: usize, packets: usize, first_timestamp: DateTime<Utc>, last_timestamp: DateTime<Utc>) -> Self {
+        let mean_size = size / packets.max(1);
         ConnectionMetadata {
             size,
+            packets,
+            initiator_size: size,
+            initiator_packets: packets,
+            responder_size: 0,
+            responder_packets: 0,
+            min_size: mean_size,
+            max_size: mean_size,
             first_timestamp,
             last_timestamp,
         }
     }
+
+    /// Accounts a new packet of the connection sent by the initiator.
+    /// # Arguments
+    /// * `size` - The size of the packet.
+    /// * `timestamp` - The timestamp of the packet, it extends the first or the last timestamp of the connection if it is outside of them.
+    pub fn update(&mut self, size: usize, timestamp: DateTime<Utc>) {
+        self.initiator_size += size;
+        self.initiator_packets += 1;
+        self.account(size, timestamp);
+    }
+
+    /// Accounts a new packet of the connection sent by the responder.
+    /// # Arguments
+    /// * `size` - The size of the packet.
+    /// * `timestamp` - The timestamp of the packet, it extends the first or the last timestamp of the connection if it is outside of them.
+    pub fn update_responder(&mut self, size: usize, timestamp: DateTime<Utc>) {
+        self.responder_size += size;
+        self.responder_packets += 1;
+        self.account(size, timestamp);
+    }
+
+    /// Swaps the initiator and responder counters, used when the initiator of a connection turns out to be its destination
+    pub fn swap_direction(&mut self) {
+        std::mem::swap(&mut self.initiator_size, &mut self.responder_size);
+        std::mem::swap(&mut self.initiator_packets, &mut self.responder_packets);
+    }
+
+    /// Accounts a flow record of the connection sent by the initiator, see [ConnectionMetadata::from_flow].
+    /// Records can arrive out of order: the first and last timestamps only grow the lifetime of the connection.
+    pub fn update_flow(&mut self, size: usize, packets: usize, first_timestamp: DateTime<Utc>, last_timestamp: DateTime<Utc>) {
+        self.initiator_size += size;
+        self.initiator_packets += packets;
+        self.account_flow(size, packets, first_timestamp, last_timestamp);
+    }
+
+    /// Accounts a flow record of the connection sent by the responder, see [ConnectionMetadata::update_flow].
+    pub fn update_flow_responder(&mut self, size: usize, packets: usize, first_timestamp: DateTime<Utc>, last_timestamp: DateTime<Utc>) {
+        self.responder_size += size;
+        self.responder_packets += packets;
+        self.account_flow(size, packets, first_timestamp, last_timestamp);
+    }
+
+    fn account_flow(&mut self, size: usize, packets: usize, first_timestamp: DateTime<Utc>, last_timestamp: DateTime<Utc>) {
+        let mean_size = size / packets.max(1);
+        self.size += size;
+        self.packets += packets;
+        self.min_size = self.min_size.min(mean_size);
+        self.max_size = self.max_size.max(mean_size);
+        self.first_timestamp = self.first_timestamp.min(first_timestamp);
+        self.last_timestamp = self.last_timestamp.max(last_timestamp);
+    }
+
+    fn account(&mut self, size: usize, timestamp: DateTime<Utc>) {
+        self.size += size;
+        self.packets += 1;
+        self.min_size = self.min_size.min(size);
+        self.max_size = self.max_size.max(size);
+        //packets may arrive out of order, e.g. from several interfaces or parsers
+        self.first_timestamp = self.first_timestamp.min(timestamp);
+        self.last_timestamp = self.last_timestamp.max(timestamp);
+    }
+
+    /// Returns the mean size of the packets of the connection
+    pub fn mean_size(&self) -> f64 {
+        self.size as f64 / self.packets as f64
+    }
+
+    /// Returns the lifetime of the connection in seconds, from the first to the last packet
+    pub fn duration(&self) -> f64 {
+        (self.last_timestamp - self.first_timestamp).num_microseconds().unwrap_or(i64::MAX) as f64 / 1_000_000.0
+    }
+
+    /// Returns the average throughput of the connection in bytes per second over its lifetime,
+    /// `None` if all the packets share the same timestamp
+    pub fn bytes_per_second(&self) -> Option<f64> {
+        let duration = self.duration();
+        if duration > 0.0 { Some(self.size as f64 / duration) } else { None }
+    }
+
+    /// Returns the average throughput of the connection in packets per second over its lifetime,
+    /// `None` if all the packets share the same timestamp
+    pub fn packets_per_second(&self) -> Option<f64> {
+        let duration = self.duration();
+        if duration > 0.0 { Some(self.packets as f64 / duration) } else { None }
+    }
 }
 
+/// Display implementation for the ConnectionMetadata struct.
+/// It is used to print the ConnectionMetadata struct as a markdown table entry, timestamps are shown in local time
+/// and throughputs are shown as `-` when the connection lifetime is zero.
 impl fmt::Display for ConnectionMetadata {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f," {0:<9} \t| {1:<15} \t| {2:<3}|",self.size,self.first_timestamp,self.last_timestamp)
+        let first_timestamp = self.first_timestamp.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string();
+        let last_timestamp = self.last_timestamp.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string();
+        let bytes_per_second = match self.bytes_per_second() {
+            Some(x) => format!("{:.1}", x),
+            None => String::from("-"),
+        };
+        let packets_per_second = match self.packets_per_second() {
+            Some(x) => format!("{:.2}", x),
+            None => String::from("-"),
+        };
+        write!(f," {0:<9} \t| {1:<7} \t| {2:<5} \t| {3:<5} \t| {4:<7.1} \t| {5:<9} \t| {6:<7} \t| {7:<9} \t| {8:<7} \t| {9:<9} \t| {10:<7} \t| {11:<15} \t| {12:<3}|",
+               self.size, self.packets, self.min_size, self.max_size, self.mean_size(), bytes_per_second, packets_per_second,
+               self.initiator_size, self.initiator_packets, self.responder_size, self.responder_packets, first_timestamp, last_timestamp)
     }
 }
 
+/// Enum describing how a connection changed since the previous report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeStatus {
+    /// The connection was not in the previous report
+    New,
+    /// The connection exchanged packets since the previous report
+    Updated,
+    /// The connection did not exchange packets since the previous report
+    Unchanged,
+    /// The connection was in the previous report but is no longer in the aggregated data (e.g. it expired)
+    Disappeared,
+}
+
+/// Display implementation for the ChangeStatus enum, e.g. `new` or `disappeared`.
+impl fmt::Display for ChangeStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            ChangeStatus::New => "new",
+            ChangeStatus::Updated => "updated",
+            ChangeStatus::Unchanged => "unchanged",
+            ChangeStatus::Disappeared => "disappeared",
+        })
+    }
+}
+
+/// This struct represents the change of a connection since the previous report.
+/// Bytes and packets are the ones exchanged during the interval between the two reports,
+/// the rates are computed over the interval (`None` if the interval is zero).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConnectionDelta {
+    pub status: ChangeStatus,
+    pub bytes: usize,
+    pub packets: usize,
+    pub bytes_per_second: Option<f64>,
+    pub packets_per_second: Option<f64>,
+}
+
+/// Display implementation for the ConnectionDelta struct.
+/// It is used to print the ConnectionDelta struct as a markdown table entry, rates are shown as `-` when not available.
+impl fmt::Display for ConnectionDelta {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let bytes_per_second = match self.bytes_per_second {
+            Some(x) => format!("{:.1}", x),
+            None => String::from("-"),
+        };
+        let packets_per_second = match self.packets_per_second {
+            Some(x) => format!("{:.2}", x),
+            None => String::from("-"),
+        };
+        write!(f," {0:<11} \t| {1:<9} \t| {2:<7} \t| {3:<9} \t| {4:<7} \t|",
+               self.status, self.bytes, self.packets, bytes_per_second, packets_per_second)
+    }
+}
+
+/// This struct represents the changes of the aggregated data between two reports.
+#[derive(Debug, Clone, Default)]
+pub struct DeltaReport {
+    /// Time elapsed between the two reports
+    pub interval: Duration,
+    /// Change of every connection of the current aggregated data and of the disappeared ones
+    pub changes: HashMap<Connection, ConnectionDelta>,
+    /// Connections of the previous report that are no longer in the aggregated data, with their last known totals
+    pub disappeared: HashMap<Connection, ConnectionMetadata>,
+}
+
+impl DeltaReport {
+    /// Computes the changes between two snapshots of the aggregated data.
+    /// # Arguments
+    /// * `previous` - The aggregated data of the previous report.
+    /// * `current` - The current aggregated data.
+    /// * `interval` - The time elapsed between the two snapshots.
+    /// # Remarks
+    /// A connection whose counters decreased (e.g. it expired and started again between the reports)
+    /// is considered new, its whole totals are counted in the interval.
+    pub fn compute(previous: &HashMap<Connection, ConnectionMetadata>, current: &HashMap<Connection, ConnectionMetadata>, interval: Duration) -> Self {
+        let seconds = interval.as_secs_f64();
+        let rate = |count: usize| if seconds > 0.0 { Some(count as f64 / seconds) } else { None };
+        let mut changes: HashMap<Connection, ConnectionDelta> = current.iter().map(|(conn, data)| {
+            let (status, bytes, packets) = match previous.get(conn) {
+                Some(old) if old.packets <= data.packets && old.size <= data.size => {
+                    let status = if data.packets > old.packets { ChangeStatus::Updated } else { ChangeStatus::Unchanged };
+                    (status, data.size - old.size, data.packets - old.packets)
+                },
+                _ => (ChangeStatus::New, data.size, data.packets),
+            };
+            (conn.clone(), ConnectionDelta { status, bytes, packets, bytes_per_second: rate(bytes), packets_per_second: rate(packets) })
+        }).collect();
+        let disappeared: HashMap<Connection, ConnectionMetadata> = previous.iter()
+            .filter(|(conn, _)| !current.contains_key(conn))
+            .map(|(conn, data)| (conn.clone(), data.clone()))
+            .collect();
+        for conn in disappeared.keys() {
+            let delta = ConnectionDelta { status: ChangeStatus::Disappeared, bytes: 0, packets: 0, bytes_per_second: rate(0), packets_per_second: rate(0) };
+            changes.insert(conn.clone(), delta);
+        }
+        DeltaReport { interval, changes, disappeared }
+    }
+}
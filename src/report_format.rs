@@ -0,0 +1,396 @@
+use std::collections::HashMap;
+use std::io::{Result, Write};
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use serde_json::{json, Value};
+
+use crate::aggregator::WindowedData;
+use crate::parsed_packet::Protocol;
+use crate::parser::CaptureStats;
+use crate::report_entry::{Connection, ConnectionDelta, ConnectionMetadata, DeltaReport};
+use crate::report_html::write_html;
+use crate::report_sink::Report;
+
+/// Enum describing the format of the reports written by a [ReportWriter](crate::report_writer::ReportWriter)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReportFormat {
+    /// Markdown tables, one for the lifetime totals and one for each time window (the default),
+    /// preceded by a summary of the capture statistics and by a table of the changes since the previous report if available
+    #[default]
+    Markdown,
+    /// Comma separated values as described by RFC 4180, with a header row.
+    ///
+    /// Numeric columns are written without any formatting, timestamps in RFC 3339 format (UTC), empty fields stand
+    /// for missing values. The `window_start` column is empty for the lifetime totals and holds the start of the
+    /// window for the rows of a time window. The capture statistics are not included.
+    ///
+    /// When the report includes the changes since the previous report, the columns `change`, `delta_bytes`, `delta_packets`,
+    /// `delta_bytes_per_second` and `delta_packets_per_second` are added (empty for the rows of the time windows) and the
+    /// connections that disappeared are written after the lifetime totals, with their last totals
+    Csv,
+    /// A single JSON document:
+    /// ```json
+    /// {
+    ///   "schema_version": 1,
+    ///   "generated_at": "2022-11-06T22:58:10.000000Z",
+    ///   "summary": { ... },
+    ///   "changes": { "interval_seconds": 5.0, "connections": [ ... ] },
+    ///   "connections": [ ... ],
+    ///   "windows": [ { "start": "2022-11-06T22:58:00.000000Z", "connections": [ ... ] } ]
+    /// }
+    /// ```
+    /// `summary` is only present when the capture statistics are available, it is an object with the fields:
+    /// * `device`: string, the name of the device or the path of the capture file
+    /// * `start_time`: string in RFC 3339 format (UTC), `elapsed_seconds`: number
+    /// * `packets`, `bytes`, `skipped_packets`: integers
+    /// * `dropped_packets`, `interface_dropped_packets`: integers, `null` if not reported by libpcap
+    /// * `error`: string, the error that stopped the capture early, `null` if none
+    /// * `protocols`: array of objects with the fields `protocol` (string), `ip_protocol`, `packets` and `bytes` (integers)
+    ///
+    /// `changes` is only present when the report includes the changes since the previous report: its connections are
+    /// the ones that changed, with the fields of the connections plus `change` (`new`, `updated` or `disappeared`),
+    /// `delta_bytes`, `delta_packets` (integers), `delta_bytes_per_second` and `delta_packets_per_second` (numbers)
+    ///
+    /// `windows` is only present when the report includes time windows. Each connection is an object with the fields:
+    /// * `interface`, `source_address`, `destination_address`, `protocol`: strings, addresses of networks are in CIDR notation
+    /// * `source_port`, `destination_port`, `ip_protocol`, `icmp_type`, `icmp_code`: integers
+    /// * `bytes`, `packets`, `min_size`, `max_size`, `initiator_bytes`, `initiator_packets`, `responder_bytes`, `responder_packets`: integers
+    /// * `mean_size`, `bytes_per_second`, `packets_per_second`: numbers
+    /// * `first_timestamp`, `last_timestamp`: strings in RFC 3339 format (UTC)
+    ///
+    /// Fields without a value (e.g. the ports of an ICMP connection or the throughput of a single packet connection) are `null`
+    Json,
+    /// Newline-delimited JSON: one connection object per line, with the fields described for [ReportFormat::Json]
+    /// plus `window_start`, `null` for the lifetime totals and the start of the window for the rows of a time window.
+    /// The capture statistics are not included, the changes since the previous report are added to the lifetime totals
+    /// as described for [ReportFormat::Json] and followed by the connections that disappeared
+    Ndjson,
+    /// A single self-contained HTML page, without external assets: the capture summary, a pie chart of the bytes per protocol,
+    /// a bar chart of the hosts that sent the most bytes and the tables of the report. The rows of the tables can be sorted
+    /// by clicking on a column header and filtered by text, the page reloads itself every time the report is rewritten
+    /// keeping the sorting and the filters
+    Html,
+}
+
+/// Version of the schema of the JSON and NDJSON reports, incremented when fields are changed or removed
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+/// Header row of the CSV reports
+const CSV_HEADER: [&str; 21] = [
+    "window_start", "interface", "source_address", "destination_address", "source_port", "destination_port",
+    "protocol", "ip_protocol", "bytes", "packets", "min_size", "max_size", "mean_size", "bytes_per_second",
+    "packets_per_second", "initiator_bytes", "initiator_packets", "responder_bytes", "responder_packets",
+    "first_timestamp", "last_timestamp",
+];
+
+/// Columns added to the header row of the CSV reports that include the changes since the previous report
+const CSV_DELTA_HEADER: [&str; 5] = ["change", "delta_bytes", "delta_packets", "delta_bytes_per_second", "delta_packets_per_second"];
+
+impl ReportFormat {
+    /// Returns the extension of the report files written in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ReportFormat::Markdown => "md",
+            ReportFormat::Csv => "csv",
+            ReportFormat::Json => "json",
+            ReportFormat::Ndjson => "ndjson",
+            ReportFormat::Html => "html",
+        }
+    }
+
+    /// Writes the report of the aggregated data, followed by the data of each time window if given
+    /// # Arguments
+    /// * `output` - The destination of the report
+    /// * `aggregated_data` - Aggregated data that have as key [Connection] and as a value [ConnectionMetadata]
+    /// * `windowed_data` - Data aggregated per time window, from the oldest to the most recent window
+    /// # Errors
+    /// If the report cannot be written to `output`
+    /// # Remarks
+    /// [Report::render] also writes the capture statistics and the changes since the previous report, with the rows
+    /// selected and sorted by the [ReportOptions](crate::report_options::ReportOptions) of the report
+    pub fn write<W: Write>(&self, output: &mut W, aggregated_data: &HashMap<Connection, ConnectionMetadata>, windowed_data: Option<&WindowedData>) -> Result<()> {
+        let report = Report { name: "", format: *self, aggregated_data, windowed_data, options: &Default::default(), capture_stats: None, delta: None, rewrite_time: None };
+        write_report(output, &report)
+    }
+}
+
+/// Writes the report in its [ReportFormat]
+pub(crate) fn write_report<W: Write>(output: &mut W, report: &Report) -> Result<()> {
+    let options = report.options;
+    match report.format {
+        ReportFormat::Markdown => {
+            if let Some(capture_stats) = report.capture_stats {
+                write_markdown_summary(output, capture_stats)?;
+            }
+            if let Some(delta) = report.delta {
+                write_markdown_changes(output, delta, &options.select_changes(delta))?;
+            }
+            if report.capture_stats.is_some() || report.delta.is_some() {
+                writeln!(output, "## Connections\n")?;
+            }
+            write_markdown_table(output, &options.select(report.aggregated_data))?;
+            for (start, entries) in report.windowed_data.into_iter().flatten() {
+                writeln!(output, "\n### Window starting at {}\n", start.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"))?;
+                write_markdown_table(output, &options.select(entries))?;
+            }
+        },
+        ReportFormat::Csv => {
+            let delta_header = report.delta.map(|_| CSV_DELTA_HEADER.iter()).into_iter().flatten();
+            write_csv_record(output, CSV_HEADER.iter().chain(delta_header).map(|field| field.to_string()))?;
+            for (conn, data) in options.select(report.aggregated_data) {
+                write_csv_record(output, csv_fields(None, conn, data).into_iter().chain(csv_delta_fields(report.delta, conn)))?;
+            }
+            if let Some(delta) = report.delta {
+                for (conn, data) in options.select(&delta.disappeared) {
+                    write_csv_record(output, csv_fields(None, conn, data).into_iter().chain(csv_delta_fields(report.delta, conn)))?;
+                }
+            }
+            //the rows of the windows leave the changes empty
+            let empty_delta = vec![String::new(); report.delta.map_or(0, |_| CSV_DELTA_HEADER.len())];
+            for (start, entries) in report.windowed_data.into_iter().flatten() {
+                for (conn, data) in options.select(entries) {
+                    write_csv_record(output, csv_fields(Some(start), conn, data).into_iter().chain(empty_delta.clone()))?;
+                }
+            }
+        },
+        ReportFormat::Json => {
+            let mut document = json!({
+                "schema_version": JSON_SCHEMA_VERSION,
+                "generated_at": rfc3339_timestamp(&Utc::now()),
+                "connections": json_connections(&options.select(report.aggregated_data)),
+            });
+            if let Some(capture_stats) = report.capture_stats {
+                document["summary"] = json_summary(capture_stats);
+            }
+            if let Some(delta) = report.delta {
+                document["changes"] = json_changes(report, delta);
+            }
+            if let Some(windowed_data) = report.windowed_data {
+                document["windows"] = windowed_data.iter()
+                    .map(|(start, entries)| json!({ "start": rfc3339_timestamp(start), "connections": json_connections(&options.select(entries)) }))
+                    .collect();
+            }
+            serde_json::to_writer_pretty(&mut *output, &document)?;
+            writeln!(output)?;
+        },
+        ReportFormat::Ndjson => {
+            let mut lines = Vec::new();
+            for (conn, data) in options.select(report.aggregated_data) {
+                lines.push((None, conn, data));
+            }
+            for (conn, data) in report.delta.map(|delta| options.select(&delta.disappeared)).into_iter().flatten() {
+                lines.push((None, conn, data));
+            }
+            for (start, entries) in report.windowed_data.into_iter().flatten() {
+                for (conn, data) in options.select(entries) {
+                    lines.push((Some(start), conn, data));
+                }
+            }
+            for (window_start, conn, data) in lines {
+                let mut line = json_connection(conn, data);
+                line["window_start"] = json!(window_start.map(rfc3339_timestamp));
+                if let (Some(delta), None) = (report.delta, window_start) {
+                    add_json_delta(&mut line, &delta.changes[conn]);
+                }
+                serde_json::to_writer(&mut *output, &line)?;
+                writeln!(output)?;
+            }
+        },
+        ReportFormat::Html => write_html(output, report)?,
+    }
+    output.flush()
+}
+
+/// Prints the capture statistics as a list, followed by a table with a row for each protocol
+fn write_markdown_summary<W: Write>(output: &mut W, capture_stats: &CaptureStats) -> Result<()> {
+    let elapsed = capture_stats.elapsed().as_secs();
+    writeln!(output, "## Capture summary\n")?;
+    writeln!(output, "* Device: {}", capture_stats.device)?;
+    writeln!(output, "* Start time: {}", capture_stats.start_time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"))?;
+    writeln!(output, "* Elapsed time: {:02}:{:02}:{:02}", elapsed / 3600, elapsed / 60 % 60, elapsed % 60)?;
+    writeln!(output, "* Packets: {} ({} bytes)", capture_stats.packets, capture_stats.bytes)?;
+    writeln!(output, "* Skipped packets (not IP or with invalid headers): {}", capture_stats.skipped)?;
+    match (capture_stats.dropped, capture_stats.interface_dropped) {
+        (Some(dropped), Some(interface_dropped)) => writeln!(output, "* Dropped packets: {} by libpcap, {} by the interface", dropped, interface_dropped)?,
+        _ => writeln!(output, "* Dropped packets: not available")?,
+    }
+    if let Some(error) = &capture_stats.error {
+        writeln!(output, "* Capture stopped early: {}", error)?;
+    }
+    writeln!(output, "\n|  Protocol  |  Packets  |    Bytes      |")?;
+    writeln!(output, "| :--------: | :-------: | :-----------: |")?;
+    for (ip_number, protocol) in capture_stats.protocols.iter() {
+        writeln!(output, "| {} | {} | {} |", protocol_name(*ip_number), protocol.packets, protocol.bytes)?;
+    }
+    writeln!(output)
+}
+
+/// Returns the name of the protocol with the given IP protocol number, ICMP messages of any type included
+pub(crate) fn protocol_name(ip_number: u8) -> String {
+    match ip_number {
+        1 => String::from("ICMPv4"),
+        58 => String::from("ICMPv6"),
+        number => Protocol::from_ip_number(number).to_string(),
+    }
+}
+
+/// Prints a markdown table with a row for each connection that changed since the previous report
+fn write_markdown_changes<W: Write>(output: &mut W, delta: &DeltaReport, changes: &[(&Connection, &ConnectionDelta)]) -> Result<()> {
+    writeln!(output, "## Changes in the last {:.1} s\n", delta.interval.as_secs_f64())?;
+    writeln!(output, "|  Interface  |   Src IP address  |  Dst IP address   |  Src port |  Dst port |  Protocol |   Change    |    Bytes      |  Packets  |    Bytes/s    | Packets/s |")?;
+    writeln!(output, "| :---------: | :---------------: | :---------------: | :-------: | :-------: | :-------: | :---------: | :-----------: | :-------: | :-----------: | :-------: |")?;
+    for (conn, change) in changes {
+        writeln!(output, "{}{}", conn, change)?;
+    }
+    writeln!(output)
+}
+
+/// Prints a markdown table with a row for each entry of the aggregated data
+fn write_markdown_table<W: Write>(output: &mut W, entries: &[(&Connection, &ConnectionMetadata)]) -> Result<()> {
+    writeln!(output, "|  Interface  |   Src IP address  |  Dst IP address   |  Src port |  Dst port |  Protocol |    Bytes      |  Packets  |  Min size |  Max size | Mean size |    Bytes/s    | Packets/s | Initiator bytes | Initiator packets | Responder bytes | Responder packets |  Initial timestamp    |   Final timestamp  |")?;
+    writeln!(output, "| :---------: | :---------------: | :---------------: | :-------: | :-------: | :-------: | :-----------: | :-------: | :-------: | :-------: | :-------: | :-----------: | :-------: | :-------------: | :---------------: | :-------------: | :---------------: | :-------------------: | :----------------: |")?;
+
+    for (conn, data) in entries {
+        writeln!(output, "{}{}", conn, data)?;
+    }
+    Ok(())
+}
+
+/// Returns the fields of the CSV record of an entry of the aggregated data
+fn csv_fields(window_start: Option<&DateTime<Utc>>, conn: &Connection, data: &ConnectionMetadata) -> Vec<String> {
+    let optional = |value: Option<String>| value.unwrap_or_default();
+    vec![
+        optional(window_start.map(rfc3339_timestamp)),
+        optional(conn.interface.clone()),
+        optional(conn.source_address()),
+        optional(conn.destination_address()),
+        optional(conn.source_port.map(|port| port.to_string())),
+        optional(conn.destination_port.map(|port| port.to_string())),
+        optional(conn.protocol.map(|protocol| protocol.to_string())),
+        optional(conn.protocol.map(|protocol| protocol.ip_number().to_string())),
+        data.size.to_string(),
+        data.packets.to_string(),
+        data.min_size.to_string(),
+        data.max_size.to_string(),
+        data.mean_size().to_string(),
+        optional(data.bytes_per_second().map(|x| x.to_string())),
+        optional(data.packets_per_second().map(|x| x.to_string())),
+        data.initiator_size.to_string(),
+        data.initiator_packets.to_string(),
+        data.responder_size.to_string(),
+        data.responder_packets.to_string(),
+        rfc3339_timestamp(&data.first_timestamp),
+        rfc3339_timestamp(&data.last_timestamp),
+    ]
+}
+
+/// Returns the fields of the changes of a connection since the previous report, none if the report does not include them
+fn csv_delta_fields(delta: Option<&DeltaReport>, conn: &Connection) -> Vec<String> {
+    let optional = |value: Option<f64>| value.map(|x| x.to_string()).unwrap_or_default();
+    match delta.and_then(|delta| delta.changes.get(conn)) {
+        Some(change) => vec![
+            change.status.to_string(),
+            change.bytes.to_string(),
+            change.packets.to_string(),
+            optional(change.bytes_per_second),
+            optional(change.packets_per_second),
+        ],
+        None => Vec::new(),
+    }
+}
+
+pub(crate) fn rfc3339_timestamp(timestamp: &DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+/// Prints a CSV record terminated by CRLF, quoting the fields that contain commas, quotes or line breaks
+fn write_csv_record<W: Write>(output: &mut W, fields: impl IntoIterator<Item = String>) -> Result<()> {
+    let record: Vec<String> = fields.into_iter().map(|field| {
+        if field.contains([',', '"', '\r', '\n']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field
+        }
+    }).collect();
+    write!(output, "{}\r\n", record.join(","))
+}
+
+/// Returns the JSON object of the capture statistics, as documented in [ReportFormat::Json]
+fn json_summary(capture_stats: &CaptureStats) -> Value {
+    let protocols: Value = capture_stats.protocols.iter().map(|(ip_number, protocol)| json!({
+        "protocol": protocol_name(*ip_number),
+        "ip_protocol": ip_number,
+        "packets": protocol.packets,
+        "bytes": protocol.bytes,
+    })).collect();
+    json!({
+        "device": capture_stats.device,
+        "start_time": rfc3339_timestamp(&capture_stats.start_time),
+        "elapsed_seconds": capture_stats.elapsed().as_secs_f64(),
+        "packets": capture_stats.packets,
+        "bytes": capture_stats.bytes,
+        "skipped_packets": capture_stats.skipped,
+        "dropped_packets": capture_stats.dropped,
+        "interface_dropped_packets": capture_stats.interface_dropped,
+        "error": capture_stats.error,
+        "protocols": protocols,
+    })
+}
+
+/// Returns the JSON object of the changes since the previous report, as documented in [ReportFormat::Json]
+fn json_changes(report: &Report, delta: &DeltaReport) -> Value {
+    let connections: Value = report.options.select_changes(delta).into_iter()
+        .filter_map(|(conn, change)| {
+            let data = report.aggregated_data.get(conn).or_else(|| delta.disappeared.get(conn))?;
+            let mut connection = json_connection(conn, data);
+            add_json_delta(&mut connection, change);
+            Some(connection)
+        })
+        .collect();
+    json!({ "interval_seconds": delta.interval.as_secs_f64(), "connections": connections })
+}
+
+/// Adds the fields of the change since the previous report to the JSON object of a connection
+fn add_json_delta(connection: &mut Value, change: &ConnectionDelta) {
+    connection["change"] = json!(change.status.to_string());
+    connection["delta_bytes"] = json!(change.bytes);
+    connection["delta_packets"] = json!(change.packets);
+    connection["delta_bytes_per_second"] = json!(change.bytes_per_second);
+    connection["delta_packets_per_second"] = json!(change.packets_per_second);
+}
+
+/// Returns the JSON array of the connections of the aggregated data
+fn json_connections(entries: &[(&Connection, &ConnectionMetadata)]) -> Value {
+    entries.iter().map(|(conn, data)| json_connection(conn, data)).collect()
+}
+
+/// Returns the JSON object describing a connection, as documented in [ReportFormat::Json]
+fn json_connection(conn: &Connection, data: &ConnectionMetadata) -> Value {
+    let (icmp_type, icmp_code) = match conn.protocol {
+        Some(Protocol::Icmpv4 { icmp_type, code }) | Some(Protocol::Icmpv6 { icmp_type, code }) => (Some(icmp_type), Some(code)),
+        _ => (None, None),
+    };
+    json!({
+        "interface": conn.interface,
+        "source_address": conn.source_address(),
+        "destination_address": conn.destination_address(),
+        "source_port": conn.source_port,
+        "destination_port": conn.destination_port,
+        "protocol": conn.protocol.map(|protocol| protocol.to_string()),
+        "ip_protocol": conn.protocol.map(|protocol| protocol.ip_number()),
+        "icmp_type": icmp_type,
+        "icmp_code": icmp_code,
+        "bytes": data.size,
+        "packets": data.packets,
+        "min_size": data.min_size,
+        "max_size": data.max_size,
+        "mean_size": data.mean_size(),
+        "bytes_per_second": data.bytes_per_second(),
+        "packets_per_second": data.packets_per_second(),
+        "initiator_bytes": data.initiator_size,
+        "initiator_packets": data.initiator_packets,
+        "responder_bytes": data.responder_size,
+        "responder_packets": data.responder_packets,
+        "first_timestamp": rfc3339_timestamp(&data.first_timestamp),
+        "last_timestamp": rfc3339_timestamp(&data.last_timestamp),
+    })
+}
@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::io::{Result, Write};
+use chrono::{DateTime, Local, Utc};
+
+use crate::parser::CaptureStats;
+use crate::report_entry::{Connection, ConnectionDelta, ConnectionMetadata};
+use crate::report_format::{protocol_name, rfc3339_timestamp};
+use crate::report_sink::Report;
+
+/// Number of hosts shown in the top talkers chart
+const TOP_TALKERS: usize = 10;
+
+/// Colors of the slices and bars of the charts
+const PALETTE: [&str; 10] = ["#4e79a7", "#f28e2b", "#e15759", "#76b7b2", "#59a14f", "#edc948", "#b07aa1", "#ff9da7", "#9c755f", "#bab0ac"];
+
+const STYLE: &str = "body{font-family:sans-serif;margin:1.5em;color:#222}\
+table{border-collapse:collapse;font-size:0.85em;margin-bottom:1.5em}\
+th,td{border:1px solid #ccc;padding:0.25em 0.5em;text-align:right}\
+td.text{text-align:left}\
+th{background:#eee;cursor:pointer;user-select:none}\
+th.asc::after{content:' \\25B2'}th.desc::after{content:' \\25BC'}\
+.charts{display:flex;flex-wrap:wrap;gap:3em;align-items:flex-start}\
+.legend{font-size:0.85em}.legend span{display:inline-block;width:0.8em;height:0.8em;margin-right:0.4em}\
+input.filter{margin-bottom:0.5em;width:20em}";
+
+/// Sorts a table when a header is clicked and filters its rows with the text of the input before it,
+/// the state is kept across the reloads of the page
+const SCRIPT: &str = r#"
+document.querySelectorAll('table.connections').forEach(function (table, index) {
+  var key = 'network-analyzer-' + index;
+  var state = JSON.parse(sessionStorage.getItem(key) || '{}');
+  var body = table.tBodies[0];
+  var input = table.previousElementSibling;
+  function save() { sessionStorage.setItem(key, JSON.stringify(state)); }
+  function value(row, column) {
+    var cell = row.cells[column];
+    var sort = cell.getAttribute('data-sort');
+    return sort !== null && sort !== '' && !isNaN(sort) ? parseFloat(sort) : (sort !== null ? sort : cell.textContent);
+  }
+  function sort() {
+    if (state.column === undefined) { return; }
+    var rows = Array.prototype.slice.call(body.rows);
+    rows.sort(function (a, b) {
+      var x = value(a, state.column), y = value(b, state.column);
+      var order = typeof x === 'number' && typeof y === 'number' ? x - y : String(x).localeCompare(String(y));
+      return state.descending ? -order : order;
+    });
+    rows.forEach(function (row) { body.appendChild(row); });
+    Array.prototype.forEach.call(table.tHead.rows[0].cells, function (th, column) {
+      th.className = column === state.column ? (state.descending ? 'desc' : 'asc') : '';
+    });
+  }
+  function filter() {
+    var text = (state.filter || '').toLowerCase();
+    Array.prototype.forEach.call(body.rows, function (row) {
+      row.style.display = row.textContent.toLowerCase().indexOf(text) >= 0 ? '' : 'none';
+    });
+  }
+  Array.prototype.forEach.call(table.tHead.rows[0].cells, function (th, column) {
+    th.addEventListener('click', function () {
+      state.descending = state.column === column ? !state.descending : true;
+      state.column = column;
+      save();
+      sort();
+    });
+  });
+  input.value = state.filter || '';
+  input.addEventListener('input', function () { state.filter = input.value; save(); filter(); });
+  sort();
+  filter();
+});
+"#;
+
+/// Writes a self-contained HTML page with the report: summary, charts of the bytes per protocol and of the top talkers,
+/// and sortable and filterable tables of the connections
+pub(crate) fn write_html<W: Write>(output: &mut W, report: &Report) -> Result<()> {
+    let options = report.options;
+    writeln!(output, "<!DOCTYPE html>")?;
+    writeln!(output, "<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">")?;
+    if let Some(rewrite_time) = report.rewrite_time.filter(|rewrite_time| *rewrite_time > 0) {
+        //the page is reloaded when the report is rewritten
+        writeln!(output, "<meta http-equiv=\"refresh\" content=\"{}\">", rewrite_time)?;
+    }
+    writeln!(output, "<title>{}</title>\n<style>{}</style>\n</head>\n<body>", escape(&title(report.name)), STYLE)?;
+    writeln!(output, "<h1>{}</h1>", escape(&title(report.name)))?;
+    writeln!(output, "<p>Generated at {}</p>", Local::now().format("%Y-%m-%d %H:%M:%S"))?;
+    if let Some(capture_stats) = report.capture_stats {
+        write_summary(output, capture_stats)?;
+    }
+
+    writeln!(output, "<div class=\"charts\">")?;
+    let mut protocols: HashMap<String, usize> = HashMap::new();
+    let mut talkers: HashMap<String, usize> = HashMap::new();
+    for (conn, data) in options.select(report.aggregated_data) {
+        let protocol = conn.protocol.map_or_else(|| String::from("*"), |protocol| protocol_name(protocol.ip_number()));
+        *protocols.entry(protocol).or_default() += data.size;
+        //each side of the connection is credited with the bytes it sent
+        let sides = [(conn.source_address(), data.initiator_size), (conn.destination_address(), data.responder_size)];
+        for (talker, sent) in sides {
+            if let Some(talker) = talker.filter(|_| sent > 0) {
+                *talkers.entry(talker).or_default() += sent;
+            }
+        }
+    }
+    write_pie_chart(output, "Bytes per protocol", sorted_by_value(protocols))?;
+    let mut talkers = sorted_by_value(talkers);
+    talkers.truncate(TOP_TALKERS);
+    write_bar_chart(output, "Top talkers (bytes sent)", talkers)?;
+    writeln!(output, "</div>")?;
+
+    if let Some(delta) = report.delta {
+        writeln!(output, "<h2>Changes in the last {:.1} s</h2>", delta.interval.as_secs_f64())?;
+        write_changes_table(output, &options.select_changes(delta))?;
+    }
+    writeln!(output, "<h2>Connections</h2>")?;
+    write_connections_table(output, &options.select(report.aggregated_data))?;
+    for (start, entries) in report.windowed_data.into_iter().flatten() {
+        writeln!(output, "<h3>Window starting at {}</h3>", local_time(start))?;
+        write_connections_table(output, &options.select(entries))?;
+    }
+    writeln!(output, "<script>{}</script>\n</body>\n</html>", SCRIPT)
+}
+
+fn title(name: &str) -> String {
+    if name.is_empty() { String::from("Network analyzer report") } else { format!("Network analyzer report: {}", name) }
+}
+
+/// Escapes the characters with a special meaning in HTML
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&#39;")
+}
+
+fn local_time(timestamp: &DateTime<Utc>) -> String {
+    timestamp.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Returns the entries sorted by decreasing value, then by name
+fn sorted_by_value(entries: HashMap<String, usize>) -> Vec<(String, usize)> {
+    let mut entries: Vec<(String, usize)> = entries.into_iter().collect();
+    entries.sort_by(|(name_a, value_a), (name_b, value_b)| value_b.cmp(value_a).then_with(|| name_a.cmp(name_b)));
+    entries
+}
+
+fn write_summary<W: Write>(output: &mut W, capture_stats: &CaptureStats) -> Result<()> {
+    let elapsed = capture_stats.elapsed().as_secs();
+    let dropped = match (capture_stats.dropped, capture_stats.interface_dropped) {
+        (Some(dropped), Some(interface_dropped)) => format!("{} by libpcap, {} by the interface", dropped, interface_dropped),
+        _ => String::from("not available"),
+    };
+    writeln!(output, "<h2>Capture summary</h2>\n<ul>")?;
+    writeln!(output, "<li>Device: {}</li>", escape(&capture_stats.device))?;
+    writeln!(output, "<li>Start time: {}</li>", local_time(&capture_stats.start_time))?;
+    writeln!(output, "<li>Elapsed time: {:02}:{:02}:{:02}</li>", elapsed / 3600, elapsed / 60 % 60, elapsed % 60)?;
+    writeln!(output, "<li>Packets: {} ({} bytes)</li>", capture_stats.packets, capture_stats.bytes)?;
+    writeln!(output, "<li>Skipped packets (not IP or with invalid headers): {}</li>", capture_stats.skipped)?;
+    writeln!(output, "<li>Dropped packets: {}</li>", dropped)?;
+    if let Some(error) = &capture_stats.error {
+        writeln!(output, "<li>Capture stopped early: {}</li>", escape(error))?;
+    }
+    writeln!(output, "</ul>")
+}
+
+/// Writes an SVG pie chart with a legend, slices are drawn clockwise from the top
+fn write_pie_chart<W: Write>(output: &mut W, title: &str, slices: Vec<(String, usize)>) -> Result<()> {
+    let total: usize = slices.iter().map(|(_, value)| value).sum();
+    writeln!(output, "<figure>\n<figcaption>{}</figcaption>", escape(title))?;
+    writeln!(output, "<svg width=\"220\" height=\"220\" viewBox=\"-110 -110 220 220\" role=\"img\">")?;
+    if total == 0 {
+        writeln!(output, "<circle r=\"100\" fill=\"#eee\"/>")?;
+    }
+    let mut angle: f64 = 0.0;
+    for (index, (name, value)) in slices.iter().enumerate() {
+        let color = PALETTE[index % PALETTE.len()];
+        let share = *value as f64 / total as f64;
+        if share >= 1.0 {
+            //a single slice is a full circle, an arc cannot start and end at the same point
+            writeln!(output, "<circle r=\"100\" fill=\"{}\"><title>{}: {} bytes</title></circle>", color, escape(name), value)?;
+            continue;
+        }
+        let start = angle;
+        angle += share * 2.0 * PI;
+        let (x1, y1) = (100.0 * start.sin(), -100.0 * start.cos());
+        let (x2, y2) = (100.0 * angle.sin(), -100.0 * angle.cos());
+        let large_arc = if share > 0.5 { 1 } else { 0 };
+        writeln!(output, "<path d=\"M0,0 L{:.2},{:.2} A100,100 0 {} 1 {:.2},{:.2} Z\" fill=\"{}\"><title>{}: {} bytes</title></path>",
+                 x1, y1, large_arc, x2, y2, color, escape(name), value)?;
+    }
+    writeln!(output, "</svg>\n<div class=\"legend\">")?;
+    for (index, (name, value)) in slices.iter().enumerate() {
+        writeln!(output, "<div><span style=\"background:{}\"></span>{}: {} bytes ({:.1}%)</div>",
+                 PALETTE[index % PALETTE.len()], escape(name), value, *value as f64 * 100.0 / total as f64)?;
+    }
+    writeln!(output, "</div>\n</figure>")
+}
+
+/// Writes an SVG horizontal bar chart, the longest bar is the one with the greatest value
+fn write_bar_chart<W: Write>(output: &mut W, title: &str, bars: Vec<(String, usize)>) -> Result<()> {
+    let max = bars.iter().map(|(_, value)| *value).max().unwrap_or(0).max(1);
+    writeln!(output, "<figure>\n<figcaption>{}</figcaption>", escape(title))?;
+    writeln!(output, "<svg width=\"520\" height=\"{}\" role=\"img\" font-size=\"12\">", bars.len().max(1) * 22)?;
+    for (index, (name, value)) in bars.iter().enumerate() {
+        let y = index * 22;
+        let width = (*value as f64 / max as f64 * 220.0).max(1.0);
+        writeln!(output, "<text x=\"195\" y=\"{}\" text-anchor=\"end\">{}</text>", y + 15, escape(name))?;
+        writeln!(output, "<rect x=\"200\" y=\"{}\" width=\"{:.1}\" height=\"18\" fill=\"{}\"><title>{}: {} bytes</title></rect>",
+                 y + 2, width, PALETTE[0], escape(name), value)?;
+        writeln!(output, "<text x=\"{:.1}\" y=\"{}\">{}</text>", 205.0 + width, y + 15, value)?;
+    }
+    writeln!(output, "</svg>\n</figure>")
+}
+
+/// Writes a table cell, `sort` is the value used to sort the column when it differs from the text
+fn cell(text: &str, sort: Option<String>, is_text: bool) -> String {
+    let class = if is_text { " class=\"text\"" } else { "" };
+    match sort {
+        Some(sort) => format!("<td{} data-sort=\"{}\">{}</td>", class, escape(&sort), escape(text)),
+        None => format!("<td{}>{}</td>", class, escape(text)),
+    }
+}
+
+/// Returns the cells of the columns identifying a connection
+fn connection_cells(conn: &Connection) -> String {
+    let optional = |value: Option<String>, missing: &str| value.unwrap_or_else(|| String::from(missing));
+    let port = |port: Option<u16>| (conn.port_label(port), Some(port.map(|port| port.to_string()).unwrap_or_default()));
+    let (source_port, source_port_sort) = port(conn.source_port);
+    let (destination_port, destination_port_sort) = port(conn.destination_port);
+    [
+        cell(&optional(conn.interface.clone(), "-"), None, true),
+        cell(&optional(conn.source_address(), "*"), None, true),
+        cell(&optional(conn.destination_address(), "*"), None, true),
+        cell(&source_port, source_port_sort, false),
+        cell(&destination_port, destination_port_sort, false),
+        cell(&optional(conn.protocol.map(|protocol| protocol.to_string()), "*"), None, true),
+    ].concat()
+}
+
+const CONNECTION_HEADER: &str = "<th>Interface</th><th>Src IP address</th><th>Dst IP address</th><th>Src port</th><th>Dst port</th><th>Protocol</th>";
+
+fn write_connections_table<W: Write>(output: &mut W, entries: &[(&Connection, &ConnectionMetadata)]) -> Result<()> {
+    let rate = |rate: Option<f64>, precision: usize| match rate {
+        Some(x) => (format!("{:.*}", precision, x), Some(x.to_string())),
+        None => (String::from("-"), Some(String::new())),
+    };
+    writeln!(output, "<input class=\"filter\" type=\"search\" placeholder=\"Filter rows\">")?;
+    writeln!(output, "<table class=\"connections\">\n<thead><tr>{}<th>Bytes</th><th>Packets</th><th>Min size</th><th>Max size</th><th>Mean size</th><th>Bytes/s</th><th>Packets/s</th><th>Initiator bytes</th><th>Initiator packets</th><th>Responder bytes</th><th>Responder packets</th><th>Initial timestamp</th><th>Final timestamp</th></tr></thead>\n<tbody>", CONNECTION_HEADER)?;
+    for (conn, data) in entries {
+        let (bytes_per_second, bytes_per_second_sort) = rate(data.bytes_per_second(), 1);
+        let (packets_per_second, packets_per_second_sort) = rate(data.packets_per_second(), 2);
+        writeln!(output, "<tr>{}{}{}{}{}{}{}{}{}{}{}{}{}{}</tr>",
+                 connection_cells(conn),
+                 cell(&data.size.to_string(), None, false),
+                 cell(&data.packets.to_string(), None, false),
+                 cell(&data.min_size.to_string(), None, false),
+                 cell(&data.max_size.to_string(), None, false),
+                 cell(&format!("{:.1}", data.mean_size()), Some(data.mean_size().to_string()), false),
+                 cell(&bytes_per_second, bytes_per_second_sort, false),
+                 cell(&packets_per_second, packets_per_second_sort, false),
+                 cell(&data.initiator_size.to_string(), None, false),
+                 cell(&data.initiator_packets.to_string(), None, false),
+                 cell(&data.responder_size.to_string(), None, false),
+                 cell(&data.responder_packets.to_string(), None, false),
+                 cell(&local_time(&data.first_timestamp), Some(rfc3339_timestamp(&data.first_timestamp)), true),
+                 cell(&local_time(&data.last_timestamp), Some(rfc3339_timestamp(&data.last_timestamp)), true))?;
+    }
+    writeln!(output, "</tbody>\n</table>")
+}
+
+fn write_changes_table<W: Write>(output: &mut W, changes: &[(&Connection, &ConnectionDelta)]) -> Result<()> {
+    let rate = |rate: Option<f64>, precision: usize| match rate {
+        Some(x) => (format!("{:.*}", precision, x), Some(x.to_string())),
+        None => (String::from("-"), Some(String::new())),
+    };
+    writeln!(output, "<input class=\"filter\" type=\"search\" placeholder=\"Filter rows\">")?;
+    writeln!(output, "<table class=\"connections\">\n<thead><tr>{}<th>Change</th><th>Bytes</th><th>Packets</th><th>Bytes/s</th><th>Packets/s</th></tr></thead>\n<tbody>", CONNECTION_HEADER)?;
+    for (conn, change) in changes {
+        let (bytes_per_second, bytes_per_second_sort) = rate(change.bytes_per_second, 1);
+        let (packets_per_second, packets_per_second_sort) = rate(change.packets_per_second, 2);
+        writeln!(output, "<tr>{}{}{}{}{}{}</tr>",
+                 connection_cells(conn),
+                 cell(&change.status.to_string(), None, true),
+                 cell(&change.bytes.to_string(), None, false),
+                 cell(&change.packets.to_string(), None, false),
+                 cell(&bytes_per_second, bytes_per_second_sort, false),
+                 cell(&packets_per_second, packets_per_second_sort, false))?;
+    }
+    writeln!(output, "</tbody>\n</table>")
+}
@@ -0,0 +1,179 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use crate::parsed_packet::Protocol;
+use crate::report_entry::{ChangeStatus, Connection, ConnectionDelta, ConnectionMetadata, DeltaReport};
+
+/// Enum describing the order of the rows of a report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    /// Number of bytes, the busiest connections first
+    Bytes,
+    /// Number of packets, the busiest connections first
+    Packets,
+    /// Timestamp of the first packet, the oldest connections first
+    FirstSeen,
+    /// Timestamp of the last packet, the most recently seen connections first
+    LastSeen,
+    /// Source address, then destination address and ports, in ascending order (IPv4 addresses before IPv6 ones)
+    Address,
+}
+
+/// Struct describing an inclusive range of IP addresses of the same family
+///
+/// # Examples
+/// Basic usage:
+/// ```rust
+/// use Network_analyzer::report_options::AddressRange;
+///
+/// let lan = AddressRange::from_cidr("192.168.1.0".parse().unwrap(), 24);
+/// assert!(lan.contains("192.168.1.42".parse().unwrap()));
+/// let hosts = AddressRange::new("10.0.0.1".parse().unwrap(), "10.0.0.9".parse().unwrap());
+/// assert!(!hosts.contains("10.0.0.10".parse().unwrap()));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressRange {
+    first: IpAddr,
+    last: IpAddr,
+}
+
+impl AddressRange {
+    /// Creates the range of the addresses from `first` to `last`, both included
+    /// # Panics
+    /// If the addresses are not of the same family or `first` is greater than `last`
+    pub fn new(first: IpAddr, last: IpAddr) -> Self {
+        assert_eq!(first.is_ipv4(), last.is_ipv4(), "the addresses of a range must be of the same family");
+        assert!(first <= last, "the first address of a range cannot be greater than the last one");
+        AddressRange { first, last }
+    }
+
+    /// Creates the range of the addresses of the network `address/prefix`
+    /// # Panics
+    /// If the prefix is longer than the address (32 bits for IPv4, 128 bits for IPv6)
+    pub fn from_cidr(address: IpAddr, prefix: u8) -> Self {
+        match address {
+            IpAddr::V4(address) => {
+                assert!(prefix <= 32, "IPv4 prefix length cannot exceed 32");
+                let host_mask = u32::MAX.checked_shr(prefix as u32).unwrap_or(0);
+                let first = u32::from(address) & !host_mask;
+                AddressRange { first: IpAddr::from(first.to_be_bytes()), last: IpAddr::from((first | host_mask).to_be_bytes()) }
+            },
+            IpAddr::V6(address) => {
+                assert!(prefix <= 128, "IPv6 prefix length cannot exceed 128");
+                let host_mask = u128::MAX.checked_shr(prefix as u32).unwrap_or(0);
+                let first = u128::from(address) & !host_mask;
+                AddressRange { first: IpAddr::from(first.to_be_bytes()), last: IpAddr::from((first | host_mask).to_be_bytes()) }
+            },
+        }
+    }
+
+    /// Returns `true` if the address is in the range
+    pub fn contains(&self, address: IpAddr) -> bool {
+        self.first <= address && address <= self.last
+    }
+}
+
+/// Struct describing which rows of a report are written and in which order, applied to every table of the report
+///
+/// A connection is written if it satisfies every filter that is set (an empty list matches every connection).
+/// Fields that are not part of the aggregation key (see [AggregationKey](crate::aggregator::AggregationKey)) never match a filter,
+/// the addresses of networks match if the network address is in the range.
+///
+/// # Examples
+/// The ten TCP connections to or from the local network that transferred the most bytes:
+/// ```rust
+/// use Network_analyzer::parsed_packet::Protocol;
+/// use Network_analyzer::report_options::{AddressRange, ReportOptions, SortKey};
+///
+/// let options = ReportOptions {
+///     sort: Some(SortKey::Bytes),
+///     top: Some(10),
+///     protocols: vec![Protocol::Tcp],
+///     addresses: vec![AddressRange::from_cidr("192.168.1.0".parse().unwrap(), 24)],
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReportOptions {
+    /// Order of the rows, `None` to write them in the arbitrary order of the aggregated data
+    pub sort: Option<SortKey>,
+    /// If `true` the order given by `sort` is reversed
+    pub reverse: bool,
+    /// Maximum number of rows of each table, the first ones after sorting and filtering are written
+    pub top: Option<usize>,
+    /// Protocols of the connections to write, ICMP protocols match regardless of type and code
+    pub protocols: Vec<Protocol>,
+    /// Ports of the connections to write, matching either the source or the destination port
+    pub ports: Vec<u16>,
+    /// Ranges of the addresses of the connections to write, matching either the source or the destination address
+    pub addresses: Vec<AddressRange>,
+}
+
+impl ReportOptions {
+    /// Returns `true` if the connection satisfies the filters
+    pub fn matches(&self, conn: &Connection) -> bool {
+        let protocol_matches = self.protocols.is_empty() || conn.protocol.is_some_and(|protocol| {
+            self.protocols.iter().any(|filter| filter.ip_number() == protocol.ip_number())
+        });
+        let port_matches = self.ports.is_empty() || [conn.source_port, conn.destination_port].into_iter().flatten()
+            .any(|port| self.ports.contains(&port));
+        let address_matches = self.addresses.is_empty() || [conn.source_ip, conn.destination_ip].into_iter().flatten()
+            .any(|address| self.addresses.iter().any(|range| range.contains(address)));
+        protocol_matches && port_matches && address_matches
+    }
+
+    /// Returns the rows of a table of the report: the entries that satisfy the filters, sorted and limited to the top entries
+    /// # Arguments
+    /// * `entries` - Aggregated data that have as key [Connection] and as a value [ConnectionMetadata]
+    pub fn select<'a>(&self, entries: &'a HashMap<Connection, ConnectionMetadata>) -> Vec<(&'a Connection, &'a ConnectionMetadata)> {
+        let mut rows: Vec<(&Connection, &ConnectionMetadata)> = entries.iter().filter(|(conn, _)| self.matches(conn)).collect();
+        if let Some(sort) = self.sort {
+            //ties are broken by address, so that the order does not depend on the order of the map
+            rows.sort_by(|(conn_a, data_a), (conn_b, data_b)| {
+                let ordering = match sort {
+                    SortKey::Bytes => data_b.size.cmp(&data_a.size),
+                    SortKey::Packets => data_b.packets.cmp(&data_a.packets),
+                    SortKey::FirstSeen => data_a.first_timestamp.cmp(&data_b.first_timestamp),
+                    SortKey::LastSeen => data_b.last_timestamp.cmp(&data_a.last_timestamp),
+                    SortKey::Address => Ordering::Equal,
+                };
+                let ordering = ordering.then_with(|| address_order(conn_a, conn_b));
+                if self.reverse { ordering.reverse() } else { ordering }
+            });
+        }
+        if let Some(top) = self.top {
+            rows.truncate(top);
+        }
+        rows
+    }
+
+    /// Returns the rows of the table of the changes since the previous report: the connections that changed and satisfy the filters,
+    /// limited to the top entries
+    /// # Remarks
+    /// The rows are sorted by the bytes exchanged since the previous report, by the packets if `sort` is [SortKey::Packets]
+    /// or by address if it is [SortKey::Address]
+    pub fn select_changes<'a>(&self, delta: &'a DeltaReport) -> Vec<(&'a Connection, &'a ConnectionDelta)> {
+        let mut rows: Vec<(&Connection, &ConnectionDelta)> = delta.changes.iter()
+            .filter(|(conn, change)| change.status != ChangeStatus::Unchanged && self.matches(conn))
+            .collect();
+        rows.sort_by(|(conn_a, change_a), (conn_b, change_b)| {
+            let ordering = match self.sort {
+                Some(SortKey::Packets) => change_b.packets.cmp(&change_a.packets),
+                Some(SortKey::Address) => Ordering::Equal,
+                _ => change_b.bytes.cmp(&change_a.bytes),
+            };
+            let ordering = ordering.then_with(|| address_order(conn_a, conn_b));
+            if self.reverse { ordering.reverse() } else { ordering }
+        });
+        if let Some(top) = self.top {
+            rows.truncate(top);
+        }
+        rows
+    }
+}
+
+fn address_order(a: &Connection, b: &Connection) -> Ordering {
+    (a.source_ip, a.destination_ip, a.source_port, a.destination_port, a.protocol, &a.interface)
+        .cmp(&(b.source_ip, b.destination_ip, b.source_port, b.destination_port, b.protocol, &b.interface))
+}
@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{stdout, BufRead, BufReader, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use chrono::{Duration, Utc};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use std::sync::{Arc, Mutex};
+
+use crate::aggregator::WindowedData;
+use crate::parser::CaptureStats;
+use crate::report_entry::{Connection, ConnectionMetadata, DeltaReport};
+use crate::report_format::{write_report, ReportFormat};
+use crate::report_options::ReportOptions;
+
+/// Struct holding everything a [ReportSink] needs to write a report
+pub struct Report<'a> {
+    /// The name of the report, as set by [ReportWriter::set_report_path]
+    pub name: &'a str,
+    /// The format of the report, as set by [ReportWriter::set_format]
+    pub format: ReportFormat,
+    /// Aggregated data that have as key [Connection] and as a value [ConnectionMetadata]
+    pub aggregated_data: &'a HashMap<Connection, ConnectionMetadata>,
+    /// Data aggregated per time window, if the report includes it
+    pub windowed_data: Option<&'a WindowedData>,
+    /// The sorting and the filters of the rows, as set by [ReportWriter::set_options]
+    pub options: &'a ReportOptions,
+    /// The statistics of the capture, as set by [ReportWriter::set_capture_stats]
+    pub capture_stats: Option<&'a CaptureStats>,
+    /// The changes since the previous report, if enabled with [ReportWriter::set_delta_reports]
+    pub delta: Option<&'a DeltaReport>,
+    /// The period in seconds between two reports, as set by [ReportWriter::set_rewrite_time], `None` if the report is not rewritten
+    pub rewrite_time: Option<u64>,
+}
+
+impl Report<'_> {
+    /// Returns the report rendered in its [ReportFormat]
+    pub fn render(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+        write_report(&mut output, self).expect("writing to memory cannot fail");
+        output
+    }
+}
+
+/// Trait for the destinations of the reports of a [ReportWriter], that calls [ReportSink::write_report] on every tick
+///
+/// # Examples
+/// A sink that only prints the number of connections:
+/// ```rust
+/// use std::io::Result;
+/// use Network_analyzer::report_sink::{Report, ReportSink};
+///
+/// struct CountSink;
+///
+/// impl ReportSink for CountSink {
+///     fn write_report(&mut self, report: &Report) -> Result<()> {
+///         println!("{}: {} connections", report.name, report.aggregated_data.len());
+///         Ok(())
+///     }
+/// }
+/// ```
+pub trait ReportSink: Send {
+    /// Writes a report, a sink is free to ignore the [ReportFormat] of the report and render the data itself
+    /// # Errors
+    /// If the report cannot be written
+    fn write_report(&mut self, report: &Report) -> Result<()>;
+}
+
+/// Enum describing how a [FileSink] keeps the history of the reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rotation {
+    /// Every report replaces the previous one in `[name].[extension]` (the default)
+    #[default]
+    Overwrite,
+    /// Every report is written to a new archive `[name]-[timestamp].[extension]`, the timestamp being the UTC time of the report
+    Timestamped,
+    /// Reports are appended to `[name].[extension]`, that is moved to the archive `[name]-[timestamp].[extension]`
+    /// before it grows beyond the given number of bytes
+    ///
+    /// Only the formats that stay valid when concatenated can be appended: writing a [ReportFormat::Json] or a [ReportFormat::Html]
+    /// report fails, use [ReportFormat::Ndjson] or [Rotation::Timestamped] instead.
+    /// A CSV file keeps a single header row, it is also archived when the columns of the reports change (e.g. with delta reports).
+    /// Appended reports are not written atomically, a reader of the current file may observe a partially appended report
+    Size(u64),
+    /// Reports are appended to `[name].[extension]`, that is moved to the archive `[name]-[timestamp].[extension]`
+    /// once it holds the given number of reports, see [Rotation::Size] for the formats that can be appended.
+    /// A file left by a previous [FileSink] (e.g. before a restart) is archived at the first report, so that no file
+    /// holds more than the given number of reports
+    Count(usize),
+}
+
+/// Struct describing the rotation and the archiving of the reports of a [FileSink]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RotationPolicy {
+    pub rotation: Rotation,
+    /// If `true` the archives are compressed with gzip, `.gz` is appended to their name
+    /// (with [Rotation::Timestamped] the most recent report is left uncompressed)
+    pub compress: bool,
+    /// If set, the number of archives kept, the oldest ones are deleted
+    pub retention: Option<usize>,
+}
+
+/// Format of the timestamp in the names of the archives, in UTC
+/// (archives created in the same millisecond get the next free timestamp)
+const ARCHIVE_TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+/// [ReportSink] writing each report on the file `[directory]/[name].[extension]`, replacing the previous one (the default sink
+/// writes to `report/[name].[extension]`, with the extension of the [ReportFormat])
+///
+/// Each report is first written to a temporary file in the same directory, that is then renamed over the previous report,
+/// so that readers of the file never observe a partially written report (except for the reports appended by [Rotation::Size] and [Rotation::Count]).
+/// A [RotationPolicy] can be given to keep the history of the reports instead, see [Rotation]
+///
+/// # Examples
+/// Basic usage:
+/// ```rust
+/// use Network_analyzer::report_sink::FileSink;
+/// use Network_analyzer::report_writer::ReportWriter;
+/// use Network_analyzer::socket_listener::SocketListener;
+///
+/// let sl=SocketListener::new("eth0");
+/// let report_writer = ReportWriter::new(String::from("traffic"), 5, sl.get_aggregated_data());
+/// // reports are written to /var/lib/network-analyzer/traffic.txt
+/// report_writer.set_sinks(vec![Box::new(FileSink::with_directory("/var/lib/network-analyzer", Some(String::from("txt"))))]);
+/// ```
+///
+/// Keep a snapshot every tick, compressing them and deleting the ones older than a day (with a 60 seconds timer):
+/// ```rust
+/// use Network_analyzer::report_format::ReportFormat;
+/// use Network_analyzer::report_sink::{FileSink, Rotation, RotationPolicy};
+/// use Network_analyzer::report_writer::ReportWriter;
+/// use Network_analyzer::socket_listener::SocketListener;
+///
+/// let sl=SocketListener::new("eth0");
+/// let report_writer = ReportWriter::new(String::from("traffic"), 60, sl.get_aggregated_data());
+/// report_writer.set_format(ReportFormat::Json);
+/// let policy = RotationPolicy { rotation: Rotation::Timestamped, compress: true, retention: Some(24 * 60) };
+/// report_writer.set_sinks(vec![Box::new(FileSink::with_rotation("report", None, policy))]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct FileSink {
+    directory: PathBuf,
+    extension: Option<String>,
+    policy: RotationPolicy,
+    /// Number of reports appended to the current file, for [Rotation::Count], `None` until the sink writes its first report
+    reports_in_current: Option<usize>,
+}
+
+impl FileSink {
+    /// Creates a FileSink writing to the `report/` directory, with the extension of the [ReportFormat] of the reports
+    pub fn new() -> Self {
+        FileSink::with_directory("report", None)
+    }
+
+    /// Creates a FileSink writing to the given directory, that is created if missing
+    /// # Arguments
+    /// * `directory` - The directory of the report files
+    /// * `extension` - The extension of the report files, `None` to use the extension of the [ReportFormat] of the reports,
+    ///   an empty extension to write files without extension
+    pub fn with_directory(directory: impl Into<PathBuf>, extension: Option<String>) -> Self {
+        FileSink::with_rotation(directory, extension, RotationPolicy::default())
+    }
+
+    /// Creates a FileSink writing to the given directory and keeping the history of the reports as described by the [RotationPolicy]
+    /// # Arguments
+    /// * `directory` - The directory of the report files
+    /// * `extension` - The extension of the report files, as in [FileSink::with_directory]
+    /// * `policy` - The rotation, compression and retention of the reports
+    pub fn with_rotation(directory: impl Into<PathBuf>, extension: Option<String>, policy: RotationPolicy) -> Self {
+        FileSink { directory: directory.into(), extension, policy, reports_in_current: None }
+    }
+
+    /// Returns the path of the file the report with the given name and format is written to
+    pub fn get_report_path(&self, name: &str, format: ReportFormat) -> PathBuf {
+        self.directory.join(format!("{}{}", name, self.dot_extension(format)))
+    }
+
+    /// Returns the paths of the archives of the report with the given name and format, from the oldest to the most recent
+    /// # Errors
+    /// If the directory of the reports cannot be read
+    pub fn get_archive_paths(&self, name: &str, format: ReportFormat) -> Result<Vec<PathBuf>> {
+        let path = self.get_report_path(name, format);
+        let prefix = format!("{}-", Path::new(name).file_name().and_then(|file_name| file_name.to_str()).unwrap_or_default());
+        let suffix = self.dot_extension(format);
+        let timestamp_len = Utc::now().format(ARCHIVE_TIMESTAMP_FORMAT).to_string().len();
+        let mut archives: Vec<PathBuf> = fs::read_dir(path.parent().unwrap_or(&self.directory))?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|archive| {
+                let file_name = archive.file_name().and_then(|file_name| file_name.to_str()).unwrap_or_default();
+                let file_name = file_name.strip_suffix(".gz").unwrap_or(file_name);
+                file_name.strip_prefix(&prefix)
+                    .and_then(|rest| rest.strip_suffix(&suffix))
+                    .is_some_and(|timestamp| timestamp.len() == timestamp_len && timestamp.ends_with('Z'))
+            })
+            .collect();
+        //timestamps sort in chronological order
+        archives.sort();
+        Ok(archives)
+    }
+
+    fn dot_extension(&self, format: ReportFormat) -> String {
+        match self.extension.as_deref().unwrap_or(format.extension()) {
+            "" => String::new(),
+            extension => format!(".{}", extension),
+        }
+    }
+
+    /// Returns the path of a new archive, that does not replace an existing one (compressed or not)
+    fn archive_path(&self, name: &str, format: ReportFormat) -> PathBuf {
+        let mut time = Utc::now();
+        loop {
+            let archive = self.directory.join(format!("{}-{}{}", name, time.format(ARCHIVE_TIMESTAMP_FORMAT), self.dot_extension(format)));
+            let mut compressed = archive.clone().into_os_string();
+            compressed.push(".gz");
+            if !archive.exists() && !Path::new(&compressed).exists() {
+                return archive;
+            }
+            //moving to the next millisecond keeps the archives in chronological order
+            time += Duration::milliseconds(1);
+        }
+    }
+
+    /// Compresses the archives (except `latest`) if required and deletes the ones beyond the retention
+    fn maintain_archives(&self, name: &str, format: ReportFormat, latest: Option<&Path>) -> Result<()> {
+        let mut archives = self.get_archive_paths(name, format)?;
+        if self.policy.compress {
+            for archive in archives.iter_mut() {
+                if Some(archive.as_path()) != latest && archive.extension().is_none_or(|extension| extension != "gz") {
+                    *archive = compress(archive)?;
+                }
+            }
+        }
+        if let Some(retention) = self.policy.retention {
+            let excess = archives.len().saturating_sub(retention);
+            for archive in archives.iter().take(excess) {
+                fs::remove_file(archive)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for FileSink {
+    fn default() -> Self {
+        FileSink::new()
+    }
+}
+
+impl ReportSink for FileSink {
+    fn write_report(&mut self, report: &Report) -> Result<()> {
+        let path = self.get_report_path(report.name, report.format);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let rendered = report.render();
+        match self.policy.rotation {
+            Rotation::Overwrite => write_atomically(&path, &rendered),
+            Rotation::Timestamped => {
+                let archive = self.archive_path(report.name, report.format);
+                write_atomically(&archive, &rendered)?;
+                self.maintain_archives(report.name, report.format, Some(&archive))
+            },
+            Rotation::Size(_) | Rotation::Count(_) => {
+                if matches!(report.format, ReportFormat::Json | ReportFormat::Html) {
+                    return Err(io::Error::new(ErrorKind::InvalidInput, format!("{:?} reports cannot be appended to the same file, use Rotation::Timestamped or NDJSON", report.format)));
+                }
+                let current_size = fs::metadata(&path).map(|metadata| metadata.len()).unwrap_or(0);
+                //the header row is only written at the top of the file
+                let header = (report.format == ReportFormat::Csv).then(|| {
+                    let header_end = rendered.iter().position(|byte| *byte == b'\n').map_or(rendered.len(), |end| end + 1);
+                    &rendered[..header_end]
+                });
+                let columns_changed = match header {
+                    Some(header) if current_size > 0 => {
+                        let mut current_header = Vec::new();
+                        BufReader::new(File::open(&path)?).read_until(b'\n', &mut current_header)?;
+                        current_header != header
+                    },
+                    _ => false,
+                };
+                let rotate = current_size > 0 && (columns_changed || match self.policy.rotation {
+                    Rotation::Size(max_size) => current_size + rendered.len() as u64 > max_size,
+                    //the reports of a file left by a previous run are not counted, the file is archived
+                    Rotation::Count(max_reports) => self.reports_in_current.is_none_or(|reports| reports >= max_reports),
+                    _ => false,
+                });
+                if rotate {
+                    fs::rename(&path, self.archive_path(report.name, report.format))?;
+                    self.maintain_archives(report.name, report.format, None)?;
+                }
+                let mut rendered = rendered.as_slice();
+                if let Some(header) = header.filter(|_| current_size > 0 && !rotate) {
+                    rendered = &rendered[header.len()..];
+                }
+                //appending is not atomic: a reader of the current file may observe a partially appended report
+                OpenOptions::new().create(true).append(true).open(&path)?.write_all(rendered)?;
+                self.reports_in_current = Some(if rotate { 1 } else { self.reports_in_current.unwrap_or(0) + 1 });
+                Ok(())
+            },
+        }
+    }
+}
+
+/// Writes the content to a hidden temporary file next to the given path, then atomically renames it
+fn write_atomically(path: &Path, content: &[u8]) -> Result<()> {
+    let mut temp_name = OsString::from(".");
+    temp_name.push(path.file_name().unwrap_or_default());
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+    let result = File::create(&temp_path).and_then(|mut file| {
+        file.write_all(content)?;
+        file.sync_all()
+    }).and_then(|_| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Compresses the file with gzip, replacing it with the file with `.gz` appended to its name
+/// # Returns
+/// The path of the compressed file
+fn compress(path: &Path) -> Result<PathBuf> {
+    let mut compressed_name = path.file_name().unwrap_or_default().to_os_string();
+    compressed_name.push(".gz");
+    let compressed_path = path.with_file_name(compressed_name);
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    io::copy(&mut File::open(path)?, &mut encoder)?;
+    write_atomically(&compressed_path, &encoder.finish()?)?;
+    fs::remove_file(path)?;
+    Ok(compressed_path)
+}
+
+/// [ReportSink] printing each report on the standard output
+#[derive(Debug, Clone, Default)]
+pub struct StdoutSink;
+
+impl StdoutSink {
+    pub fn new() -> Self {
+        StdoutSink
+    }
+}
+
+impl ReportSink for StdoutSink {
+    fn write_report(&mut self, report: &Report) -> Result<()> {
+        write_report(&mut stdout().lock(), report)
+    }
+}
+
+/// [ReportSink] keeping the rendered reports in memory, clones of a MemorySink share the same reports.
+/// Every report is kept, so it is meant for tests and for embedding the analyzer rather than for long captures
+///
+/// # Examples
+/// Basic usage:
+/// ```rust
+/// use Network_analyzer::report_sink::MemorySink;
+/// use Network_analyzer::report_writer::ReportWriter;
+/// use Network_analyzer::socket_listener::SocketListener;
+///
+/// let sl=SocketListener::new("eth0");
+/// let memory = MemorySink::new();
+/// let report_writer = ReportWriter::new(String::from("report"), 5, sl.get_aggregated_data());
+/// report_writer.set_sinks(vec![Box::new(memory.clone())]);
+/// // ...
+/// println!("{}", memory.get_last_report().unwrap_or_default());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemorySink {
+    reports: Arc<Mutex<Vec<String>>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        MemorySink::default()
+    }
+
+    /// Returns all the reports written so far, from the oldest to the most recent
+    pub fn get_reports(&self) -> Vec<String> {
+        self.reports.lock().unwrap().clone()
+    }
+
+    /// Returns the most recent report, if any
+    pub fn get_last_report(&self) -> Option<String> {
+        self.reports.lock().unwrap().last().cloned()
+    }
+}
+
+impl ReportSink for MemorySink {
+    fn write_report(&mut self, report: &Report) -> Result<()> {
+        let rendered = String::from_utf8_lossy(&report.render()).into_owned();
+        self.reports.lock().unwrap().push(rendered);
+        Ok(())
+    }
+}
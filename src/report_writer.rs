@@ -1,11 +1,17 @@
 use std::collections::HashMap;
 use std::fs;
 use std::fs::File;
-use std::io::{BufWriter, ErrorKind, Write};
+use std::io::{self, BufWriter, ErrorKind};
 use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant};
 use chrono::Local;
 
-use crate::report_entry::{Connection, ConnectionMetadata};
+use crate::aggregator::WindowedData;
+use crate::parser::CaptureStats;
+use crate::report_format::ReportFormat;
+use crate::report_options::ReportOptions;
+use crate::report_sink::{FileSink, Report, ReportSink};
+use crate::report_entry::{Connection, ConnectionMetadata, DeltaReport};
 
 ///enum to indicate the state to be assumed by the writing thread
 #[derive(PartialEq,Debug)]
@@ -15,6 +21,12 @@ enum Command {
     EXIT
 }
 
+/// Aggregated data of the previous report, to compute the changes since then
+struct DeltaState {
+    previous: HashMap<Connection, ConnectionMetadata>,
+    taken_at: Instant,
+}
+
 /// Struct for managing the writing of aggregated data to files
 ///
 /// # Examples
@@ -49,14 +61,29 @@ enum Command {
 /// The associated thread will panic if the file or the report/folder cannot be opened/created
 /// by the [ReportWriter::write_report] method or if the `aggregated_data` lock is poisoned
 ///
+/// When the [ReportWriter] is stopped or dropped the final report is written, see [ReportWriter::stop]
+///
 /// # Remarks
 /// Is meant to be used in conjunction with one or multiple [SocketListener] or [Aggregator] instances,
 /// unless custom implementation is needed, the [Analyzer] struct might be more suitable
+///
+/// Clones of a [ReportWriter] share the same thread and settings, the final report is written when the last clone is dropped
 #[derive(Clone)]
 pub struct ReportWriter{
+    state: Arc<ReportWriterState>
+}
+
+/// The settings and the thread control shared by the clones of a [ReportWriter]
+struct ReportWriterState{
     report_path: Arc<Mutex<String>>,
     rewrite_time: Arc<Mutex<u64>>,
+    format: Arc<Mutex<ReportFormat>>,
+    options: Arc<Mutex<ReportOptions>>,
     aggregated_data: Arc<RwLock<HashMap<Connection, ConnectionMetadata>>>,
+    windowed_data: Option<Arc<RwLock<WindowedData>>>,
+    capture_stats: Arc<Mutex<Option<Arc<Mutex<CaptureStats>>>>>,
+    delta_state: Arc<Mutex<Option<DeltaState>>>,
+    sinks: Arc<Mutex<Vec<Box<dyn ReportSink>>>>,
     cmd: Arc<Mutex<Command>>,
     cv_cmd: Arc<Condvar>
 }
@@ -83,9 +110,43 @@ impl ReportWriter {
     /// # Panics
     /// Spawn a new thread that will panic if the file or the `report/` folder cannot be opened/created
     pub fn new(report_path: String, rewrite_time: u64, aggregated_data: Arc<RwLock<HashMap<Connection, ConnectionMetadata>>>) -> Self {
+        ReportWriter::build(report_path, rewrite_time, aggregated_data, None)
+    }
+
+    /// Creates a new ReportWriter that prints on a file, after a time passed as parameter, a table with the aggregated data
+    /// followed by a table for each time window of a windowed [Aggregator](crate::aggregator::Aggregator)
+    /// # Arguments
+    /// * `report_path` - The name of the file on which the tables will be printed.
+    /// * `rewrite_time` - The period of time that must elapse before writing to file
+    /// * `aggregated_data` - Aggregated data that have as key [Connection] and as a value [ConnectionMetadata]
+    /// * `windowed_data` - Data aggregated per time window, as returned by [Aggregator::get_windowed_data](crate::aggregator::Aggregator::get_windowed_data)
+    /// # Example
+    /// Basic usage:
+    /// ```rust
+    /// use std::time::Duration;
+    /// use Network_analyzer::aggregator::{Aggregator, AggregatorConfig, WindowConfig};
+    /// use Network_analyzer::report_writer::ReportWriter;
+    ///
+    /// let window = WindowConfig { width: Duration::from_secs(60), retention: 60 };
+    /// let aggregator = Aggregator::with_config(AggregatorConfig { window: Some(window), ..Default::default() });
+    /// let report_writer = ReportWriter::with_windows(String::from("timeline"), 5, aggregator.get_aggregated_data(), aggregator.get_windowed_data());
+    /// ```
+    ///
+    /// # Panics
+    /// Spawn a new thread that will panic if the file or the `report/` folder cannot be opened/created
+    pub fn with_windows(report_path: String, rewrite_time: u64, aggregated_data: Arc<RwLock<HashMap<Connection, ConnectionMetadata>>>, windowed_data: Arc<RwLock<WindowedData>>) -> Self {
+        ReportWriter::build(report_path, rewrite_time, aggregated_data, Some(windowed_data))
+    }
+
+    fn build(report_path: String, rewrite_time: u64, aggregated_data: Arc<RwLock<HashMap<Connection, ConnectionMetadata>>>, windowed_data: Option<Arc<RwLock<WindowedData>>>) -> Self {
         //generate all the Arcs
         let report_path = Arc::new(Mutex::new(report_path));
         let rwr_time = Arc::new(Mutex::new(rewrite_time));
+        let format = Arc::new(Mutex::new(ReportFormat::default()));
+        let options = Arc::new(Mutex::new(ReportOptions::default()));
+        let capture_stats = Arc::new(Mutex::new(None));
+        let delta_state = Arc::new(Mutex::new(None));
+        let sinks: Arc<Mutex<Vec<Box<dyn ReportSink>>>> = Arc::new(Mutex::new(vec![Box::new(FileSink::new())]));
         let cmd = Arc::new(Mutex::new(Command::PROCEED));
         let cv_cmd = Arc::new(Condvar::new());
 
@@ -94,7 +155,13 @@ impl ReportWriter {
         let cmd_clone =cmd.clone();
         let cv_cmd_clone = cv_cmd.clone();
         let rwr_time_clone = rwr_time.clone();
+        let format_clone = format.clone();
+        let options_clone = options.clone();
+        let capture_stats_clone = capture_stats.clone();
+        let delta_state_clone = delta_state.clone();
+        let sinks_clone = sinks.clone();
         let aggregated_data_clone = aggregated_data.clone();
+        let windowed_data_clone = windowed_data.clone();
 
         std::thread::spawn( move || {
             let mut loop1 = true;
@@ -110,110 +177,242 @@ impl ReportWriter {
                     Command::PROCEED => {
                         let rwr_time = *rwr_time_clone.lock().unwrap();
                         //println!("ReportWriter thread proceeding, will wait for: {:?}", rwr_time);
-                        //release the lock on cmd
-                        drop(cmd);
-                        std::thread::sleep(std::time::Duration::from_secs(rwr_time as u64));
-                        //get the lock on cmd again and check if it is still "PROCEED"
-                        let cmd = cmd_clone.lock().unwrap();
+                        //release the lock on cmd while waiting, a pause or an exit interrupts the wait
+                        let (cmd, _) = cv_cmd_clone.wait_timeout_while(cmd, Duration::from_secs(rwr_time), |cmd| *cmd == Command::PROCEED).unwrap();
+                        //check if it is still "PROCEED"
                         if *cmd == Command::PROCEED{
                             //ReportWriter thread awake, writing report
                             let report_path = report_path_clone.lock().unwrap();
-                            ReportWriter::write_report((*report_path).as_str(), aggregated_data_clone.clone());
+                            let format = *format_clone.lock().unwrap();
+                            let options = options_clone.lock().unwrap().clone();
+                            let capture_stats = capture_stats_clone.lock().unwrap().clone();
+                            //a failing sink is logged and retried at the next report, the thread keeps running
+                            let _ = ReportWriter::write_report((*report_path).as_str(), format, &options, aggregated_data_clone.clone(), windowed_data_clone.clone(), capture_stats, &delta_state_clone, Some(rwr_time), &sinks_clone);
                         }
                     }
                 }
             }
         });
-        ReportWriter { report_path, rewrite_time: rwr_time, aggregated_data, cmd, cv_cmd }
+        let state = ReportWriterState { report_path, rewrite_time: rwr_time, format, options, aggregated_data, windowed_data, capture_stats, delta_state, sinks, cmd, cv_cmd };
+        ReportWriter { state: Arc::new(state) }
     }
 
     /// Stops the [ReportWriter], stopping the write timer after which, the print to file method is called
     pub fn pause(&self) {
-        let mut cmd = self.cmd.lock().unwrap();
+        let mut cmd = self.state.cmd.lock().unwrap();
         *cmd = Command::PAUSE;
-        self.cv_cmd.notify_one();
+        self.state.cv_cmd.notify_one();
     }
 
     /// Reactivates the [ReportWriter], reactivating the write timer after which, the print to file method is called
     pub fn resume(&self) {
-        let mut cmd = self.cmd.lock().unwrap();
+        let mut cmd = self.state.cmd.lock().unwrap();
         *cmd = Command::PROCEED;
-        self.cv_cmd.notify_one();
+        self.state.cv_cmd.notify_one();
     }
 
-    /// Interrupts the loop of the [ReportWriter] thread, allowing the thread to end
-    fn exit(&self) {
-        let mut cmd = self.cmd.lock().unwrap();
-        *cmd = Command::EXIT;
-        self.cv_cmd.notify_one();
+    /// Stops the write timer for good and writes the final report, so that the last interval of data is not lost
+    /// # Errors
+    /// see [ReportWriter::write_now]
+    /// # Remarks
+    /// Only the first call writes the report, it is also called when the last clone of the [ReportWriter] is dropped
+    pub fn stop(&self) -> io::Result<()> {
+        self.state.stop()
     }
 
     /// Modifies the value of the timer
     pub fn set_rewrite_time(&self, rewrite_time: u64) {
-        let mut rwr_time = self.rewrite_time.lock().unwrap();
+        let mut rwr_time = self.state.rewrite_time.lock().unwrap();
         *rwr_time = rewrite_time;
     }
 
     /// Return the value of the timer
     pub fn get_rewrite_time(&self) -> u64 {
-        let rwr_time = self.rewrite_time.lock().unwrap();
+        let rwr_time = self.state.rewrite_time.lock().unwrap();
         *rwr_time
     }
 
+    /// Modifies the format of the next reports
+    pub fn set_format(&self, new_format: ReportFormat) {
+        let mut format = self.state.format.lock().unwrap();
+        *format = new_format;
+    }
+
+    /// Return the format of the reports
+    pub fn get_format(&self) -> ReportFormat {
+        *self.state.format.lock().unwrap()
+    }
+
+    /// Modifies the sorting, the filters and the number of rows of the next reports
+    /// # Example
+    /// Only the ten connections that transferred the most bytes:
+    /// ```rust
+    /// use Network_analyzer::report_options::{ReportOptions, SortKey};
+    /// use Network_analyzer::report_writer::ReportWriter;
+    /// use Network_analyzer::socket_listener::SocketListener;
+    ///
+    /// let sl=SocketListener::new("eth0");
+    /// let report_writer = ReportWriter::new(String::from("top_talkers"), 5, sl.get_aggregated_data());
+    /// report_writer.set_options(ReportOptions { sort: Some(SortKey::Bytes), top: Some(10), ..Default::default() });
+    /// ```
+    pub fn set_options(&self, new_options: ReportOptions) {
+        let mut options = self.state.options.lock().unwrap();
+        *options = new_options;
+    }
+
+    /// Return the sorting, the filters and the number of rows of the reports
+    pub fn get_options(&self) -> ReportOptions {
+        self.state.options.lock().unwrap().clone()
+    }
+
+    /// Sets the statistics of the capture summarized at the top of the next reports, `None` to write only the connections
+    /// # Example
+    /// ```rust
+    /// use Network_analyzer::report_writer::ReportWriter;
+    /// use Network_analyzer::socket_listener::SocketListener;
+    ///
+    /// let sl=SocketListener::new("eth0");
+    /// let report_writer = ReportWriter::new(String::from("traffic"), 5, sl.get_aggregated_data());
+    /// report_writer.set_capture_stats(Some(sl.get_capture_stats()));
+    /// ```
+    pub fn set_capture_stats(&self, new_capture_stats: Option<Arc<Mutex<CaptureStats>>>) {
+        let mut capture_stats = self.state.capture_stats.lock().unwrap();
+        *capture_stats = new_capture_stats;
+    }
+
+    /// Return the statistics of the capture summarized in the reports, if any
+    pub fn get_capture_stats(&self) -> Option<Arc<Mutex<CaptureStats>>> {
+        self.state.capture_stats.lock().unwrap().clone()
+    }
+
+    /// Enables or disables the changes since the previous report in the next reports: new connections, connections that
+    /// disappeared and the bytes and packets exchanged by each connection during the interval between the reports, with their rates
+    /// # Remarks
+    /// The first report after enabling them shows the changes since this call
+    pub fn set_delta_reports(&self, enabled: bool) {
+        let mut delta_state = self.state.delta_state.lock().unwrap();
+        match (enabled, delta_state.is_some()) {
+            (true, false) => *delta_state = Some(DeltaState { previous: HashMap::new(), taken_at: Instant::now() }),
+            (false, _) => *delta_state = None,
+            (true, true) => {},
+        }
+    }
+
+    /// Return `true` if the reports include the changes since the previous report
+    pub fn get_delta_reports(&self) -> bool {
+        self.state.delta_state.lock().unwrap().is_some()
+    }
+
+    /// Replaces the [ReportSink]s the next reports are written to, by default the reports are written to a [FileSink]
+    pub fn set_sinks(&self, new_sinks: Vec<Box<dyn ReportSink>>) {
+        let mut sinks = self.state.sinks.lock().unwrap();
+        *sinks = new_sinks;
+    }
+
+    /// Adds a [ReportSink] the next reports are written to, in addition to the current ones
+    pub fn add_sink(&self, sink: Box<dyn ReportSink>) {
+        let mut sinks = self.state.sinks.lock().unwrap();
+        sinks.push(sink);
+    }
+
     /// Return aggregated data
     pub fn get_aggregated_data(&self) -> Arc<RwLock<HashMap<Connection, ConnectionMetadata>>> {
-        Arc::clone(&self.aggregated_data)
+        Arc::clone(&self.state.aggregated_data)
+    }
+
+    /// Return the data aggregated per time window, if the report includes it
+    pub fn get_windowed_data(&self) -> Option<Arc<RwLock<WindowedData>>> {
+        self.state.windowed_data.clone()
     }
 
     ///Change the name of the file on which the aggregated data will be printed
     pub fn set_report_path(&self, new_report_path: String) {
-        let mut report_path = self.report_path.lock().unwrap();
+        let mut report_path = self.state.report_path.lock().unwrap();
         *report_path = new_report_path;
     }
 
     ///Return the name of the file on which the aggregated data will be printed
     pub fn get_report_path(&self) -> String {
-        let report_path = self.report_path.lock().unwrap();
+        let report_path = self.state.report_path.lock().unwrap();
         (*report_path).clone()
     }
 
-    /// Prints on a markdown file a table representing the aggregated data
+    /// Writes the report of the aggregated data in the given format to every [ReportSink], followed by the data of each time window if given
     /// # Arguments
-    /// * `report_path` - The name of the file on which the table with the aggregated data will be printed.
+    /// * `report_path` - The name of the report, e.g. the file name without extension for a [FileSink]
+    /// * `format` - The [ReportFormat] of the report
+    /// * `options` - The [ReportOptions] selecting and sorting the rows of the report
     /// * `aggregated_data` - Aggregated data that have as key [Connection] and as a value [ConnectionMetadata]
+    /// * `windowed_data` - Data aggregated per time window, from the oldest to the most recent window
+    /// * `capture_stats` - The statistics of the capture, summarized at the top of the report
+    /// * `delta_state` - The aggregated data of the previous report, if the changes since then are included in the report
+    /// * `rewrite_time` - The period in seconds between two reports
+    /// * `sinks` - The destinations of the report
+    /// # Errors
+    /// The first error of the sinks that cannot write the report (e.g. the file or the `report/` folder cannot be created/opened),
+    /// the error of each sink is logged and the report is still written to the other sinks
     /// # Panics
-    /// panics if the file or the `report/` folder cannot be created/opened
-    /// also panics if the aggregated data lock is poisoned
-    fn write_report(filename:&str,aggregated_data: Arc<RwLock<HashMap<Connection, ConnectionMetadata>>>) {
+    /// panics if the aggregated data lock is poisoned
+    #[allow(clippy::too_many_arguments)]
+    fn write_report(filename:&str, format: ReportFormat, options: &ReportOptions, aggregated_data: Arc<RwLock<HashMap<Connection, ConnectionMetadata>>>, windowed_data: Option<Arc<RwLock<WindowedData>>>, capture_stats: Option<Arc<Mutex<CaptureStats>>>, delta_state: &Mutex<Option<DeltaState>>, rewrite_time: Option<u64>, sinks: &Mutex<Vec<Box<dyn ReportSink>>>) -> io::Result<()> {
+        //copy the statistics so that the parser is not blocked while the report is written
+        let capture_stats = capture_stats.map(|capture_stats| capture_stats.lock().unwrap().clone());
         let aggregated_data = aggregated_data.read().unwrap();
+        let windowed_data = windowed_data.as_ref().map(|windowed_data| windowed_data.read().unwrap());
+        let delta = delta_state.lock().unwrap().as_mut().map(|state| {
+            let now = Instant::now();
+            let delta = DeltaReport::compute(&state.previous, &aggregated_data, now - state.taken_at);
+            state.previous = aggregated_data.clone();
+            state.taken_at = now;
+            delta
+        });
+        let report = Report { name: filename, format, aggregated_data: &aggregated_data, windowed_data: windowed_data.as_deref(), options, capture_stats: capture_stats.as_ref(), delta: delta.as_ref(), rewrite_time };
 
-        let mut output = ReportWriter::create_dir_report(filename);
-        writeln!(output, "|   Src IP address  |  Dst IP address   |  Src port |  Dst port |  Protocol |    Bytes      |  Initial timestamp    |   Final timestamp  |").expect("Error writing output file\n\r");
-        writeln!(output, "| :---------------: | :---------------: | :-------: | :-------: | :-------: | :-----------: | :-------------------: | :----------------: |").expect("Error writing output file\n\r");
+        let mut result = Ok(());
+        for sink in sinks.lock().unwrap().iter_mut() {
+            if let Err(e) = sink.write_report(&report) {
+                eprintln!("Error writing report '{}': {}", filename, e);
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
 
-        for (conn, data) in aggregated_data.iter() {
-            writeln!(output,"{}{}",conn,data).expect("Error writing output file\n\r");
+        if result.is_ok() {
+            let time_report = Local::now();
+            println!("[{}] '{}' updated", time_report.format("%Y-%m-%d %H:%M:%S"), filename);
         }
+        result
+    }
 
-        let time_report = Local::now();
-        println!("[{}] '{}' updated", time_report.format("%Y-%m-%d %H:%M:%S"), filename);
+    /// Writes immediately the report with the current name, format, options and sinks of the [ReportWriter],
+    /// independently of the write timer and even if the [ReportWriter] is paused
+    /// # Errors
+    /// The first error of the sinks that cannot write the report, the report is still written to the other sinks
+    pub fn write_now(&self) -> io::Result<()> {
+        self.state.write_with_rewrite_time(Some(self.get_rewrite_time()))
     }
 
-    /// Creates the directory `report/` if not present and the file `report/[filename].md`
+    /// Creates the directory `report/` if not present and the file `report/[filename].[extension]`
     /// # Arguments
     /// * `filename` - The name of the file on which the table with the aggregated data will be printed.
+    /// * `extension` - The extension of the file, e.g. [ReportFormat::extension]
     ///
     /// # Return
-    /// The BufWriter pointing to the file `report/[filename].md`
+    /// The BufWriter pointing to the file `report/[filename].[extension]`
     ///
     /// # Panics
     /// If the directory `report/` cannot be created because:
     /// - User lacks permissions to create directory at path.
     /// - Other errors returned by [std::fs::create_dir] **except for the `AlreadyExists` error**.
     ///
-    /// or the file `report/[filename].md` cannot be created
+    /// or the file `report/[filename].[extension]` cannot be created
+    ///
+    /// # Remarks
+    /// The file is truncated in place, the [FileSink](crate::report_sink::FileSink) used by the [ReportWriter] writes the reports
+    /// atomically in a configurable directory
     ///
-    pub fn create_dir_report(filename:&str) -> BufWriter<File> {
+    pub fn create_dir_report(filename:&str, extension:&str) -> BufWriter<File> {
         let res_dir=fs::create_dir("report");
         match res_dir {
             Ok(_) => {},
@@ -225,7 +424,8 @@ impl ReportWriter {
         }
         let mut path =String::from("report/");
         path.push_str(filename);
-        path.push_str(".md");
+        path.push('.');
+        path.push_str(extension);
         let file=File::create(path.as_str()).expect("Error creating output file\n\r");
         let output = BufWriter::new(file);
         return output;
@@ -234,10 +434,47 @@ impl ReportWriter {
 
 }
 
-/// When the ReportWriter instance is dropped also the associated thread
-/// will be stopped
-impl Drop for ReportWriter{
+impl ReportWriterState {
+    /// Interrupts the loop of the [ReportWriter] thread, allowing the thread to end
+    /// # Returns
+    /// `false` if the thread had already been interrupted
+    fn exit(&self) -> bool {
+        let mut cmd = self.cmd.lock().unwrap();
+        let running = *cmd != Command::EXIT;
+        *cmd = Command::EXIT;
+        self.cv_cmd.notify_one();
+        running
+    }
+
+    /// see [ReportWriter::stop]
+    fn stop(&self) -> io::Result<()> {
+        if self.exit() {
+            //the final report is not rewritten anymore
+            self.write_with_rewrite_time(None)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn write_with_rewrite_time(&self, rewrite_time: Option<u64>) -> io::Result<()> {
+        let report_path = self.report_path.lock().unwrap().clone();
+        let format = *self.format.lock().unwrap();
+        let options = self.options.lock().unwrap().clone();
+        let capture_stats = self.capture_stats.lock().unwrap().clone();
+        ReportWriter::write_report(report_path.as_str(), format, &options, self.aggregated_data.clone(), self.windowed_data.clone(), capture_stats, &self.delta_state, rewrite_time, &self.sinks)
+    }
+}
+
+/// When the last clone of the ReportWriter is dropped also the associated thread
+/// will be stopped and the final report written
+impl Drop for ReportWriterState{
     fn drop(&mut self) {
-        self.exit();
+        if std::thread::panicking() {
+            //a failure writing the report would abort the process
+            self.exit();
+        } else {
+            //the errors of the sinks have already been logged
+            let _ = self.stop();
+        }
     }
 }
\ No newline at end of file
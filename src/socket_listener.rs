@@ -1,7 +1,7 @@
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use crate::aggregator::Aggregator;
-use crate::parser::Parser;
+use crate::parser::{CaptureStats, FilterError, Parser};
 use crate::report_entry::{Connection, ConnectionMetadata};
 
 /// Struct that contains a [Parser] and an [Aggregator] and allows packets to be sent from the [Parser] to the [Aggregator]
@@ -47,6 +47,65 @@ impl SocketListener {
         SocketListener{parser,aggregator,device}
     }
 
+    /// Creates a [SocketListener] by creating and connecting an [Aggregator] and a [Parser] reading from a capture file
+    /// # Arguments
+    /// * `path` - The path of the `.pcap` or `.pcapng` file to read
+    /// # Example
+    /// Basic usage:
+    /// ```rust
+    /// use Network_analyzer::socket_listener::SocketListener;
+    /// // I am creating a SocketListener passing it the capture file that will be read by the parser
+    /// let sl=SocketListener::from_file("capture.pcap");
+    /// // I am waiting for all the packets of the file to be aggregated
+    /// sl.wait_for_completion();
+    /// // I am retrieving data from the aggregator
+    /// let data=sl.get_aggregated_data();
+    /// ```
+    pub fn from_file(path:&str) -> Self {
+        let aggregator=Aggregator::new();
+        let aggregator_tx=aggregator.get_sender();
+        let parser=Parser::from_file(path, aggregator_tx.clone());
+
+        let device=String::from(path);
+        SocketListener{parser,aggregator,device}
+    }
+
+    /// Creates a [SocketListener] whose [Parser] only forwards to the [Aggregator] the packets matching a BPF filter
+    /// # Arguments
+    /// * `device_str` - The name of device to listen to
+    /// * `filter` - The BPF filter expression, e.g. `tcp port 443 or udp port 53`
+    /// # Example
+    /// Basic usage:
+    /// ```rust
+    /// use Network_analyzer::socket_listener::SocketListener;
+    /// let sl=SocketListener::with_filter("eth0", "tcp port 443 or udp port 53").expect("invalid filter");
+    /// ```
+    /// # Errors
+    /// Returns a [FilterError] if the filter expression cannot be compiled
+    pub fn with_filter(device_str:&str, filter:&str) -> Result<Self, FilterError> {
+        let aggregator=Aggregator::new();
+        let aggregator_tx=aggregator.get_sender();
+        let parser=Parser::with_filter(device_str, filter, aggregator_tx.clone())?;
+
+        let device=String::from(device_str);
+        Ok(SocketListener{parser,aggregator,device})
+    }
+
+    /// Creates a [SocketListener] reading from a capture file whose [Parser] only forwards to the [Aggregator] the packets matching a BPF filter
+    /// # Arguments
+    /// * `path` - The path of the `.pcap` or `.pcapng` file to read
+    /// * `filter` - The BPF filter expression, e.g. `tcp port 443 or udp port 53`
+    /// # Errors
+    /// Returns a [FilterError] if the filter expression cannot be compiled
+    pub fn from_file_with_filter(path:&str, filter:&str) -> Result<Self, FilterError> {
+        let aggregator=Aggregator::new();
+        let aggregator_tx=aggregator.get_sender();
+        let parser=Parser::from_file_with_filter(path, filter, aggregator_tx.clone())?;
+
+        let device=String::from(path);
+        Ok(SocketListener{parser,aggregator,device})
+    }
+
     /// Pauses the [Parser] of [SocketListener] from receiving packets if it is not already paused
     pub fn pause(&self){
     self.parser.stop_iter_cap();
@@ -57,9 +116,43 @@ impl SocketListener {
     self.parser.resume_iter_cap();
     }
 
+    /// Changes the BPF filter applied by the [Parser] of [SocketListener], `None` removes the current filter
+    /// # Errors
+    /// Returns a [FilterError] if the filter expression cannot be compiled, in that case the current filter is kept
+    pub fn set_filter(&self, filter:Option<&str>) -> Result<(), FilterError> {
+        self.parser.set_filter(filter)
+    }
+
+    /// Returns the BPF filter currently applied by the [Parser] of [SocketListener], if any
+    pub fn get_filter(&self) -> Option<String> {
+        self.parser.get_filter()
+    }
+
+    /// Blocks until the [Parser] of [SocketListener] has stopped reading packets
+    /// and all the packets it parsed have been inserted by the [Aggregator] into the aggregated data
+    pub fn wait_for_completion(&self){
+        let parsed=self.parser.wait_for_completion();
+        self.aggregator.wait_for_packets(parsed);
+    }
+
+    /// Returns true if the [Parser] of [SocketListener] has stopped reading packets, e.g. because the end of the capture file has been reached
+    pub fn is_finished(&self) -> bool {
+        self.parser.is_finished()
+    }
+
+    /// Returns the statistics of the packets read by the [Parser] of [SocketListener]
+    pub fn get_capture_stats(&self) -> Arc<Mutex<CaptureStats>> {
+        self.parser.get_capture_stats()
+    }
+
     /// Returns aggregated data from the [Aggregator] of [SocketListener]
     pub fn get_aggregated_data(&self)-> Arc<RwLock<HashMap<Connection,ConnectionMetadata>>>{
         self.aggregator.get_aggregated_data()
     }
 
+    /// Returns the [Aggregator] of [SocketListener], e.g. to serve its data with a [MetricsServer](crate::metrics::MetricsServer)
+    pub fn get_aggregator(&self) -> &Aggregator {
+        &self.aggregator
+    }
+
 }
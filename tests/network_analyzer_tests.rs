@@ -1,24 +1,43 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
 use std::sync::mpsc;
-use network_analyzer::aggregator::Aggregator;
-use network_analyzer::parsed_packet::ParsedPacket;
-use network_analyzer::parser::Parser;
-use network_analyzer::report_entry::Connection;
+use std::time::Duration;
+use chrono::{DateTime, TimeZone, Utc};
+use network_analyzer::aggregator::{AggregationKey, Aggregator, AggregatorConfig, CompletedFlow, EvictionPolicy, ExpiryReason, WindowConfig, WindowedData};
+use network_analyzer::parsed_packet::{FlowSummary, ParsedPacket, Protocol, TCP_ACK, TCP_SYN, UNKNOWN_ICMP_TYPE};
+use network_analyzer::collector::FlowCollector;
+use network_analyzer::ipfix::{IpfixExporter, IpfixTransport, ENTERPRISE_NUMBER};
+use network_analyzer::metrics::MetricsServer;
+use network_analyzer::netflow::{NetflowExporter, NetflowVersion};
+use network_analyzer::parser::{CaptureStats, Parser};
+use network_analyzer::report_entry::{ChangeStatus, Connection, ConnectionMetadata, DeltaReport};
+use network_analyzer::report_format::ReportFormat;
+use network_analyzer::report_options::{AddressRange, ReportOptions, SortKey};
+use network_analyzer::report_sink::{FileSink, MemorySink, Report, ReportSink, Rotation, RotationPolicy};
+use network_analyzer::report_writer::ReportWriter;
+use network_analyzer::socket_listener::SocketListener;
+use etherparse::PacketBuilder;
 
 
+/// Returns the timestamp corresponding to the given seconds since the epoch
+fn ts(secs: i64) -> DateTime<Utc> {
+    Utc.timestamp_opt(secs, 0).unwrap()
+}
+
 #[test]
 fn test_aggregator() {
-    let timestamp1 : usize = 1667775485;
-    let source_ip1 : String = "127.0.0.1".to_string();
-    let destination_ip1 : String = "128.0.0.2".to_string();
-    let source_port1 : usize = 62911;
-    let destination_port1 : usize = 80;
-    let protocol1 : String = "TCP".to_string();
+    let timestamp1 : i64 = 1667775485;
+    let source_ip1 : IpAddr = "127.0.0.1".parse().unwrap();
+    let destination_ip1 : IpAddr = "128.0.0.2".parse().unwrap();
+    let source_port1 : u16 = 62911;
+    let destination_port1 : u16 = 80;
+    let protocol1 : Protocol = Protocol::Tcp;
     let size1 : usize = 64;
     //declare a list of ParsePacket initialized with dummy values
-    let list:Vec<ParsedPacket> = vec![ParsedPacket::new(timestamp1.to_string(),source_ip1.clone(),destination_ip1.clone(),Some(source_port1),Some(destination_port1),protocol1.clone(),size1),
-                                  ParsedPacket::new((timestamp1+1).to_string(),source_ip1.clone(),destination_ip1.clone(),Some(source_port1),Some(destination_port1),protocol1.clone(),size1),
-                                  ParsedPacket::new((timestamp1+2).to_string(),source_ip1.clone(),destination_ip1.clone(),Some(source_port1),Some(destination_port1),protocol1.clone(),size1),
-                                  ParsedPacket::new((timestamp1+3).to_string(),source_ip1.clone(),destination_ip1.clone(),Some(source_port1),Some(destination_port1),protocol1.clone(),size1)];
+    let list:Vec<ParsedPacket> = vec![ParsedPacket::new(ts(timestamp1),source_ip1,destination_ip1,Some(source_port1),Some(destination_port1),protocol1,size1),
+                                  ParsedPacket::new(ts(timestamp1+1),source_ip1,destination_ip1,Some(source_port1),Some(destination_port1),protocol1,size1),
+                                  ParsedPacket::new(ts(timestamp1+2),source_ip1,destination_ip1,Some(source_port1),Some(destination_port1),protocol1,size1),
+                                  ParsedPacket::new(ts(timestamp1+3),source_ip1,destination_ip1,Some(source_port1),Some(destination_port1),protocol1,size1)];
     let aggregator = Aggregator::new();
     //send each packet to the aggregator
     for packet in list {
@@ -27,44 +46,46 @@ fn test_aggregator() {
     //wait for the aggregator to finish
     std::thread::sleep(std::time::Duration::from_secs(5));
     //test the aggregated data
-    let key = Connection::new(source_ip1,destination_ip1,Some(source_port1),Some(destination_port1),protocol1);
+    let key = Connection::new(source_ip1,destination_ip1,Some(source_port1),Some(destination_port1),protocol1,None);
     let binding = aggregator.get_aggregated_data();
-    let aggregated_data = binding.lock().unwrap();
+    let aggregated_data = binding.read().unwrap();
     assert_eq!(aggregated_data.len(),1);
     assert_eq!(aggregated_data.contains_key(&key),true);
     let value = aggregated_data.get(&key).unwrap();
     assert_eq!(value.size,256 as usize);
-    assert_eq!(value.first_timestamp,1667775485.to_string());
-    assert_eq!(value.last_timestamp,1667775488.to_string());
+    assert_eq!(value.first_timestamp,ts(1667775485));
+    assert_eq!(value.last_timestamp,ts(1667775488));
+    assert_eq!(value.packets,4);
+    assert_eq!(value.bytes_per_second(),Some(256.0 / 3.0));
     println!("aggregated record received: {:?}, {:?}",key,value);
 }
 
 #[test]
 fn test_aggregator_multiple_destinations() {
     //prima entry
-    let timestamp1 : usize = 1667775485;
-    let source_ip1 : String = "127.0.0.1".to_string();
-    let destination_ip1 : String = "128.0.0.2".to_string();
-    let source_port1 : usize = 62911;
-    let destination_port1 : usize = 80;
-    let protocol1 : String = "TCP".to_string();
+    let timestamp1 : i64 = 1667775485;
+    let source_ip1 : IpAddr = "127.0.0.1".parse().unwrap();
+    let destination_ip1 : IpAddr = "128.0.0.2".parse().unwrap();
+    let source_port1 : u16 = 62911;
+    let destination_port1 : u16 = 80;
+    let protocol1 : Protocol = Protocol::Tcp;
     let size1 : usize = 64;
     //seconda entry
-    let timestamp2 : usize = 1667775470;
-    let source_ip2 : String = "127.0.0.3".to_string();
-    let destination_ip2 : String = "128.0.0.4".to_string();
-    let source_port2 : usize = 62912;
-    let destination_port2 : usize = 81;
-    let protocol2 : String = "TCP".to_string();
+    let timestamp2 : i64 = 1667775470;
+    let source_ip2 : IpAddr = "127.0.0.3".parse().unwrap();
+    let destination_ip2 : IpAddr = "128.0.0.4".parse().unwrap();
+    let source_port2 : u16 = 62912;
+    let destination_port2 : u16 = 81;
+    let protocol2 : Protocol = Protocol::Tcp;
     let size2 : usize = 64;
 
-    let list1:Vec<ParsedPacket> = vec![ParsedPacket::new(timestamp1.to_string(),source_ip1.clone(),destination_ip1.clone(),Some(source_port1),Some(destination_port1),protocol1.clone(),size1),
-                                   ParsedPacket::new((timestamp1+1).to_string(),source_ip1.clone(),destination_ip1.clone(),Some(source_port1),Some(destination_port1),protocol1.clone(),size1),
-                                   ParsedPacket::new((timestamp1+2).to_string(),source_ip1.clone(),destination_ip1.clone(),Some(source_port1),Some(destination_port1),protocol1.clone(),size1),
-                                   ParsedPacket::new((timestamp1+3).to_string(),source_ip1.clone(),destination_ip1.clone(),Some(source_port1),Some(destination_port1),protocol1.clone(),size1)];
+    let list1:Vec<ParsedPacket> = vec![ParsedPacket::new(ts(timestamp1),source_ip1,destination_ip1,Some(source_port1),Some(destination_port1),protocol1,size1),
+                                   ParsedPacket::new(ts(timestamp1+1),source_ip1,destination_ip1,Some(source_port1),Some(destination_port1),protocol1,size1),
+                                   ParsedPacket::new(ts(timestamp1+2),source_ip1,destination_ip1,Some(source_port1),Some(destination_port1),protocol1,size1),
+                                   ParsedPacket::new(ts(timestamp1+3),source_ip1,destination_ip1,Some(source_port1),Some(destination_port1),protocol1,size1)];
 
-    let list2:Vec<ParsedPacket> = vec![ParsedPacket::new(timestamp2.to_string(),source_ip2.clone(),destination_ip2.clone(),Some(source_port2),Some(destination_port2),protocol2.clone(),size2),
-                                   ParsedPacket::new((timestamp2+1).to_string(),source_ip2.clone(),destination_ip2.clone(),Some(source_port2),Some(destination_port2),protocol2.clone(),size2)];
+    let list2:Vec<ParsedPacket> = vec![ParsedPacket::new(ts(timestamp2),source_ip2,destination_ip2,Some(source_port2),Some(destination_port2),protocol2,size2),
+                                   ParsedPacket::new(ts(timestamp2+1),source_ip2,destination_ip2,Some(source_port2),Some(destination_port2),protocol2,size2)];
 
     let aggregator = Aggregator::new();
     //send each packet to the aggregator
@@ -78,10 +99,10 @@ fn test_aggregator_multiple_destinations() {
     std::thread::sleep(std::time::Duration::from_secs(2));
     //test the aggregated data
     //test the aggregated data
-    let key1 = Connection::new(source_ip1,destination_ip1,Some(source_port1),Some(destination_port1),protocol1);
-    let key2 = Connection::new(source_ip2,destination_ip2,Some(source_port2),Some(destination_port2),protocol2);
+    let key1 = Connection::new(source_ip1,destination_ip1,Some(source_port1),Some(destination_port1),protocol1,None);
+    let key2 = Connection::new(source_ip2,destination_ip2,Some(source_port2),Some(destination_port2),protocol2,None);
     let binding = aggregator.get_aggregated_data();
-    let aggregated_data = binding.lock().unwrap();
+    let aggregated_data = binding.read().unwrap();
     assert_eq!(aggregated_data.len(),2);
     assert_eq!(aggregated_data.contains_key(&key1),true);
     assert_eq!(aggregated_data.contains_key(&key2),true);
@@ -89,61 +110,1423 @@ fn test_aggregator_multiple_destinations() {
     let value2 = aggregated_data.get(&key2).unwrap();
     //assert_eq!(*value.len(),4);
     assert_eq!(value1.size,256 as usize);
-    assert_eq!(value1.first_timestamp,1667775485.to_string());
-    assert_eq!(value1.last_timestamp,1667775488.to_string());
+    assert_eq!(value1.first_timestamp,ts(1667775485));
+    assert_eq!(value1.last_timestamp,ts(1667775488));
 
     assert_eq!(value2.size,128 as usize);
-    assert_eq!(value2.first_timestamp,1667775470.to_string());
-    assert_eq!(value2.last_timestamp,1667775471.to_string());
+    assert_eq!(value2.first_timestamp,ts(1667775470));
+    assert_eq!(value2.last_timestamp,ts(1667775471));
     println!("aggregated record1 received: {:?}, {:?}",key1,value1);
     println!("aggregated record2 received: {:?}, {:?}",key2,value2);
 
 
 }
 
+/// Writes the given ethernet frames into a classic pcap file, one second apart starting from `first_ts`
+fn write_pcap_file(path: &std::path::Path, first_ts: u32, frames: &[Vec<u8>]) {
+    let mut bytes: Vec<u8> = Vec::new();
+    //global header: magic number, version 2.4, thiszone, sigfigs, snaplen, linktype ethernet
+    bytes.extend_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+    bytes.extend_from_slice(&2u16.to_le_bytes());
+    bytes.extend_from_slice(&4u16.to_le_bytes());
+    bytes.extend_from_slice(&0i32.to_le_bytes());
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    bytes.extend_from_slice(&65535u32.to_le_bytes());
+    bytes.extend_from_slice(&1u32.to_le_bytes());
+    for (i, frame) in frames.iter().enumerate() {
+        //record header: ts seconds, ts microseconds, captured length, original length
+        bytes.extend_from_slice(&(first_ts + i as u32).to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(frame);
+    }
+    std::fs::write(path, bytes).unwrap();
+}
+
+/// Builds the frames used by the offline tests: two TCP packets of the same connection, one UDP packet and one ARP frame
+fn test_frames() -> Vec<Vec<u8>> {
+    let mut frames = Vec::new();
+    for _ in 0..2 {
+        let mut frame = Vec::new();
+        PacketBuilder::ethernet2([1, 2, 3, 4, 5, 6], [7, 8, 9, 10, 11, 12])
+            .ipv4([192, 168, 1, 2], [93, 184, 216, 34], 64)
+            .tcp(62911, 80, 1, 1024)
+            .write(&mut frame, &[0u8; 10]).unwrap();
+        frames.push(frame);
+    }
+    let mut frame = Vec::new();
+    PacketBuilder::ethernet2([1, 2, 3, 4, 5, 6], [7, 8, 9, 10, 11, 12])
+        .ipv4([192, 168, 1, 2], [8, 8, 8, 8], 64)
+        .udp(53000, 53)
+        .write(&mut frame, &[0u8; 20]).unwrap();
+    frames.push(frame);
+    //ARP request: not an IP packet, the parser must skip it
+    let mut frame = vec![0xffu8; 6];
+    frame.extend_from_slice(&[1, 2, 3, 4, 5, 6, 0x08, 0x06]);
+    frame.extend_from_slice(&[0u8; 28]);
+    frames.push(frame);
+    frames
+}
+
+#[test]
+fn test_aggregator_bidirectional() {
+    let client : IpAddr = "192.168.1.2".parse().unwrap();
+    let server : IpAddr = "93.184.216.34".parse().unwrap();
+    let dns : IpAddr = "8.8.8.8".parse().unwrap();
+    let tcp_packet = |timestamp: i64, source: IpAddr, destination: IpAddr, ports: (u16, u16), size: usize, flags: u8| {
+        let mut packet = ParsedPacket::new(ts(timestamp), source, destination, Some(ports.0), Some(ports.1), Protocol::Tcp, size);
+        packet.tcp_flags = Some(flags);
+        packet
+    };
+    let aggregator = Aggregator::with_config(AggregatorConfig { bidirectional: true, ..Default::default() });
+    assert!(aggregator.get_config().bidirectional);
+    //the capture starts from the SYN-ACK: the client is still the initiator
+    aggregator.send(tcp_packet(1667775485, server, client, (80, 62911), 60, TCP_SYN | TCP_ACK));
+    aggregator.send(tcp_packet(1667775486, client, server, (62911, 80), 54, TCP_ACK));
+    aggregator.send(tcp_packet(1667775487, server, client, (80, 62911), 1000, TCP_ACK));
+    //without a SYN the sender of the first packet is the initiator
+    aggregator.send(ParsedPacket::new(ts(1667775488), client, dns, Some(5353), Some(53), Protocol::Udp, 70));
+    aggregator.send(ParsedPacket::new(ts(1667775489), dns, client, Some(53), Some(5353), Protocol::Udp, 150));
+    aggregator.wait_for_packets(5);
+    let binding = aggregator.get_aggregated_data();
+    let aggregated_data = binding.read().unwrap();
+    assert_eq!(aggregated_data.len(), 2);
+    let value = aggregated_data.get(&Connection::new(client, server, Some(62911), Some(80), Protocol::Tcp, None)).unwrap();
+    assert_eq!((value.size, value.packets), (1114, 3));
+    assert_eq!((value.initiator_size, value.initiator_packets), (54, 1));
+    assert_eq!((value.responder_size, value.responder_packets), (1060, 2));
+    assert_eq!(value.first_timestamp, ts(1667775485));
+    let value = aggregated_data.get(&Connection::new(client, dns, Some(5353), Some(53), Protocol::Udp, None)).unwrap();
+    assert_eq!((value.initiator_size, value.initiator_packets), (70, 1));
+    assert_eq!((value.responder_size, value.responder_packets), (150, 1));
+}
+
+#[test]
+fn test_aggregation_keys() {
+    let packets = || vec![
+        ParsedPacket::new(ts(1667775485), "192.168.1.2".parse().unwrap(), "93.184.216.34".parse().unwrap(), Some(62911), Some(80), Protocol::Tcp, 100),
+        ParsedPacket::new(ts(1667775486), "192.168.1.3".parse().unwrap(), "93.184.216.34".parse().unwrap(), Some(62912), Some(80), Protocol::Tcp, 200),
+        ParsedPacket::new(ts(1667775487), "192.168.1.3".parse().unwrap(), "93.184.216.34".parse().unwrap(), Some(5353), Some(53), Protocol::Udp, 50),
+        ParsedPacket::new(ts(1667775488), "2001:db8:0:1::2".parse().unwrap(), "2001:db8:0:2::1".parse().unwrap(), Some(5353), Some(53), Protocol::Udp, 80),
+    ];
+    let aggregate = |key: AggregationKey| {
+        let aggregator = Aggregator::with_config(AggregatorConfig { key, ..Default::default() });
+        for packet in packets() {
+            aggregator.send(packet);
+        }
+        aggregator.wait_for_packets(4);
+        let binding = aggregator.get_aggregated_data();
+        let aggregated_data = binding.read().unwrap();
+        let mut entries: Vec<(String, usize)> = aggregated_data.iter().map(|(k, v)| (k.to_string(), v.size)).collect();
+        entries.sort();
+        entries
+    };
+    let server = aggregate(AggregationKey::DestinationIp);
+    assert_eq!(server.len(), 2);
+    //the fields that are not part of the key are wildcards
+    assert!(server[1].0.starts_with("| -          \t| *               \t| 93.184.216.34   \t| *     \t | *     \t| *       \t|"));
+    assert_eq!(server[1].1, 350);
+    let services = aggregate(AggregationKey::DestinationIpPort);
+    assert_eq!(services.len(), 3);
+    assert!(services.iter().any(|(k, size)| k.contains("| 93.184.216.34   \t| *     \t | 80    \t| TCP") && *size == 300));
+    let protocols = aggregate(AggregationKey::Protocol);
+    assert_eq!(protocols.iter().map(|(_, size)| *size).collect::<Vec<_>>(), vec![300, 130]);
+    let hosts = aggregate(AggregationKey::SourceIp);
+    assert_eq!(hosts.len(), 3);
+    let subnets = aggregate(AggregationKey::Subnets { ipv4_prefix: 24, ipv6_prefix: 48 });
+    assert_eq!(subnets.len(), 2);
+    assert!(subnets[0].0.starts_with("| -          \t| 192.168.1.0/24  \t| 93.184.216.0/24 \t|"));
+    assert_eq!(subnets[0].1, 350);
+    assert!(subnets[1].0.starts_with("| -          \t| 2001:db8::/48   \t| 2001:db8::/48   \t|"));
+}
+
+#[test]
+fn test_aggregator_timeouts() {
+    let client : IpAddr = "192.168.1.2".parse().unwrap();
+    let server : IpAddr = "93.184.216.34".parse().unwrap();
+    let packet = |timestamp: i64, port: u16| ParsedPacket::new(ts(timestamp), client, server, Some(port), Some(80), Protocol::Tcp, 100);
+    //idle timeout: entries without packets for 10 seconds are removed
+    let aggregator = Aggregator::with_config(AggregatorConfig { idle_timeout: Some(Duration::from_secs(10)), ..Default::default() });
+    let completed = aggregator.subscribe_completed_flows();
+    aggregator.send(packet(1667775485, 62911));
+    aggregator.send(packet(1667775485, 62912));
+    aggregator.send(packet(1667775490, 62911));
+    aggregator.send(packet(1667775505, 62913));
+    let mut flows = [completed.recv_timeout(Duration::from_secs(5)).unwrap(), completed.recv_timeout(Duration::from_secs(5)).unwrap()];
+    flows.sort_by_key(|flow| flow.connection.source_port);
+    assert_eq!(flows[0].connection, Connection::new(client, server, Some(62911), Some(80), Protocol::Tcp, None));
+    assert_eq!(flows[0].metadata.packets, 2);
+    assert_eq!(flows[0].reason, ExpiryReason::Idle);
+    assert_eq!(flows[1].connection.source_port, Some(62912));
+    let binding = aggregator.get_aggregated_data();
+    let aggregated_data = binding.read().unwrap();
+    assert_eq!(aggregated_data.len(), 1);
+    assert!(aggregated_data.contains_key(&Connection::new(client, server, Some(62913), Some(80), Protocol::Tcp, None)));
+    drop(aggregated_data);
+
+    //active timeout: an entry older than 60 seconds is completed and the connection starts a new entry
+    let aggregator = Aggregator::with_config(AggregatorConfig { active_timeout: Some(Duration::from_secs(60)), ..Default::default() });
+    let completed = aggregator.subscribe_completed_flows();
+    for timestamp in [1667775485, 1667775515, 1667775545, 1667775550] {
+        aggregator.send(packet(timestamp, 62911));
+    }
+    aggregator.wait_for_packets(4);
+    let flow = completed.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(flow.reason, ExpiryReason::Active);
+    assert_eq!((flow.metadata.packets, flow.metadata.first_timestamp, flow.metadata.last_timestamp), (2, ts(1667775485), ts(1667775515)));
+    let binding = aggregator.get_aggregated_data();
+    let aggregated_data = binding.read().unwrap();
+    let value = aggregated_data.get(&flow.connection).unwrap();
+    assert_eq!((value.packets, value.first_timestamp), (2, ts(1667775545)));
+}
+
+#[test]
+fn test_aggregator_eviction() {
+    let client : IpAddr = "192.168.1.2".parse().unwrap();
+    let server : IpAddr = "93.184.216.34".parse().unwrap();
+    let packet = |timestamp: i64, port: u16, size: usize| ParsedPacket::new(ts(timestamp), client, server, Some(port), Some(80), Protocol::Tcp, size);
+    //least recently seen: the entry of port 1 is refreshed, so port 2 is evicted
+    let aggregator = Aggregator::with_config(AggregatorConfig { max_entries: Some(3), ..Default::default() });
+    let completed = aggregator.subscribe_completed_flows();
+    let packets = [packet(1667775485, 1, 100), packet(1667775486, 2, 100), packet(1667775487, 3, 100), packet(1667775488, 1, 100), packet(1667775489, 4, 100)];
+    for p in packets {
+        aggregator.send(p);
+    }
+    aggregator.wait_for_packets(5);
+    assert_eq!(aggregator.get_evicted_count(), 1);
+    let flow = completed.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!((flow.connection.source_port, flow.reason), (Some(2), ExpiryReason::Evicted));
+    assert_eq!(aggregator.get_aggregated_data().read().unwrap().len(), 3);
+    //smallest bytes: the scan-like single packet entries are evicted first
+    let aggregator = Aggregator::with_config(AggregatorConfig { max_entries: Some(2), eviction: EvictionPolicy::SmallestBytes, ..Default::default() });
+    let packets = [packet(1667775485, 1, 1500), packet(1667775486, 2, 60), packet(1667775487, 3, 60), packet(1667775488, 4, 60)];
+    for p in packets {
+        aggregator.send(p);
+    }
+    aggregator.wait_for_packets(4);
+    assert_eq!(aggregator.get_evicted_count(), 2);
+    let binding = aggregator.get_aggregated_data();
+    let aggregated_data = binding.read().unwrap();
+    assert_eq!(aggregated_data.len(), 2);
+    assert!(aggregated_data.contains_key(&Connection::new(client, server, Some(1), Some(80), Protocol::Tcp, None)));
+    assert!(aggregated_data.contains_key(&Connection::new(client, server, Some(4), Some(80), Protocol::Tcp, None)));
+    //a map without room for any entry is rejected
+    assert!(std::panic::catch_unwind(|| Aggregator::with_config(AggregatorConfig { max_entries: Some(0), ..Default::default() })).is_err());
+}
+
+#[test]
+fn test_aggregator_windows() {
+    let client : IpAddr = "192.168.1.2".parse().unwrap();
+    let server : IpAddr = "93.184.216.34".parse().unwrap();
+    let window = WindowConfig { width: Duration::from_secs(60), retention: 2 };
+    let aggregator = Aggregator::with_config(AggregatorConfig { window: Some(window), ..Default::default() });
+    //1667775480 is the start of a minute
+    for (timestamp, size) in [(1667775485, 100), (1667775530, 200), (1667775545, 300), (1667775590, 400), (1667775719, 500)] {
+        aggregator.send(ParsedPacket::new(ts(timestamp), client, server, Some(62911), Some(80), Protocol::Tcp, size));
+    }
+    aggregator.wait_for_packets(5);
+    let key = Connection::new(client, server, Some(62911), Some(80), Protocol::Tcp, None);
+    //lifetime totals are still kept
+    assert_eq!(aggregator.get_aggregated_data().read().unwrap().get(&key).unwrap().size, 1500);
+    let binding = aggregator.get_windowed_data();
+    let windowed_data = binding.read().unwrap();
+    //the first window is beyond the retention
+    assert_eq!(windowed_data.keys().copied().collect::<Vec<_>>(), vec![ts(1667775540), ts(1667775660)]);
+    let value = windowed_data[&ts(1667775540)].get(&key).unwrap();
+    assert_eq!((value.size, value.packets), (700, 2));
+    assert_eq!((value.first_timestamp, value.last_timestamp), (ts(1667775545), ts(1667775590)));
+    assert_eq!(windowed_data[&ts(1667775660)].get(&key).unwrap().size, 500);
+    //a flow record is spread over the windows it spans, in proportion to its duration in each of them
+    let window = WindowConfig { width: Duration::from_secs(60), retention: 3 };
+    let aggregator = Aggregator::with_config(AggregatorConfig { window: Some(window), ..Default::default() });
+    let mut flow = ParsedPacket::new(ts(1667775630), client, server, Some(62911), Some(80), Protocol::Tcp, 1200);
+    flow.flow = Some(FlowSummary { packets: 4, first_timestamp: ts(1667775510) });
+    aggregator.send(flow);
+    aggregator.wait_for_flow_records(1);
+    let binding = aggregator.get_windowed_data();
+    let windowed_data = binding.read().unwrap();
+    let parts: Vec<(usize, usize)> = windowed_data.values().map(|entries| (entries[&key].size, entries[&key].packets)).collect();
+    assert_eq!(parts, vec![(300, 1), (600, 2), (300, 1)]);
+    let value = &windowed_data[&ts(1667775540)][&key];
+    assert_eq!((value.first_timestamp, value.last_timestamp), (ts(1667775540), ts(1667775599) + chrono::Duration::microseconds(999_999)));
+    assert_eq!(windowed_data[&ts(1667775600)][&key].last_timestamp, ts(1667775630));
+    drop(windowed_data);
+    //a flow of a day with millisecond windows only walks the windows kept by the retention
+    let window = WindowConfig { width: Duration::from_millis(1), retention: 2 };
+    let aggregator = Aggregator::with_config(AggregatorConfig { window: Some(window), ..Default::default() });
+    let mut flow = ParsedPacket::new(ts(1667775485 + 86400), client, server, Some(62911), Some(80), Protocol::Tcp, 86_400_000);
+    flow.flow = Some(FlowSummary { packets: 86_400_000, first_timestamp: ts(1667775485) });
+    aggregator.send(flow);
+    //an older flow falls entirely before the windows kept
+    let mut flow = ParsedPacket::new(ts(1667775485 + 3600), client, server, Some(62911), Some(80), Protocol::Tcp, 1000);
+    flow.flow = Some(FlowSummary { packets: 1, first_timestamp: ts(1667775485) });
+    aggregator.send(flow);
+    aggregator.wait_for_flow_records(2);
+    let binding = aggregator.get_windowed_data();
+    let windowed_data = binding.read().unwrap();
+    let parts: Vec<(usize, usize)> = windowed_data.values().map(|entries| (entries[&key].size, entries[&key].packets)).collect();
+    //the flow ends at the start of the last window, so only the window before gets a share
+    assert_eq!(parts, vec![(1, 1)]);
+    //windows shorter than a microsecond cannot be aligned
+    let window = WindowConfig { width: Duration::from_nanos(500), retention: 2 };
+    assert!(std::panic::catch_unwind(|| Aggregator::with_config(AggregatorConfig { window: Some(window), ..Default::default() })).is_err());
+}
+
+#[test]
+fn test_connection_metadata_counters() {
+    let mut value = ConnectionMetadata::new(1500, ts(1667775485), ts(1667775485));
+    //a single packet has no lifetime, so no throughput
+    assert_eq!(value.packets, 1);
+    assert_eq!(value.bytes_per_second(), None);
+    assert_eq!(value.packets_per_second(), None);
+    value.update(60, ts(1667775487));
+    value.update(90, ts(1667775489));
+    assert_eq!(value.size, 1650);
+    assert_eq!(value.packets, 3);
+    assert_eq!(value.min_size, 60);
+    assert_eq!(value.max_size, 1500);
+    assert_eq!(value.mean_size(), 550.0);
+    assert_eq!(value.duration(), 4.0);
+    assert_eq!(value.bytes_per_second(), Some(412.5));
+    assert_eq!(value.packets_per_second(), Some(0.75));
+    assert!(value.to_string().starts_with(" 1650      \t| 3       \t| 60    \t| 1500  \t| 550.0   \t| 412.5     \t| 0.75    \t|"));
+    //the bytes and packets of each direction follow the throughput
+    value.update_responder(40, ts(1667775489));
+    assert!(value.to_string().contains("\t| 1.00    \t| 1650      \t| 3       \t| 40        \t| 1       \t|"));
+    //a packet arriving out of order only extends the lifetime of the connection
+    value.update(60, ts(1667775486));
+    assert_eq!((value.first_timestamp, value.last_timestamp), (ts(1667775485), ts(1667775489)));
+    value.update_responder(60, ts(1667775484));
+    assert_eq!((value.first_timestamp, value.last_timestamp), (ts(1667775484), ts(1667775489)));
+    assert_eq!(value.duration(), 5.0);
+}
+
+#[test]
+fn test_csv_report() {
+    let mut aggregated_data = HashMap::new();
+    let connection = Connection::new("192.168.1.2".parse().unwrap(), "2001:db8::1".parse().unwrap(), Some(62911), Some(80), Protocol::Tcp, Some(String::from("eth0, \"uplink\"")));
+    let mut value = ConnectionMetadata::new(100, ts(1667775485), ts(1667775485));
+    value.update(300, ts(1667775489));
+    aggregated_data.insert(connection, value);
+    let connection = Connection::new("192.168.1.2".parse().unwrap(), "8.8.8.8".parse().unwrap(), None, None, Protocol::Icmpv4 { icmp_type: 8, code: 0 }, None);
+    aggregated_data.insert(connection, ConnectionMetadata::new(64, ts(1667775485), ts(1667775485)));
+    let mut windowed_data = WindowedData::new();
+    windowed_data.insert(ts(1667775480), aggregated_data.clone());
+    let mut output = Vec::new();
+    ReportFormat::Csv.write(&mut output, &aggregated_data, Some(&windowed_data)).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let mut lines: Vec<&str> = output.split_terminator("\r\n").collect();
+    assert_eq!(lines.len(), 5);
+    assert_eq!(lines.remove(0), "window_start,interface,source_address,destination_address,source_port,destination_port,protocol,ip_protocol,bytes,packets,min_size,max_size,mean_size,bytes_per_second,packets_per_second,initiator_bytes,initiator_packets,responder_bytes,responder_packets,first_timestamp,last_timestamp");
+    lines.sort();
+    //commas and quotes are escaped, numbers are not formatted, missing values are empty
+    assert_eq!(lines[0], ",\"eth0, \"\"uplink\"\"\",192.168.1.2,2001:db8::1,62911,80,TCP,6,400,2,100,300,200,100,0.5,400,2,0,0,2022-11-06T22:58:05.000000Z,2022-11-06T22:58:09.000000Z");
+    assert_eq!(lines[1], ",,192.168.1.2,8.8.8.8,,,ICMPv4: Echo Request,1,64,1,64,64,64,,,64,1,0,0,2022-11-06T22:58:05.000000Z,2022-11-06T22:58:05.000000Z");
+    assert!(lines[2].starts_with("2022-11-06T22:58:00.000000Z,"));
+    assert_eq!(ReportFormat::Csv.extension(), "csv");
+    assert_eq!(ReportFormat::default().extension(), "md");
+}
+
+#[test]
+fn test_report_options() {
+    let mut aggregated_data = HashMap::new();
+    let web = Connection::new("192.168.1.2".parse().unwrap(), "93.184.216.34".parse().unwrap(), Some(62911), Some(443), Protocol::Tcp, None);
+    let mut value = ConnectionMetadata::new(1500, ts(1667775485), ts(1667775485));
+    value.update(1500, ts(1667775490));
+    aggregated_data.insert(web.clone(), value);
+    let dns = Connection::new("192.168.1.3".parse().unwrap(), "8.8.8.8".parse().unwrap(), Some(5353), Some(53), Protocol::Udp, None);
+    let mut value = ConnectionMetadata::new(80, ts(1667775481), ts(1667775481));
+    for i in 0..3 {
+        value.update(80, ts(1667775482 + i));
+    }
+    aggregated_data.insert(dns.clone(), value);
+    let ping = Connection::new("10.0.0.7".parse().unwrap(), "192.168.1.2".parse().unwrap(), None, None, Protocol::Icmpv4 { icmp_type: 0, code: 0 }, None);
+    aggregated_data.insert(ping.clone(), ConnectionMetadata::new(64, ts(1667775483), ts(1667775483)));
+
+    let order = |options: &ReportOptions| -> Vec<Connection> {
+        options.select(&aggregated_data).into_iter().map(|(conn, _)| conn.clone()).collect()
+    };
+    let sorted = |sort: SortKey| ReportOptions { sort: Some(sort), ..Default::default() };
+    assert_eq!(order(&sorted(SortKey::Bytes)), vec![web.clone(), dns.clone(), ping.clone()]);
+    assert_eq!(order(&sorted(SortKey::Packets)), vec![dns.clone(), web.clone(), ping.clone()]);
+    assert_eq!(order(&sorted(SortKey::FirstSeen)), vec![dns.clone(), ping.clone(), web.clone()]);
+    assert_eq!(order(&sorted(SortKey::LastSeen)), vec![web.clone(), dns.clone(), ping.clone()]);
+    assert_eq!(order(&sorted(SortKey::Address)), vec![ping.clone(), web.clone(), dns.clone()]);
+    assert_eq!(order(&ReportOptions { sort: Some(SortKey::Bytes), reverse: true, top: Some(2), ..Default::default() }), vec![ping.clone(), dns.clone()]);
+    //filters
+    assert_eq!(order(&ReportOptions { protocols: vec![Protocol::Icmpv4 { icmp_type: 8, code: 0 }], ..Default::default() }), vec![ping.clone()]);
+    assert_eq!(order(&ReportOptions { sort: Some(SortKey::Bytes), ports: vec![53, 443], ..Default::default() }), vec![web.clone(), dns.clone()]);
+    let lan = AddressRange::from_cidr("192.168.1.77".parse().unwrap(), 24);
+    assert!(lan.contains("192.168.1.0".parse().unwrap()) && lan.contains("192.168.1.255".parse().unwrap()) && !lan.contains("192.168.2.0".parse().unwrap()));
+    assert!(!lan.contains("::1".parse().unwrap()));
+    let hosts = AddressRange::new("192.168.1.3".parse().unwrap(), "192.168.1.10".parse().unwrap());
+    assert_eq!(order(&ReportOptions { addresses: vec![hosts], ..Default::default() }), vec![dns.clone()]);
+    assert_eq!(order(&ReportOptions { sort: Some(SortKey::Address), addresses: vec![lan], protocols: vec![Protocol::Tcp, Protocol::Icmpv4 { icmp_type: 0, code: 0 }], ..Default::default() }), vec![ping.clone(), web.clone()]);
+    assert!(AddressRange::from_cidr("2001:db8::".parse().unwrap(), 32).contains("2001:db8:ffff::1".parse().unwrap()));
+    assert!(AddressRange::from_cidr("0.0.0.0".parse().unwrap(), 0).contains("255.255.255.255".parse().unwrap()));
+    //the options apply to every table of the report
+    let mut windowed_data = WindowedData::new();
+    windowed_data.insert(ts(1667775480), aggregated_data.clone());
+    let options = ReportOptions { sort: Some(SortKey::Packets), top: Some(1), ..Default::default() };
+    let report = Report { name: "traffic", format: ReportFormat::Csv, aggregated_data: &aggregated_data, windowed_data: Some(&windowed_data), options: &options, capture_stats: None, delta: None, rewrite_time: None };
+    let output = String::from_utf8(report.render()).unwrap();
+    let lines: Vec<&str> = output.split_terminator("\r\n").collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].starts_with(",,192.168.1.3,8.8.8.8,5353,53,"));
+    assert!(lines[2].starts_with("2022-11-06T22:58:00.000000Z,,192.168.1.3,"));
+}
+
+#[test]
+fn test_json_report() {
+    let mut aggregated_data = HashMap::new();
+    let connection = Connection::new("192.168.1.2".parse().unwrap(), "2001:db8::1".parse().unwrap(), Some(62911), Some(80), Protocol::Tcp, Some(String::from("eth0")));
+    let mut value = ConnectionMetadata::new(100, ts(1667775485), ts(1667775485));
+    value.update(300, ts(1667775489));
+    aggregated_data.insert(connection, value);
+    let mut windowed_data = WindowedData::new();
+    windowed_data.insert(ts(1667775480), aggregated_data.clone());
+    let connection = Connection::new("192.168.1.2".parse().unwrap(), "8.8.8.8".parse().unwrap(), None, None, Protocol::Icmpv4 { icmp_type: 8, code: 0 }, None);
+    aggregated_data.insert(connection, ConnectionMetadata::new(64, ts(1667775485), ts(1667775485)));
+
+    let mut output = Vec::new();
+    ReportFormat::Json.write(&mut output, &aggregated_data, Some(&windowed_data)).unwrap();
+    let document: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(document["schema_version"], 1);
+    let connections = document["connections"].as_array().unwrap();
+    assert_eq!(connections.len(), 2);
+    let tcp = connections.iter().find(|c| c["protocol"] == "TCP").unwrap();
+    assert_eq!(tcp["interface"], "eth0");
+    assert_eq!(tcp["source_address"], "192.168.1.2");
+    assert_eq!(tcp["destination_address"], "2001:db8::1");
+    assert_eq!((tcp["source_port"].as_u64(), tcp["destination_port"].as_u64()), (Some(62911), Some(80)));
+    assert_eq!((tcp["bytes"].as_u64(), tcp["packets"].as_u64(), tcp["mean_size"].as_f64()), (Some(400), Some(2), Some(200.0)));
+    assert_eq!(tcp["bytes_per_second"].as_f64(), Some(100.0));
+    assert_eq!(tcp["first_timestamp"], "2022-11-06T22:58:05.000000Z");
+    let icmp = connections.iter().find(|c| c["ip_protocol"] == 1).unwrap();
+    assert!(icmp["source_port"].is_null() && icmp["bytes_per_second"].is_null() && icmp["interface"].is_null());
+    assert_eq!((icmp["icmp_type"].as_u64(), icmp["icmp_code"].as_u64()), (Some(8), Some(0)));
+    let windows = document["windows"].as_array().unwrap();
+    assert_eq!(windows[0]["start"], "2022-11-06T22:58:00.000000Z");
+    assert_eq!(windows[0]["connections"].as_array().unwrap().len(), 1);
+
+    let mut output = Vec::new();
+    ReportFormat::Ndjson.write(&mut output, &aggregated_data, Some(&windowed_data)).unwrap();
+    let lines: Vec<serde_json::Value> = String::from_utf8(output).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines.iter().filter(|line| line["window_start"].is_null()).count(), 2);
+    assert_eq!(lines[2]["window_start"], "2022-11-06T22:58:00.000000Z");
+    assert_eq!(lines[2]["bytes"], 400);
+}
+
+/// Sink counting the connections of each report
+struct CountSink(std::sync::Arc<std::sync::Mutex<Vec<usize>>>);
+
+impl ReportSink for CountSink {
+    fn write_report(&mut self, report: &Report) -> std::io::Result<()> {
+        self.0.lock().unwrap().push(report.aggregated_data.len());
+        Ok(())
+    }
+}
+
+struct FailingSink;
+
+impl ReportSink for FailingSink {
+    fn write_report(&mut self, _report: &Report) -> std::io::Result<()> {
+        Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "read-only output"))
+    }
+}
+
+#[test]
+fn test_report_writer_failing_sink() {
+    let aggregator = Aggregator::new();
+    let memory = MemorySink::new();
+    let report_writer = ReportWriter::new(String::from("test_report_writer_failing_sink"), 3600, aggregator.get_aggregated_data());
+    report_writer.set_sinks(vec![Box::new(FailingSink), Box::new(memory.clone())]);
+    //the error is returned and the report is still written to the other sinks
+    assert_eq!(report_writer.write_now().unwrap_err().kind(), std::io::ErrorKind::PermissionDenied);
+    assert_eq!(memory.get_reports().len(), 1);
+    assert!(report_writer.stop().is_err());
+    assert_eq!(memory.get_reports().len(), 2);
+    //dropping the writer after a failure does not panic
+    drop(report_writer);
+}
+
+#[test]
+fn test_report_sinks() {
+    let aggregator = Aggregator::new();
+    aggregator.send(ParsedPacket::new(ts(1667775485), "192.168.1.2".parse().unwrap(), "8.8.8.8".parse().unwrap(), Some(5353), Some(53), Protocol::Udp, 80));
+    aggregator.wait_for_packets(1);
+    let report_writer = ReportWriter::new(String::from("test_report_sinks"), 1, aggregator.get_aggregated_data());
+    let memory = MemorySink::new();
+    let counts = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    //replace the default file sink before the first tick
+    report_writer.set_sinks(vec![Box::new(memory.clone())]);
+    report_writer.add_sink(Box::new(CountSink(counts.clone())));
+    report_writer.set_format(ReportFormat::Ndjson);
+    std::thread::sleep(Duration::from_millis(1500));
+    report_writer.pause();
+    let report = memory.get_last_report().unwrap();
+    assert_eq!(report.lines().count(), 1);
+    assert!(report.contains("\"destination_address\":\"8.8.8.8\""));
+    assert_eq!(counts.lock().unwrap()[0], 1);
+    assert_eq!(memory.get_reports().len(), counts.lock().unwrap().len());
+}
+
+#[test]
+fn test_report_writer_write_now() {
+    let aggregator = Aggregator::new();
+    aggregator.send(ParsedPacket::new(ts(1667775485), "192.168.1.2".parse().unwrap(), "8.8.8.8".parse().unwrap(), Some(5353), Some(53), Protocol::Udp, 80));
+    aggregator.wait_for_packets(1);
+    let memory = MemorySink::new();
+    let report_writer = ReportWriter::new(String::from("test_report_writer_write_now"), 3600, aggregator.get_aggregated_data());
+    report_writer.set_sinks(vec![Box::new(memory.clone())]);
+    report_writer.set_format(ReportFormat::Ndjson);
+    //the report is written without waiting for the timer, even when paused
+    report_writer.write_now().unwrap();
+    assert_eq!(memory.get_reports().len(), 1);
+    report_writer.pause();
+    aggregator.send(ParsedPacket::new(ts(1667775486), "192.168.1.2".parse().unwrap(), "1.1.1.1".parse().unwrap(), Some(5353), Some(53), Protocol::Udp, 80));
+    aggregator.wait_for_packets(2);
+    report_writer.write_now().unwrap();
+    assert_eq!(memory.get_last_report().unwrap().lines().count(), 2);
+    //stopping writes the final report once, the final HTML report is not reloaded anymore
+    report_writer.set_format(ReportFormat::Html);
+    report_writer.write_now().unwrap();
+    assert!(memory.get_last_report().unwrap().contains("http-equiv"));
+    let reports = memory.get_reports().len();
+    report_writer.stop().unwrap();
+    report_writer.stop().unwrap();
+    assert_eq!(memory.get_reports().len(), reports + 1);
+    assert!(!memory.get_last_report().unwrap().contains("http-equiv"));
+    //dropping the last clone of a running writer writes the final report, dropping the other clones does not stop it
+    let report_writer = ReportWriter::new(String::from("test_report_writer_write_now"), 3600, aggregator.get_aggregated_data());
+    report_writer.set_sinks(vec![Box::new(memory.clone())]);
+    drop(report_writer.clone());
+    assert_eq!(memory.get_reports().len(), reports + 1);
+    report_writer.write_now().unwrap();
+    assert_eq!(memory.get_reports().len(), reports + 2);
+    drop(report_writer);
+    assert_eq!(memory.get_reports().len(), reports + 3);
+}
+
+#[test]
+fn test_delta_reports() {
+    let web = Connection::new("192.168.1.2".parse().unwrap(), "93.184.216.34".parse().unwrap(), Some(62911), Some(443), Protocol::Tcp, None);
+    let dns = Connection::new("192.168.1.2".parse().unwrap(), "8.8.8.8".parse().unwrap(), Some(5353), Some(53), Protocol::Udp, None);
+    let ntp = Connection::new("192.168.1.2".parse().unwrap(), "162.159.200.1".parse().unwrap(), Some(123), Some(123), Protocol::Udp, None);
+    let mut previous = HashMap::new();
+    previous.insert(web.clone(), ConnectionMetadata::new(100, ts(1667775485), ts(1667775485)));
+    previous.insert(dns.clone(), ConnectionMetadata::new(80, ts(1667775485), ts(1667775485)));
+    let mut current = previous.clone();
+    current.get_mut(&web).unwrap().update(1500, ts(1667775487));
+    current.get_mut(&web).unwrap().update(500, ts(1667775488));
+    current.remove(&dns);
+    current.insert(ntp.clone(), ConnectionMetadata::new(90, ts(1667775488), ts(1667775488)));
+    let delta = DeltaReport::compute(&previous, &current, Duration::from_secs(4));
+    assert_eq!(delta.changes.len(), 3);
+    let change = delta.changes[&web];
+    assert_eq!((change.status, change.bytes, change.packets), (ChangeStatus::Updated, 2000, 2));
+    assert_eq!((change.bytes_per_second, change.packets_per_second), (Some(500.0), Some(0.5)));
+    assert_eq!((delta.changes[&ntp].status, delta.changes[&ntp].bytes), (ChangeStatus::New, 90));
+    assert_eq!((delta.changes[&dns].status, delta.changes[&dns].bytes), (ChangeStatus::Disappeared, 0));
+    assert_eq!(delta.disappeared[&dns].size, 80);
+    //no change: the connection is left out of the table of the changes
+    let unchanged = DeltaReport::compute(&current, &current, Duration::from_secs(0));
+    assert_eq!(unchanged.changes[&web].status, ChangeStatus::Unchanged);
+    assert_eq!(unchanged.changes[&web].bytes_per_second, None);
+    assert!(ReportOptions::default().select_changes(&unchanged).is_empty());
+    let changed: Vec<&Connection> = ReportOptions::default().select_changes(&delta).into_iter().map(|(conn, _)| conn).collect();
+    assert_eq!(changed, vec![&web, &ntp, &dns]);
+
+    let options = ReportOptions::default();
+    let report = Report { name: "traffic", format: ReportFormat::Csv, aggregated_data: &current, windowed_data: None, options: &options, capture_stats: None, delta: Some(&delta), rewrite_time: None };
+    let csv = String::from_utf8(report.render()).unwrap();
+    let mut lines: Vec<&str> = csv.split_terminator("\r\n").collect();
+    assert!(lines.remove(0).ends_with(",last_timestamp,change,delta_bytes,delta_packets,delta_bytes_per_second,delta_packets_per_second"));
+    //the disappeared connection is written after the current ones, with its last totals
+    assert_eq!(lines.len(), 3);
+    assert!(lines[2].starts_with(",,192.168.1.2,8.8.8.8,5353,53,UDP,17,80,") && lines[2].ends_with(",disappeared,0,0,0,0"));
+    assert!(lines.iter().any(|line| line.contains(",443,") && line.ends_with(",updated,2000,2,500,0.5")));
+    let report = Report { format: ReportFormat::Json, ..report };
+    let document: serde_json::Value = serde_json::from_slice(&report.render()).unwrap();
+    assert_eq!(document["changes"]["interval_seconds"], 4.0);
+    assert_eq!(document["changes"]["connections"][0]["change"], "updated");
+    assert_eq!(document["changes"]["connections"][0]["delta_bytes"], 2000);
+    assert_eq!(document["changes"]["connections"][2]["bytes"], 80);
+    let report = Report { format: ReportFormat::Markdown, ..report };
+    let markdown = String::from_utf8(report.render()).unwrap();
+    assert!(markdown.starts_with("## Changes in the last 4.0 s\n"));
+    assert!(markdown.contains("## Connections\n"));
+
+    //the report writer keeps the previous report to compute the changes
+    let aggregator = Aggregator::new();
+    let memory = MemorySink::new();
+    let report_writer = ReportWriter::new(String::from("test_delta_reports"), 3600, aggregator.get_aggregated_data());
+    report_writer.set_sinks(vec![Box::new(memory.clone())]);
+    report_writer.set_format(ReportFormat::Ndjson);
+    report_writer.set_delta_reports(true);
+    assert!(report_writer.get_delta_reports());
+    aggregator.send(ParsedPacket::new(ts(1667775485), "192.168.1.2".parse().unwrap(), "8.8.8.8".parse().unwrap(), Some(5353), Some(53), Protocol::Udp, 80));
+    aggregator.wait_for_packets(1);
+    report_writer.write_now().unwrap();
+    let line: serde_json::Value = serde_json::from_str(memory.get_last_report().unwrap().trim()).unwrap();
+    assert_eq!((line["change"].as_str(), line["delta_bytes"].as_u64()), (Some("new"), Some(80)));
+    aggregator.send(ParsedPacket::new(ts(1667775486), "192.168.1.2".parse().unwrap(), "8.8.8.8".parse().unwrap(), Some(5353), Some(53), Protocol::Udp, 120));
+    aggregator.wait_for_packets(2);
+    report_writer.write_now().unwrap();
+    let line: serde_json::Value = serde_json::from_str(memory.get_last_report().unwrap().trim()).unwrap();
+    assert_eq!((line["change"].as_str(), line["delta_bytes"].as_u64(), line["bytes"].as_u64()), (Some("updated"), Some(120), Some(200)));
+    report_writer.set_delta_reports(false);
+    report_writer.write_now().unwrap();
+    assert!(!memory.get_last_report().unwrap().contains("\"change\""));
+}
+
+#[test]
+fn test_html_report() {
+    let web = Connection::new("192.168.1.2".parse().unwrap(), "93.184.216.34".parse().unwrap(), Some(62911), Some(443), Protocol::Tcp, Some(String::from("<eth0>")));
+    let dns = Connection::new("192.168.1.3".parse().unwrap(), "8.8.8.8".parse().unwrap(), Some(5353), Some(53), Protocol::Udp, None);
+    let mut aggregated_data = HashMap::new();
+    let mut web_data = ConnectionMetadata::new(1000, ts(1667775485), ts(1667775486));
+    web_data.update_responder(500, ts(1667775487));
+    aggregated_data.insert(web.clone(), web_data);
+    aggregated_data.insert(dns.clone(), ConnectionMetadata::new(500, ts(1667775485), ts(1667775485)));
+    let options = ReportOptions::default();
+    let report = Report { name: "traffic", format: ReportFormat::Html, aggregated_data: &aggregated_data, windowed_data: None, options: &options, capture_stats: None, delta: None, rewrite_time: Some(5) };
+    assert_eq!(ReportFormat::Html.extension(), "html");
+    let html = String::from_utf8(report.render()).unwrap();
+    assert!(html.starts_with("<!DOCTYPE html>") && html.trim_end().ends_with("</html>"));
+    assert!(html.contains("<meta http-equiv=\"refresh\" content=\"5\">"));
+    //the page is self-contained
+    assert!(!html.contains("src=") && !html.contains("href=") && !html.contains("http://") && !html.contains("https://"));
+    assert!(html.contains("<script>") && html.contains("<style>"));
+    //pie chart of the bytes per protocol and bar chart of the top talkers
+    assert_eq!(html.matches("<svg").count(), 2);
+    assert!(html.contains("TCP: 1500 bytes (75.0%)") && html.contains("UDP: 500 bytes (25.0%)"));
+    //each host is credited with the bytes it sent
+    assert!(html.contains("<title>192.168.1.2: 1000 bytes</title></rect>") && html.contains("<title>93.184.216.34: 500 bytes</title></rect>"));
+    //one table of connections with sortable numeric cells, strings are escaped
+    assert_eq!(html.matches("<table class=\"connections\">").count(), 1);
+    assert_eq!(html.matches("<tr><td").count(), 2);
+    assert!(html.contains("<td class=\"text\">&lt;eth0&gt;</td>") && !html.contains("<eth0>"));
+    assert!(html.contains("<td data-sort=\"62911\">62911</td>"));
+    //bytes and packets of each direction, as in the other formats
+    assert!(html.contains("<th>Initiator bytes</th><th>Initiator packets</th><th>Responder bytes</th><th>Responder packets</th>"));
+    assert!(html.contains("<td>1000</td><td>1</td><td>500</td><td>1</td>"));
+
+    //a single protocol is drawn as a full circle, the changes get their own table
+    let delta = DeltaReport::compute(&HashMap::new(), &aggregated_data, Duration::from_secs(2));
+    let options = ReportOptions { protocols: vec![Protocol::Udp], ..Default::default() };
+    let report = Report { options: &options, delta: Some(&delta), rewrite_time: None, ..report };
+    let html = String::from_utf8(report.render()).unwrap();
+    assert!(!html.contains("http-equiv"));
+    assert!(html.contains("<circle r=\"100\" fill=\"#4e79a7\"><title>UDP: 500 bytes</title></circle>"));
+    assert!(html.contains("<h2>Changes in the last 2.0 s</h2>"));
+    assert_eq!(html.matches("<table class=\"connections\">").count(), 2);
+    assert_eq!(html.matches("<tr><td").count(), 2);
+}
+
+#[test]
+fn test_file_sink() {
+    let directory = std::env::temp_dir().join("network_analyzer_test_file_sink").join("reports");
+    let _ = std::fs::remove_dir_all(&directory);
+    let mut aggregated_data = HashMap::new();
+    let connection = Connection::new("192.168.1.2".parse().unwrap(), "8.8.8.8".parse().unwrap(), Some(5353), Some(53), Protocol::Udp, None);
+    aggregated_data.insert(connection, ConnectionMetadata::new(80, ts(1667775485), ts(1667775485)));
+    let report = Report { name: "traffic", format: ReportFormat::Csv, aggregated_data: &aggregated_data, windowed_data: None, options: &ReportOptions::default(), capture_stats: None, delta: None, rewrite_time: None };
+    //the directory is created and the extension of the format is used
+    let mut sink = FileSink::with_directory(&directory, None);
+    sink.write_report(&report).unwrap();
+    let content = std::fs::read_to_string(directory.join("traffic.csv")).unwrap();
+    assert_eq!(content.lines().count(), 2);
+    //a custom extension replaces the previous report, no temporary file is left behind
+    let mut sink = FileSink::with_directory(&directory, Some(String::from("txt")));
+    assert_eq!(sink.get_report_path("traffic", ReportFormat::Csv), directory.join("traffic.txt"));
+    sink.write_report(&report).unwrap();
+    aggregated_data.clear();
+    let report = Report { name: "traffic", format: ReportFormat::Csv, aggregated_data: &aggregated_data, windowed_data: None, options: &ReportOptions::default(), capture_stats: None, delta: None, rewrite_time: None };
+    sink.write_report(&report).unwrap();
+    assert_eq!(std::fs::read_to_string(directory.join("traffic.txt")).unwrap().lines().count(), 1);
+    let mut files: Vec<String> = std::fs::read_dir(&directory).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+    files.sort();
+    assert_eq!(files, vec!["traffic.csv", "traffic.txt"]);
+    assert_eq!(FileSink::new().get_report_path("traffic", ReportFormat::Markdown), std::path::Path::new("report/traffic.md"));
+}
+
+#[test]
+fn test_file_sink_rotation() {
+    use std::io::Read;
+    let directory = std::env::temp_dir().join("network_analyzer_test_file_sink_rotation");
+    let _ = std::fs::remove_dir_all(&directory);
+    let mut aggregated_data = HashMap::new();
+    let connection = Connection::new("192.168.1.2".parse().unwrap(), "8.8.8.8".parse().unwrap(), Some(5353), Some(53), Protocol::Udp, None);
+    aggregated_data.insert(connection, ConnectionMetadata::new(80, ts(1667775485), ts(1667775485)));
+    let report = Report { name: "traffic", format: ReportFormat::Csv, aggregated_data: &aggregated_data, windowed_data: None, options: &ReportOptions::default(), capture_stats: None, delta: None, rewrite_time: None };
+    let file_names = |sink: &FileSink| -> Vec<String> {
+        sink.get_archive_paths("traffic", ReportFormat::Csv).unwrap().iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap().to_string()).collect()
+    };
+    //timestamped reports: only the most recent is left uncompressed, the oldest are pruned
+    let policy = RotationPolicy { rotation: Rotation::Timestamped, compress: true, retention: Some(2) };
+    let mut sink = FileSink::with_rotation(directory.join("timestamped"), None, policy);
+    for _ in 0..3 {
+        sink.write_report(&report).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    let archives = file_names(&sink);
+    assert_eq!(archives.len(), 2);
+    assert!(archives[0].starts_with("traffic-") && archives[0].ends_with(".csv.gz"));
+    assert!(archives[1].ends_with(".csv"));
+    assert!(!sink.get_report_path("traffic", ReportFormat::Csv).exists());
+    //the compressed archive holds the whole report
+    let mut content = String::new();
+    flate2::read::GzDecoder::new(std::fs::File::open(directory.join("timestamped").join(&archives[0])).unwrap()).read_to_string(&mut content).unwrap();
+    assert_eq!(content, String::from_utf8(report.render()).unwrap());
+    //count rotation: the reports are appended to the current file with a single header row
+    let policy = RotationPolicy { rotation: Rotation::Count(2), compress: false, retention: Some(1) };
+    let mut sink = FileSink::with_rotation(directory.join("count"), None, policy);
+    for _ in 0..2 {
+        sink.write_report(&report).unwrap();
+    }
+    let current = sink.get_report_path("traffic", ReportFormat::Csv);
+    assert_eq!(std::fs::read_to_string(&current).unwrap().lines().count(), 3);
+    assert!(file_names(&sink).is_empty());
+    for _ in 0..3 {
+        sink.write_report(&report).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    //two rotations, only the most recent archive is kept
+    let archives = file_names(&sink);
+    assert_eq!(archives.len(), 1);
+    assert_eq!(std::fs::read_to_string(directory.join("count").join(&archives[0])).unwrap().lines().count(), 3);
+    assert_eq!(std::fs::read_to_string(&current).unwrap().lines().count(), 2);
+    //after a restart the file of the previous run is archived, its reports are not counted
+    let mut sink = FileSink::with_rotation(directory.join("count"), None, RotationPolicy { retention: None, ..policy });
+    sink.write_report(&report).unwrap();
+    assert_eq!(file_names(&sink).len(), 2);
+    assert_eq!(std::fs::read_to_string(&current).unwrap().lines().count(), 2);
+    sink.write_report(&report).unwrap();
+    assert_eq!(file_names(&sink).len(), 2);
+    //size rotation: the file is archived before it grows beyond the limit
+    let size = report.render().len() as u64;
+    let policy = RotationPolicy { rotation: Rotation::Size(size * 2), compress: true, retention: None };
+    let mut sink = FileSink::with_rotation(directory.join("size"), None, policy);
+    for _ in 0..3 {
+        sink.write_report(&report).unwrap();
+    }
+    let archives = file_names(&sink);
+    assert_eq!(archives.len(), 1);
+    assert!(archives[0].ends_with(".csv.gz"));
+    assert_eq!(std::fs::read_to_string(sink.get_report_path("traffic", ReportFormat::Csv)).unwrap().lines().count(), 2);
+    //archives created in the same millisecond do not replace each other
+    let policy = RotationPolicy { rotation: Rotation::Count(1), compress: false, retention: None };
+    let mut sink = FileSink::with_rotation(directory.join("burst"), None, policy);
+    for _ in 0..4 {
+        sink.write_report(&report).unwrap();
+    }
+    assert_eq!(file_names(&sink).len(), 3);
+    //the file is archived when the columns change, so that it keeps a single header row
+    let policy = RotationPolicy { rotation: Rotation::Count(10), compress: false, retention: None };
+    let mut sink = FileSink::with_rotation(directory.join("columns"), None, policy);
+    sink.write_report(&report).unwrap();
+    let delta = DeltaReport::compute(&HashMap::new(), &aggregated_data, Duration::from_secs(1));
+    sink.write_report(&Report { delta: Some(&delta), ..report }).unwrap();
+    assert_eq!(file_names(&sink).len(), 1);
+    let content = std::fs::read_to_string(sink.get_report_path("traffic", ReportFormat::Csv)).unwrap();
+    assert_eq!(content.lines().count(), 2);
+    assert!(content.lines().next().unwrap().ends_with("delta_packets_per_second"));
+    //JSON and HTML documents cannot be appended to each other
+    let mut sink = FileSink::with_rotation(directory.join("json"), None, policy);
+    let error = sink.write_report(&Report { format: ReportFormat::Json, ..report }).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    assert!(!sink.get_report_path("traffic", ReportFormat::Json).exists());
+    sink.write_report(&Report { format: ReportFormat::Ndjson, ..report }).unwrap();
+}
+
 #[test]
 fn test_parser(){
-    println!("test_parser starting");
+    let path = std::env::temp_dir().join("network_analyzer_test_parser.pcap");
+    write_pcap_file(&path, 1667775485, &test_frames());
     //define a channel to send the parsed packet
     let (sender, receiver) = mpsc::channel();
-    //create a parser
-    println!("before parser declaration");
-    let mut _parser = Parser::new("\\Device\\NPF_{CD484432-E2CB-46E8-8FCC-3D919CF3533E}",sender);
-    println!("after parser declaration, before waiting 5s");
-    //wait for the parser to finish
-    std::thread::sleep(std::time::Duration::from_secs(5));
-    println!("after waiting 5s");
-    //test the parsed packet
-    let parsed_packet = receiver.recv().unwrap();
-    println!("parsed packet received: {:?}",parsed_packet);
+    //create a parser reading the capture file
+    let parser = Parser::from_file(path.to_str().unwrap(), sender);
+    //wait for the parser to finish, the ARP frame is skipped
+    assert_eq!(parser.wait_for_completion(), 3);
+    assert!(parser.is_finished());
+    //test the parsed packets
+    let parsed: Vec<ParsedPacket> = receiver.try_iter().collect();
+    assert_eq!(parsed.len(), 3);
+    assert_eq!(parsed[0].source_ip, "192.168.1.2".parse::<IpAddr>().unwrap());
+    assert_eq!(parsed[0].destination_ip, "93.184.216.34".parse::<IpAddr>().unwrap());
+    assert_eq!(parsed[0].source_port, Some(62911));
+    assert_eq!(parsed[0].destination_port, Some(80));
+    assert_eq!(parsed[0].protocol, Protocol::Tcp);
+    assert_eq!(parsed[0].timestamp, ts(1667775485));
+    assert_eq!(parsed[2].destination_ip, "8.8.8.8".parse::<IpAddr>().unwrap());
+    assert_eq!(parsed[2].protocol, Protocol::Udp);
+    assert_eq!(parsed[2].size, 14 + 20 + 8 + 20);
+    assert_eq!(parsed[0].tcp_flags, Some(0));
+    assert_eq!(parsed[2].tcp_flags, None);
+}
 
+#[test]
+fn test_capture_stats(){
+    let path = std::env::temp_dir().join("network_analyzer_test_capture_stats.pcap");
+    write_pcap_file(&path, 1667775485, &test_frames());
+    let (sender, _receiver) = mpsc::channel();
+    let parser = Parser::from_file(path.to_str().unwrap(), sender);
+    parser.wait_for_completion();
+    let capture_stats = parser.get_capture_stats().lock().unwrap().clone();
+    assert_eq!(capture_stats.device, path.to_str().unwrap());
+    assert!(capture_stats.end_time.is_some());
+    //the ARP frame is counted as skipped
+    assert_eq!((capture_stats.packets, capture_stats.bytes, capture_stats.skipped), (4, 64 + 64 + 62 + 42, 1));
+    //libpcap does not report drops for capture files
+    assert_eq!((capture_stats.dropped, capture_stats.interface_dropped), (None, None));
+    assert_eq!(capture_stats.protocols.len(), 2);
+    assert_eq!((capture_stats.protocols[&6].packets, capture_stats.protocols[&6].bytes), (2, 128));
+    assert_eq!((capture_stats.protocols[&17].packets, capture_stats.protocols[&17].bytes), (1, 62));
+    //the elapsed time stops at the end of the capture
+    assert_eq!(capture_stats.elapsed(), capture_stats.elapsed());
+
+    let aggregated_data = HashMap::new();
+    let report = Report { name: "traffic", format: ReportFormat::Markdown, aggregated_data: &aggregated_data, windowed_data: None, options: &ReportOptions::default(), capture_stats: Some(&capture_stats), delta: None, rewrite_time: None };
+    let markdown = String::from_utf8(report.render()).unwrap();
+    assert!(markdown.starts_with("## Capture summary\n"));
+    assert!(markdown.contains("* Packets: 4 (232 bytes)\n"));
+    assert!(markdown.contains("* Skipped packets (not IP or with invalid headers): 1\n"));
+    assert!(markdown.contains("* Dropped packets: not available\n"));
+    assert!(markdown.contains("| TCP | 2 | 128 |\n| UDP | 1 | 62 |\n"));
+    let report = Report { format: ReportFormat::Json, ..report };
+    let document: serde_json::Value = serde_json::from_slice(&report.render()).unwrap();
+    let summary = &document["summary"];
+    assert_eq!((summary["packets"].as_u64(), summary["skipped_packets"].as_u64()), (Some(4), Some(1)));
+    assert!(summary["dropped_packets"].is_null());
+    assert_eq!(summary["protocols"][1]["protocol"], "UDP");
+    assert_eq!(summary["protocols"][1]["bytes"], 62);
 }
 
 #[test]
 fn test_parser_with_aggregator(){
+    let path = std::env::temp_dir().join("network_analyzer_test_parser_with_aggregator.pcap");
+    write_pcap_file(&path, 1667775485, &test_frames());
+    let sl = SocketListener::from_file(path.to_str().unwrap());
+    //wait for the parser/aggregator to process the whole file
+    sl.wait_for_completion();
+    assert!(sl.is_finished());
+    let binding = sl.get_aggregated_data();
+    let aggregated_data = binding.read().unwrap();
+    assert_eq!(aggregated_data.len(), 2);
+    let key = Connection::new("192.168.1.2".parse().unwrap(), "93.184.216.34".parse().unwrap(), Some(62911), Some(80), Protocol::Tcp, None);
+    let value = aggregated_data.get(&key).unwrap();
+    assert_eq!(value.size, 2 * (14 + 20 + 20 + 10));
+}
+
+/// Appends a pcapng block with the given type and body to the file bytes
+fn push_pcapng_block(bytes: &mut Vec<u8>, block_type: u32, body: &[u8]) {
+    let padded = (body.len() + 3) & !3;
+    let length = (12 + padded) as u32;
+    bytes.extend_from_slice(&block_type.to_le_bytes());
+    bytes.extend_from_slice(&length.to_le_bytes());
+    bytes.extend_from_slice(body);
+    bytes.resize(bytes.len() + padded - body.len(), 0);
+    bytes.extend_from_slice(&length.to_le_bytes());
+}
+
+/// Builds the body of an interface description block for an ethernet interface with the given name and timestamp resolution
+fn pcapng_interface(name: &str, tsresol: u8) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&1u16.to_le_bytes());
+    body.extend_from_slice(&0u16.to_le_bytes());
+    body.extend_from_slice(&0u32.to_le_bytes());
+    //if_name option, padded to 32 bits
+    body.extend_from_slice(&2u16.to_le_bytes());
+    body.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    body.extend_from_slice(name.as_bytes());
+    body.resize(body.len() + ((name.len() + 3) & !3) - name.len(), 0);
+    //if_tsresol option
+    body.extend_from_slice(&9u16.to_le_bytes());
+    body.extend_from_slice(&1u16.to_le_bytes());
+    body.extend_from_slice(&[tsresol, 0, 0, 0]);
+    //opt_endofopt
+    body.extend_from_slice(&[0u8; 4]);
+    body
+}
+
+/// Builds the body of an enhanced packet block for the given interface, timestamp and frame
+fn pcapng_packet(interface_id: u32, ts: u64, frame: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&interface_id.to_le_bytes());
+    body.extend_from_slice(&((ts >> 32) as u32).to_le_bytes());
+    body.extend_from_slice(&(ts as u32).to_le_bytes());
+    body.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+    body.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+    body.extend_from_slice(frame);
+    body
+}
+
+#[test]
+fn test_pcapng_interfaces(){
+    let frames = test_frames();
+    let mut bytes = Vec::new();
+    //section header block: byte order magic, version 1.0, unknown section length
+    let mut shb = Vec::new();
+    shb.extend_from_slice(&0x1A2B3C4Du32.to_le_bytes());
+    shb.extend_from_slice(&1u16.to_le_bytes());
+    shb.extend_from_slice(&0u16.to_le_bytes());
+    shb.extend_from_slice(&(-1i64).to_le_bytes());
+    push_pcapng_block(&mut bytes, 0x0A0D0D0A, &shb);
+    //eth0 with microsecond timestamps, wlan0 with nanosecond timestamps
+    push_pcapng_block(&mut bytes, 1, &pcapng_interface("eth0", 6));
+    push_pcapng_block(&mut bytes, 1, &pcapng_interface("wlan0", 9));
+    push_pcapng_block(&mut bytes, 6, &pcapng_packet(0, 1_667_775_485_000_001, &frames[0]));
+    push_pcapng_block(&mut bytes, 6, &pcapng_packet(1, 1_667_775_486_000_000_001, &frames[1]));
+    push_pcapng_block(&mut bytes, 6, &pcapng_packet(1, 1_667_775_487_000_000_001, &frames[3]));
+    let path = std::env::temp_dir().join("network_analyzer_test_pcapng_interfaces.pcapng");
+    std::fs::write(&path, &bytes).unwrap();
+
+    let (sender, receiver) = mpsc::channel();
+    let parser = Parser::from_file(path.to_str().unwrap(), sender);
+    //the ARP frame is skipped
+    assert_eq!(parser.wait_for_completion(), 2);
+    let parsed: Vec<ParsedPacket> = receiver.try_iter().collect();
+    assert_eq!(parsed[0].interface, Some("eth0".to_string()));
+    assert_eq!(parsed[1].interface, Some("wlan0".to_string()));
+    //nanosecond timestamps are kept
+    assert_eq!(parsed[1].timestamp, Utc.timestamp_opt(1667775486, 1).unwrap());
+
+    //the same 5-tuple seen on two interfaces produces two connections
+    let sl = SocketListener::from_file(path.to_str().unwrap());
+    sl.wait_for_completion();
+    let binding = sl.get_aggregated_data();
+    let aggregated_data = binding.read().unwrap();
+    assert_eq!(aggregated_data.len(), 2);
+    for interface in ["eth0", "wlan0"] {
+        let key = Connection::new("192.168.1.2".parse().unwrap(), "93.184.216.34".parse().unwrap(), Some(62911), Some(80), Protocol::Tcp, Some(interface.to_string()));
+        assert_eq!(aggregated_data.get(&key).unwrap().size, 14 + 20 + 20 + 10);
+    }
+    drop(aggregated_data);
+
+    //a truncated block stops the capture with an error instead of a clean end of file
+    let truncated = std::env::temp_dir().join("network_analyzer_test_pcapng_truncated.pcapng");
+    std::fs::write(&truncated, &bytes[..bytes.len() - 6]).unwrap();
+    let (sender, _receiver) = mpsc::channel();
+    let parser = Parser::from_file(truncated.to_str().unwrap(), sender);
+    assert_eq!(parser.wait_for_completion(), 2);
+    assert!(parser.get_capture_stats().lock().unwrap().error.is_some());
+    assert_eq!(sl.get_capture_stats().lock().unwrap().error, None);
+
+    //a corrupt block length is reported as an error, not allocated
+    let mut corrupt = bytes.clone();
+    corrupt.extend_from_slice(&6u32.to_le_bytes());
+    corrupt.extend_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+    std::fs::write(&truncated, &corrupt).unwrap();
+    let (sender, _receiver) = mpsc::channel();
+    let parser = Parser::from_file(truncated.to_str().unwrap(), sender);
+    parser.wait_for_completion();
+    assert!(parser.get_capture_stats().lock().unwrap().error.as_deref().is_some_and(|error| error.contains("truncated pcapng block")));
+}
+
+#[test]
+fn test_invalid_filter(){
+    let path = std::env::temp_dir().join("network_analyzer_test_invalid_filter.pcap");
+    write_pcap_file(&path, 1667775485, &test_frames());
+    let (sender, _receiver) = mpsc::channel();
+    let error = Parser::from_file_with_filter(path.to_str().unwrap(), "tcp port https or", sender).err().unwrap();
+    assert_eq!(error.filter, "tcp port https or");
+    assert!(error.to_string().starts_with("invalid capture filter 'tcp port https or'"));
+}
+
+#[test]
+fn test_filter(){
+    let path = std::env::temp_dir().join("network_analyzer_test_filter.pcap");
+    write_pcap_file(&path, 1667775485, &test_frames());
+    let sl = SocketListener::from_file_with_filter(path.to_str().unwrap(), "udp").unwrap();
+    assert_eq!(sl.get_filter(), Some("udp".to_string()));
+    //an invalid filter is rejected and the current one is kept
+    assert!(sl.set_filter(Some("udp and")).is_err());
+    assert_eq!(sl.get_filter(), Some("udp".to_string()));
+    sl.wait_for_completion();
+    let binding = sl.get_aggregated_data();
+    let aggregated_data = binding.read().unwrap();
+    assert_eq!(aggregated_data.len(), 1);
+    assert!(aggregated_data.keys().all(|c| c.protocol == Some(Protocol::Udp)));
+}
+
+#[test]
+fn test_filter_change_without_traffic(){
+    //capturing on the loopback device needs privileges, the test is skipped without them
+    if pcap::Capture::from_device("lo").and_then(|cap| cap.open()).is_err() {
+        return;
+    }
+    let receiver_socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let port = receiver_socket.local_addr().unwrap().port();
+    let (sender, receiver) = mpsc::channel();
+    //the first filter matches no traffic, so no packet ever wakes the parser up
+    let parser = Parser::with_filter("lo", "udp and port 9 and port 7", sender).unwrap();
+    std::thread::sleep(Duration::from_millis(300));
+    parser.set_filter(Some(&format!("udp dst port {}", port))).unwrap();
+    std::net::UdpSocket::bind("127.0.0.1:0").unwrap().send_to(b"ping", ("127.0.0.1", port)).unwrap();
+    let packet = receiver.recv_timeout(Duration::from_secs(5)).expect("the new filter has not been applied");
+    assert_eq!((packet.protocol, packet.destination_port), (Protocol::Udp, Some(port)));
+
+    //the libpcap statistics are refreshed even if no packet is read
+    parser.set_filter(Some("udp and port 9 and port 7")).unwrap();
+    std::thread::sleep(Duration::from_millis(1500));
+    assert!(parser.get_capture_stats().lock().unwrap().dropped.is_some());
+}
+
+#[test]
+fn test_protocol_display(){
+    assert_eq!(Protocol::Tcp.to_string(), "TCP");
+    assert_eq!(Protocol::Icmpv4 { icmp_type: 8, code: 0 }.to_string(), "ICMPv4: Echo Request");
+    assert_eq!(Protocol::Icmpv6 { icmp_type: 1, code: 4 }.to_string(), "ICMPv6: Destination Unreachable");
+    assert_eq!(Protocol::Other(47).to_string(), "IP protocol 47");
+    assert_eq!(Protocol::Icmpv6 { icmp_type: 129, code: 0 }.ip_number(), 58);
+    let connection = Connection::new("192.168.1.2".parse().unwrap(), "8.8.8.8".parse().unwrap(), None, None, Protocol::Icmpv4 { icmp_type: 0, code: 0 }, None);
+    assert_eq!(connection.to_string(), "| -          \t| 192.168.1.2     \t| 8.8.8.8         \t| -     \t | -     \t| ICMPv4: Echo Reply \t|");
+
+    //a non-first fragment of an ICMP message has no ICMP header, so its type is unknown
+    let mut fragment = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 0x08, 0x00];
+    fragment.extend_from_slice(&[0x45, 0, 0, 28, 0, 1, 0x00, 0xb9, 64, 1, 0, 0, 192, 168, 1, 2, 8, 8, 8, 8]);
+    fragment.extend_from_slice(&[0u8; 8]);
+    //an ICMPv6 header truncated to its first byte has no code, so its type is not trusted either
+    let mut truncated = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 0x86, 0xdd, 0x60, 0, 0, 0, 0, 1, 58, 64];
+    truncated.extend_from_slice(&"2001:db8::1".parse::<std::net::Ipv6Addr>().unwrap().octets());
+    truncated.extend_from_slice(&"2001:db8::2".parse::<std::net::Ipv6Addr>().unwrap().octets());
+    truncated.push(128);
+    let path = std::env::temp_dir().join("network_analyzer_test_protocol_display.pcap");
+    write_pcap_file(&path, 1667775485, &[fragment, truncated]);
+    let (sender, receiver) = mpsc::channel();
+    assert_eq!(Parser::from_file(path.to_str().unwrap(), sender).wait_for_completion(), 2);
+    let unknown = Protocol::Icmpv4 { icmp_type: UNKNOWN_ICMP_TYPE, code: 0 };
+    assert_eq!(receiver.recv().unwrap().protocol, unknown);
+    assert_eq!(unknown.to_string(), "ICMPv4: Type Unknown");
+    let unknown = receiver.recv().unwrap().protocol;
+    assert_eq!(unknown, Protocol::Icmpv6 { icmp_type: UNKNOWN_ICMP_TYPE, code: 0 });
+    assert_eq!(unknown.to_string(), "ICMPv6: Type Unknown");
+}
+
+/// Builds an ethernet frame carrying an IPv6 packet with the given extension headers before the transport header
+fn ipv6_frame(next_header: u8, extensions: &[u8], transport: &[u8]) -> Vec<u8> {
+    let mut frame = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 0x86, 0xdd];
+    frame.extend_from_slice(&[0x60, 0, 0, 0]);
+    frame.extend_from_slice(&((extensions.len() + transport.len()) as u16).to_be_bytes());
+    frame.extend_from_slice(&[next_header, 64]);
+    frame.extend_from_slice(&"2001:db8::1".parse::<std::net::Ipv6Addr>().unwrap().octets());
+    frame.extend_from_slice(&"2001:db8:0:1::2".parse::<std::net::Ipv6Addr>().unwrap().octets());
+    frame.extend_from_slice(extensions);
+    frame.extend_from_slice(transport);
+    frame
+}
+
+#[test]
+fn test_parser_ipv6(){
+    let source = [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+    let destination = [0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+    let mut frames = Vec::new();
+    let mut frame = Vec::new();
+    PacketBuilder::ethernet2([1, 2, 3, 4, 5, 6], [7, 8, 9, 10, 11, 12])
+        .ipv6(source, destination, 64)
+        .tcp(50000, 443, 1, 1024)
+        .write(&mut frame, &[0u8; 10]).unwrap();
+    frames.push(frame);
+    let mut frame = Vec::new();
+    PacketBuilder::ethernet2([1, 2, 3, 4, 5, 6], [7, 8, 9, 10, 11, 12])
+        .ipv6(destination, source, 64)
+        .udp(5353, 53)
+        .write(&mut frame, &[0u8; 20]).unwrap();
+    frames.push(frame);
+    let mut frame = Vec::new();
+    PacketBuilder::ethernet2([1, 2, 3, 4, 5, 6], [7, 8, 9, 10, 11, 12])
+        .ipv6(source, destination, 64)
+        .icmpv6_echo_request(1, 1)
+        .write(&mut frame, &[0u8; 8]).unwrap();
+    frames.push(frame);
+    let path = std::env::temp_dir().join("network_analyzer_test_parser_ipv6.pcap");
+    write_pcap_file(&path, 1667775485, &frames);
+    let (sender, receiver) = mpsc::channel();
+    let parser = Parser::from_file(path.to_str().unwrap(), sender);
+    assert_eq!(parser.wait_for_completion(), 3);
+    let parsed: Vec<ParsedPacket> = receiver.try_iter().collect();
+    //addresses are rendered in RFC 5952 compressed form
+    assert_eq!(parsed[0].source_ip.to_string(), "2001:db8::1");
+    assert_eq!(parsed[0].destination_ip.to_string(), "fe80::1");
+    assert_eq!((parsed[0].source_port, parsed[0].destination_port), (Some(50000), Some(443)));
+    assert_eq!(parsed[0].protocol, Protocol::Tcp);
+    assert_eq!(parsed[1].source_ip.to_string(), "fe80::1");
+    assert_eq!((parsed[1].source_port, parsed[1].destination_port), (Some(5353), Some(53)));
+    assert_eq!(parsed[1].protocol, Protocol::Udp);
+    assert_eq!(parsed[1].size, 14 + 40 + 8 + 20);
+    assert_eq!(parsed[2].source_port, None);
+    assert_eq!(parsed[2].protocol, Protocol::Icmpv6 { icmp_type: 128, code: 0 });
+}
+
+#[test]
+fn test_parser_ipv6_extension_headers(){
+    let udp = [0x13, 0x88, 0x00, 0x35, 0, 8, 0, 0];
+    let tcp = [0xc3, 0x50, 0x01, 0xbb, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x02, 0x04, 0x00, 0, 0, 0, 0];
+    //hop-by-hop options -> destination options (16 bytes) -> first fragment -> UDP
+    let mut extensions = vec![60, 0, 1, 4, 0, 0, 0, 0];
+    extensions.extend_from_slice(&[44, 1, 1, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    extensions.extend_from_slice(&[17, 0, 0x00, 0x01, 0, 0, 0, 7]);
+    let first_fragment = ipv6_frame(0, &extensions, &udp);
+    //non-first fragment (offset 185): the payload does not start with the UDP header
+    let other_fragment = ipv6_frame(44, &[17, 0, 0x05, 0xc8, 0, 0, 0, 7], &udp);
+    //routing header -> destination options -> TCP
+    let mut extensions = vec![60, 0, 0, 0, 0, 0, 0, 0];
+    extensions.extend_from_slice(&[6, 0, 1, 4, 0, 0, 0, 0]);
+    let routed = ipv6_frame(43, &extensions, &tcp);
+    let path = std::env::temp_dir().join("network_analyzer_test_parser_ipv6_extension_headers.pcap");
+    write_pcap_file(&path, 1667775485, &[first_fragment, other_fragment, routed]);
+    let (sender, receiver) = mpsc::channel();
+    let parser = Parser::from_file(path.to_str().unwrap(), sender);
+    assert_eq!(parser.wait_for_completion(), 3);
+    let parsed: Vec<ParsedPacket> = receiver.try_iter().collect();
+    assert_eq!(parsed[0].source_ip.to_string(), "2001:db8::1");
+    //a single zero group is not compressed
+    assert_eq!(parsed[0].destination_ip.to_string(), "2001:db8:0:1::2");
+    assert_eq!((parsed[0].source_port, parsed[0].destination_port), (Some(5000), Some(53)));
+    assert_eq!(parsed[0].protocol, Protocol::Udp);
+    assert_eq!((parsed[1].source_port, parsed[1].destination_port), (None, None));
+    assert_eq!(parsed[1].protocol, Protocol::Udp);
+    assert_eq!((parsed[2].source_port, parsed[2].destination_port), (Some(50000), Some(443)));
+    assert_eq!(parsed[2].protocol, Protocol::Tcp);
+    assert_eq!(parsed[2].tcp_flags, Some(TCP_SYN));
+    let connection = Connection::new(parsed[2].source_ip, parsed[2].destination_ip, parsed[2].source_port, parsed[2].destination_port, parsed[2].protocol, None);
+    assert_eq!(connection.to_string(), "| -          \t| 2001:db8::1     \t| 2001:db8:0:1::2 \t| 50000 \t | 443   \t| TCP     \t|");
+}
+
+/// Sends a GET request to the given path and returns the whole response, headers included
+fn http_get(address: std::net::SocketAddr, path: &str) -> String {
+    use std::io::{Read, Write};
+    let mut stream = std::net::TcpStream::connect(address).unwrap();
+    write!(stream, "GET {} HTTP/1.0\r\nHost: localhost\r\n\r\n", path).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn test_metrics_server() {
     let aggregator = Aggregator::new();
-    let mut _parser = Parser::new("\\Device\\NPF_{CD484432-E2CB-46E8-8FCC-3D919CF3533E}",aggregator.get_sender());
-    //wait for the parser/aggregator to process some packets
-    println!("waiting for the parser/aggregator to process some packets");
-    std::thread::sleep(std::time::Duration::from_secs(5));
-    //print the aggregated data
-    let binding = aggregator.get_aggregated_data();
-    let aggregated_data = binding.lock().unwrap();
-    for (key,value) in aggregated_data.iter() {
-        println!("aggregated record received: {:?}, {:?}",key,value);
+    aggregator.send(ParsedPacket::new(ts(1667775485), "192.168.1.2".parse().unwrap(), "93.184.216.34".parse().unwrap(), Some(62911), Some(443), Protocol::Tcp, 1500).with_interface(Some(String::from("eth\"0\""))));
+    aggregator.send(ParsedPacket::new(ts(1667775486), "192.168.1.2".parse().unwrap(), "93.184.216.34".parse().unwrap(), Some(62911), Some(443), Protocol::Tcp, 500).with_interface(Some(String::from("eth\"0\""))));
+    aggregator.send(ParsedPacket::new(ts(1667775486), "192.168.1.2".parse().unwrap(), "8.8.8.8".parse().unwrap(), Some(5353), Some(53), Protocol::Udp, 80));
+    aggregator.wait_for_packets(3);
+    assert_eq!(aggregator.get_received_count(), 3);
+    assert_eq!((aggregator.get_protocol_totals()[&6].packets, aggregator.get_protocol_totals()[&6].bytes), (2, 2000));
+
+    let metrics = MetricsServer::new("127.0.0.1:0", &aggregator).unwrap();
+    assert_eq!(metrics.get_top(), 10);
+    metrics.set_top(1);
+    let response = http_get(metrics.get_address(), "/metrics");
+    assert!(response.starts_with("HTTP/1.1 200") || response.starts_with("HTTP/1.0 200"));
+    assert!(response.contains("text/plain; version=0.0.4"));
+    assert!(response.contains("# TYPE network_analyzer_protocol_bytes_total counter\n"));
+    assert!(response.contains("network_analyzer_protocol_bytes_total{protocol=\"TCP\"} 2000\n"));
+    assert!(response.contains("network_analyzer_protocol_packets_total{protocol=\"UDP\"} 1\n"));
+    //only the busiest connection, label values are escaped
+    assert!(response.contains("# TYPE network_analyzer_connection_bytes gauge\n"));
+    assert!(response.contains("network_analyzer_connection_bytes{interface=\"eth\\\"0\\\"\",source=\"192.168.1.2\",destination=\"93.184.216.34\",source_port=\"62911\",destination_port=\"443\",protocol=\"TCP\",icmp_type=\"\",icmp_code=\"\"} 2000\n"));
+    assert_eq!(response.matches("network_analyzer_connection_packets{").count(), 1);
+    assert!(response.contains("network_analyzer_connections 2\n"));
+    assert!(response.contains("network_analyzer_aggregated_packets_total 3\n"));
+    assert!(response.contains("network_analyzer_aggregated_flow_records_total 0\n"));
+    //the health of the capture is only exposed with the capture statistics
+    assert!(!response.contains("network_analyzer_channel_backlog"));
+
+    let mut capture_stats = CaptureStats::new("eth0");
+    capture_stats.packets = 5;
+    capture_stats.skipped = 2;
+    capture_stats.dropped = Some(4);
+    metrics.set_capture_stats(Some(std::sync::Arc::new(std::sync::Mutex::new(capture_stats))));
+    let response = http_get(metrics.get_address(), "/metrics?format=text");
+    //the 3 packets parsed have all been aggregated
+    assert!(response.contains("network_analyzer_packets_parsed_total 3\n"));
+    assert!(response.contains("network_analyzer_packets_skipped_total 2\n"));
+    assert!(response.contains("network_analyzer_pcap_dropped_packets_total 4\n"));
+    assert!(!response.contains("network_analyzer_interface_dropped_packets_total"));
+    assert!(response.contains("# TYPE network_analyzer_channel_backlog gauge\nnetwork_analyzer_channel_backlog 0\n"));
+
+    //ICMP connections that differ by code only get their own series
+    for code in [1, 3] {
+        aggregator.send(ParsedPacket::new(ts(1667775487), "192.168.1.1".parse().unwrap(), "192.168.1.2".parse().unwrap(), None, None, Protocol::Icmpv4 { icmp_type: 3, code }, 70));
+    }
+    aggregator.wait_for_packets(5);
+    metrics.set_top(10);
+    let response = http_get(metrics.get_address(), "/metrics");
+    for code in ["1", "3"] {
+        assert!(response.contains(&format!("protocol=\"ICMPv4\",icmp_type=\"3\",icmp_code=\"{}\"}} 70\n", code)));
     }
 
+    let response = http_get(metrics.get_address(), "/");
+    assert!(response.contains(" 404 "));
+    metrics.stop();
+}
+
+/// Returns the flows exported in the tests of the exporters: a bidirectional TCP connection, an ICMP echo request and an IPv6 UDP flow
+fn completed_flows() -> Vec<CompletedFlow> {
+    let tcp = Connection::new("192.168.1.2".parse().unwrap(), "93.184.216.34".parse().unwrap(), Some(62911), Some(443), Protocol::Tcp, None);
+    let mut tcp_data = ConnectionMetadata::new(1500, ts(1667775485), ts(1667775485));
+    tcp_data.update_responder(500, ts(1667775486));
+    let icmp = Connection::new("192.168.1.2".parse().unwrap(), "8.8.8.8".parse().unwrap(), None, None, Protocol::Icmpv4 { icmp_type: 8, code: 0 }, None);
+    let udp = Connection::new("2001:db8::1".parse().unwrap(), "2001:db8::2".parse().unwrap(), Some(5353), Some(53), Protocol::Udp, None);
+    vec![
+        CompletedFlow { connection: tcp, metadata: tcp_data, reason: ExpiryReason::Idle },
+        CompletedFlow { connection: icmp, metadata: ConnectionMetadata::new(84, ts(1667775485), ts(1667775485)), reason: ExpiryReason::Idle },
+        CompletedFlow { connection: udp, metadata: ConnectionMetadata::new(80, ts(1667775485), ts(1667775485)), reason: ExpiryReason::Active },
+    ]
 }
 
+fn be_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
 
+fn be_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
 
+#[test]
+fn test_netflow_exporter() {
+    let collector = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    collector.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut buffer = [0u8; 2048];
 
+    //NetFlow v5: the IPv6 flow is skipped, the responder of the TCP connection gets its own record
+    let (tx, rx) = mpsc::channel();
+    let exporter = NetflowExporter::new(&collector.local_addr().unwrap().to_string(), NetflowVersion::V5, rx).unwrap();
+    for flow in completed_flows() {
+        tx.send(flow).unwrap();
+    }
+    drop(tx);
+    let stats = exporter.wait_for_completion();
+    assert_eq!((stats.packets, stats.records, stats.skipped, stats.errors), (1, 3, 1, 0));
+    let length = collector.recv(&mut buffer).unwrap();
+    let packet = &buffer[..length];
+    assert_eq!(length, 24 + 3 * 48);
+    assert_eq!((be_u16(packet, 0), be_u16(packet, 2), be_u32(packet, 16)), (5, 3, 0));
+    let record = &packet[24..72];
+    assert_eq!((&record[0..4], &record[4..8]), (&[192, 168, 1, 2][..], &[93, 184, 216, 34][..]));
+    assert_eq!((be_u32(record, 16), be_u32(record, 20)), (1, 1500));
+    assert_eq!((be_u16(record, 32), be_u16(record, 34), record[38]), (62911, 443, 6));
+    assert_eq!((record[44], record[45]), (32, 32));
+    let record = &packet[72..120];
+    assert_eq!((&record[0..4], be_u32(record, 20), be_u16(record, 32), be_u16(record, 34)), (&[93, 184, 216, 34][..], 500, 443, 62911));
+    //ICMP type and code are carried by the destination port
+    let record = &packet[120..168];
+    assert_eq!((be_u16(record, 32), be_u16(record, 34), record[38]), (0, 8 << 8, 1));
 
+    //NetFlow v9: one packet per address family, the templates are in the first one
+    let (tx, rx) = mpsc::channel();
+    let exporter = NetflowExporter::new(&collector.local_addr().unwrap().to_string(), NetflowVersion::V9, rx).unwrap();
+    for flow in completed_flows() {
+        tx.send(flow).unwrap();
+    }
+    drop(tx);
+    let stats = exporter.wait_for_completion();
+    assert_eq!((stats.packets, stats.records, stats.skipped), (2, 4, 0));
+    let length = collector.recv(&mut buffer).unwrap();
+    let packet = buffer[..length].to_vec();
+    //header: version, count (2 templates and 3 records), sequence number
+    assert_eq!((be_u16(&packet, 0), be_u16(&packet, 2), be_u32(&packet, 12)), (9, 5, 0));
+    //template flowset with the IPv4 and IPv6 templates of 12 fields each
+    assert_eq!((be_u16(&packet, 20), be_u16(&packet, 22)), (0, 4 + 2 * (4 + 12 * 4)));
+    assert_eq!((be_u16(&packet, 24), be_u16(&packet, 26), be_u16(&packet, 28), be_u16(&packet, 30)), (256, 12, 8, 4));
+    //data flowset of 3 records of 41 bytes, padded to a multiple of 4 bytes
+    let data = &packet[128..];
+    assert_eq!((be_u16(data, 0), be_u16(data, 2), data.len()), (256, 128, 128));
+    assert_eq!((&data[4..8], &data[8..12], data[12], be_u16(data, 14), be_u16(data, 16), data[18]), (&[192, 168, 1, 2][..], &[93, 184, 216, 34][..], 32, 62911, 443, 6));
+    assert_eq!(&data[21..37], &[0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 5, 220][..]);
+    let length = collector.recv(&mut buffer).unwrap();
+    let packet = &buffer[..length];
+    //no templates, a data flowset with a record of 65 bytes
+    assert_eq!((be_u16(packet, 2), be_u32(packet, 12), be_u16(packet, 20), be_u16(packet, 22)), (1, 1, 257, 72));
+    assert_eq!(&packet[24..40], &"2001:db8::1".parse::<std::net::Ipv6Addr>().unwrap().octets()[..]);
+}
 
+/// Returns the sets of an IPFIX message as pairs of set id and set content, after checking the header
+fn ipfix_sets(message: &[u8], sequence: u32) -> Vec<(u16, Vec<u8>)> {
+    assert_eq!((be_u16(message, 0), be_u16(message, 2) as usize, be_u32(message, 8)), (10, message.len(), sequence));
+    let mut sets = Vec::new();
+    let mut offset = 16;
+    while offset < message.len() {
+        let length = be_u16(message, offset + 2) as usize;
+        sets.push((be_u16(message, offset), message[offset + 4..offset + length].to_vec()));
+        offset += length;
+    }
+    sets
+}
 
+#[test]
+fn test_ipfix_exporter() {
+    let collector = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    collector.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut buffer = [0u8; 2048];
+    let (tx, rx) = mpsc::channel();
+    let exporter = IpfixExporter::new(&collector.local_addr().unwrap().to_string(), IpfixTransport::Udp, rx).unwrap();
+    for flow in completed_flows() {
+        tx.send(flow).unwrap();
+    }
+    let length = collector.recv(&mut buffer).unwrap();
+    let sets = ipfix_sets(&buffer[..length], 0);
+    assert_eq!(sets.iter().map(|(id, _)| *id).collect::<Vec<u16>>(), vec![2, 256, 257]);
+    //IPv4 template: 16 fields, the last two are enterprise-specific variable-length elements
+    let template = &sets[0].1;
+    assert_eq!((be_u16(template, 0), be_u16(template, 2)), (256, 16));
+    assert_eq!((be_u16(template, 56), be_u16(template, 58)), (82, 65535));
+    assert_eq!((be_u16(template, 60), be_u16(template, 62), be_u32(template, 64)), (0x8001, 65535, ENTERPRISE_NUMBER));
+    assert_eq!((be_u16(template, 68), be_u32(template, 72)), (0x8002, ENTERPRISE_NUMBER));
+    //the first record: 5-tuple, counters, start and end in milliseconds, idle timeout, application label
+    let record = &sets[1].1;
+    assert_eq!((&record[0..4], &record[4..8], be_u16(record, 10), be_u16(record, 12), record[14]), (&[192, 168, 1, 2][..], &[93, 184, 216, 34][..], 62911, 443, 6));
+    assert_eq!(&record[17..33], &[0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 5, 220][..]);
+    assert_eq!(&record[33..49], &[&1667775485000u64.to_be_bytes()[..], &1667775486000u64.to_be_bytes()[..]].concat()[..]);
+    assert_eq!((record[49], record[50], record[51]), (1, 0, 0));
+    assert_eq!(&record[52..58], b"\x05https");
+    //the responder gets the label of its source port, ICMP flows the name of their type
+    let record = &record[58..];
+    assert_eq!((&record[0..4], be_u16(record, 10), &record[50..58]), (&[93, 184, 216, 34][..], 443, &b"\x00\x00\x05https"[..]));
+    let record = &record[58..];
+    assert_eq!((be_u16(record, 15), record[14], &record[51..72]), (8 << 8, 1, &b"\x14ICMPv4: Echo Request"[..]));
+    //IPv6 record, ended by the active timeout
+    let record = &sets[2].1;
+    assert_eq!((&record[0..16], record[73], &record[76..80]), (&"2001:db8::1".parse::<std::net::Ipv6Addr>().unwrap().octets()[..], 2, &b"\x03dns"[..]));
 
+    //the sequence number counts the data records, the templates are only sent again after the refresh interval
+    tx.send(completed_flows().remove(1)).unwrap();
+    let length = collector.recv(&mut buffer).unwrap();
+    assert_eq!(ipfix_sets(&buffer[..length], 4).iter().map(|(id, _)| *id).collect::<Vec<u16>>(), vec![256]);
+    exporter.set_template_refresh(Duration::ZERO);
+    tx.send(completed_flows().remove(1)).unwrap();
+    let length = collector.recv(&mut buffer).unwrap();
+    assert_eq!(ipfix_sets(&buffer[..length], 5).iter().map(|(id, _)| *id).collect::<Vec<u16>>(), vec![2, 256]);
+    drop(tx);
+    let stats = exporter.wait_for_completion();
+    assert_eq!((stats.packets, stats.records, stats.errors), (3, 6, 0));
 
+    //over TCP the templates are only sent at the start of the connection
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let (tx, rx) = mpsc::channel();
+    let exporter = IpfixExporter::new(&listener.local_addr().unwrap().to_string(), IpfixTransport::Tcp, rx).unwrap();
+    exporter.set_template_refresh(Duration::ZERO);
+    let (mut stream, _) = listener.accept().unwrap();
+    tx.send(completed_flows().remove(0)).unwrap();
+    while exporter.get_export_stats().packets == 0 {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    tx.send(completed_flows().remove(0)).unwrap();
+    drop(tx);
+    assert_eq!(exporter.wait_for_completion().packets, 2);
+    let mut messages = Vec::new();
+    std::io::Read::read_to_end(&mut stream, &mut messages).unwrap();
+    let first = be_u16(&messages, 2) as usize;
+    assert_eq!(ipfix_sets(&messages[..first], 0).iter().map(|(id, _)| *id).collect::<Vec<u16>>(), vec![2, 256]);
+    assert_eq!(ipfix_sets(&messages[first..], 2).iter().map(|(id, _)| *id).collect::<Vec<u16>>(), vec![256]);
 
+    //after a TCP error the connection is established again with the next export, starting with the templates,
+    //and the sequence number only counts the records sent
+    let (tx, rx) = mpsc::channel();
+    let exporter = IpfixExporter::new(&listener.local_addr().unwrap().to_string(), IpfixTransport::Tcp, rx).unwrap();
+    drop(listener.accept().unwrap());
+    let mut attempts = 0;
+    while exporter.get_export_stats().errors == 0 {
+        tx.send(completed_flows().remove(0)).unwrap();
+        attempts += 1;
+        while exporter.get_export_stats().packets + exporter.get_export_stats().errors < attempts {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+    let sent = exporter.get_export_stats().records as u32;
+    tx.send(completed_flows().remove(0)).unwrap();
+    let (mut stream, _) = listener.accept().unwrap();
+    drop(tx);
+    assert_eq!(exporter.wait_for_completion().errors, 1);
+    let mut messages = Vec::new();
+    std::io::Read::read_to_end(&mut stream, &mut messages).unwrap();
+    assert_eq!(ipfix_sets(&messages, sent).iter().map(|(id, _)| *id).collect::<Vec<u16>>(), vec![2, 256]);
+}
+
+#[test]
+fn test_flow_collector() {
+    let tcp = Connection::new("192.168.1.2".parse().unwrap(), "93.184.216.34".parse().unwrap(), Some(62911), Some(443), Protocol::Tcp, Some(String::from("127.0.0.1")));
+    let icmp = Connection::new("192.168.1.2".parse().unwrap(), "8.8.8.8".parse().unwrap(), None, None, Protocol::Icmpv4 { icmp_type: 8, code: 0 }, Some(String::from("127.0.0.1")));
+    let udp = Connection::new("2001:db8::1".parse().unwrap(), "2001:db8::2".parse().unwrap(), Some(5353), Some(53), Protocol::Udp, Some(String::from("127.0.0.1")));
+    //the records of each exporter become one entry per direction, the interface is the address of the exporter
+    let collect = |export: &dyn Fn(&str, mpsc::Receiver<CompletedFlow>), records: usize| {
+        let aggregator = Aggregator::new();
+        let collector = FlowCollector::new("127.0.0.1:0", aggregator.get_sender()).unwrap();
+        let (tx, rx) = mpsc::channel();
+        for flow in completed_flows() {
+            tx.send(flow).unwrap();
+        }
+        drop(tx);
+        export(&collector.get_address().to_string(), rx);
+        aggregator.wait_for_flow_records(records);
+        assert_eq!(collector.get_collector_stats().records, records);
+        (aggregator, collector)
+    };
+
+    let (aggregator, _collector) = collect(&|address, rx| { NetflowExporter::new(address, NetflowVersion::V5, rx).unwrap().wait_for_completion(); }, 3);
+    assert_eq!((aggregator.get_protocol_totals()[&6].packets, aggregator.get_protocol_totals()[&6].bytes), (2, 2000));
+    let aggregated_data = aggregator.get_aggregated_data();
+    assert_eq!(aggregated_data.read().unwrap()[&tcp].size, 1500);
+    assert_eq!(aggregated_data.read().unwrap()[&tcp.reversed()].size, 500);
+    assert_eq!(aggregated_data.read().unwrap()[&icmp].size, 84);
 
+    let (aggregator, _collector) = collect(&|address, rx| { NetflowExporter::new(address, NetflowVersion::V9, rx).unwrap().wait_for_completion(); }, 4);
+    assert_eq!((aggregator.get_protocol_totals()[&17].packets, aggregator.get_protocol_totals()[&17].bytes), (1, 80));
+    assert_eq!(aggregator.get_aggregated_data().read().unwrap()[&udp].size, 80);
 
+    //IPFIX carries absolute timestamps in milliseconds
+    let (aggregator, collector) = collect(&|address, rx| { IpfixExporter::new(address, IpfixTransport::Udp, rx).unwrap().wait_for_completion(); }, 4);
+    let aggregated_data = aggregator.get_aggregated_data();
+    let data = aggregated_data.read().unwrap()[&tcp.reversed()].clone();
+    assert_eq!((data.packets, data.size, data.first_timestamp, data.last_timestamp), (1, 500, ts(1667775485), ts(1667775486)));
+    assert_eq!(aggregated_data.read().unwrap()[&icmp].first_timestamp, ts(1667775485));
 
+    //datagrams that are not flow export messages are counted and ignored
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.send_to(b"not a flow export", collector.get_address()).unwrap();
+    let start = std::time::Instant::now();
+    while collector.get_collector_stats().malformed == 0 && start.elapsed() < Duration::from_secs(5) {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(collector.get_collector_stats().malformed, 1);
+    assert_eq!(aggregator.get_flow_record_count(), 4);
+    //the flow records are not counted as captured packets, so they cannot end the wait for a parser
+    assert_eq!(aggregator.get_received_count(), 0);
+    aggregator.send(ParsedPacket::new(ts(1667775487), "192.168.1.2".parse().unwrap(), "8.8.8.8".parse().unwrap(), Some(5353), Some(53), Protocol::Udp, 80));
+    aggregator.wait_for_packets(1);
+    assert_eq!((aggregator.get_received_count(), aggregator.get_flow_record_count()), (1, 4));
+
+    //NetFlow v9 data sets received before their template are counted, an ICMP record without type and code has an unknown type
+    let v9 = |sets: &[u8]| [&[0, 9, 0, 1, 0, 0, 0, 0], &1667775485u32.to_be_bytes()[..], &[0; 8], sets].concat();
+    let data_set = [&[1, 0, 0, 17, 10, 0, 0, 1, 10, 0, 0, 2, 1][..], &84u32.to_be_bytes()].concat();
+    let template_set = [0, 0, 0, 24, 1, 0, 0, 4, 0, 8, 0, 4, 0, 12, 0, 4, 0, 4, 0, 1, 0, 1, 0, 4];
+    socket.send_to(&v9(&data_set), collector.get_address()).unwrap();
+    let start = std::time::Instant::now();
+    while collector.get_collector_stats().unknown_template_sets == 0 && start.elapsed() < Duration::from_secs(5) {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!((collector.get_collector_stats().unknown_template_sets, collector.get_collector_stats().skipped), (1, 0));
+    socket.send_to(&v9(&[&template_set[..], &data_set].concat()), collector.get_address()).unwrap();
+    aggregator.wait_for_flow_records(5);
+    let icmp = Connection::new("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap(), None, None, Protocol::Icmpv4 { icmp_type: UNKNOWN_ICMP_TYPE, code: 0 }, Some(String::from("127.0.0.1")));
+    assert_eq!(aggregator.get_aggregated_data().read().unwrap()[&icmp].size, 84);
+
+    //a truncated variable-length field ends the data set, the records before it are kept and it is counted as skipped
+    let template_set = [0, 2, 0, 28, 1, 44, 0, 5, 0, 8, 0, 4, 0, 12, 0, 4, 0, 4, 0, 1, 0, 1, 0, 4, 0, 82, 255, 255];
+    let record = |last: u8| [&[10, 0, 0, 3, 10, 0, 0, last, 17][..], &100u32.to_be_bytes()].concat();
+    let data_set = [&[1, 44, 0, 35][..], &record(4), &[1, b'x'], &record(5), &[10, b'x', b'y']].concat();
+    let sets = [&template_set[..], &data_set].concat();
+    let ipfix = [&[0, 10][..], &(16 + sets.len() as u16).to_be_bytes(), &1667775485u32.to_be_bytes(), &[0; 8], &sets].concat();
+    socket.send_to(&ipfix, collector.get_address()).unwrap();
+    aggregator.wait_for_flow_records(6);
+    let udp = Connection::new("10.0.0.3".parse().unwrap(), "10.0.0.4".parse().unwrap(), None, None, Protocol::Udp, Some(String::from("127.0.0.1")));
+    assert_eq!(aggregator.get_aggregated_data().read().unwrap()[&udp].size, 100);
+    let stats = collector.get_collector_stats();
+    assert_eq!((stats.records, stats.skipped, stats.malformed), (6, 1, 1));
+    collector.stop();
+}